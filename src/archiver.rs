@@ -1,70 +1,2493 @@
+mod chunking;
+mod filter;
+mod metadata;
+mod sparse;
+mod tar;
+mod volume;
+mod xattr;
+mod zip;
+
+use crate::checksum;
+use crate::codec;
+use crate::codec::Method;
+use crate::deflate;
+use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs::create_dir_all;
+use std::fs::hard_link;
+use std::fs::read_dir;
+use std::fs::read_link;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::symlink_metadata;
 use std::fs::File;
+use std::fs::OpenOptions;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
 use std::io::copy;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::symlink;
+use std::os::unix::fs::MetadataExt;
+use std::path::Component;
 use std::path::Path;
-use std::vec;
+use std::path::PathBuf;
+
+use chunking::Chunker;
+use filter::SourceFilter;
+pub use metadata::EntryMetadata;
+pub use sparse::DataRegion;
+use sparse::HoleFillingWriter;
+use sparse::SparseReader;
+use sparse::SparseWriter;
+use tar::TarReader;
+use tar::TarWriter;
+use volume::ArchiveFile;
+use volume::VolumeWriter;
+use zip::ZipWriter;
+
+// The archive is laid out as:
+// 1. a header with the number of entries (u64)
+// 2. for each regular file, a local record: length of name (u64), name,
+//    size of the stored data (u64), stored data
+//    (solid and chunked archives have no local records, they contain blocks instead:
+//    size of the stored block (u64), stored block)
+// 3. the central directory:
+//    a. number of solid blocks (u64)
+//    b. for each solid block, compression method (u8), size (u64),
+//       size of the stored block (u64) and offset of the stored block (u64)
+//       (the chunks of chunked archives, and the pieces of files too large to be
+//       compressed at once, are stored as blocks as well)
+//    c. for each entry, length of name (u64), name, compression method (u8),
+//       size (u64), size of the stored data (u64), offset of the stored data (u64)
+//       index of the solid block holding the data (u64, u64::MAX if none),
+//       permission bits (u32), mtime and atime (i64 seconds, u32 nanoseconds each),
+//       uid (u32), gid (u32), length of user name (u64), user name,
+//       length of group name (u64), group name, number of extended attributes (u64)
+//       and for each, length of name (u64), name, length of value (u64), value,
+//       type of entry (u8),
+//       flags (u8, see `NAME_IS_UTF8`, `LINK_TARGET_IS_UTF8`, `IS_SPARSE`, `IS_DUPLICATE`,
+//       `IS_CHUNKED`, `IS_UNCHANGED` and `HAS_HASH`), length of link target (u64),
+//       link target, for sparse files the number of data regions (u64) followed by
+//       their offsets and lengths (u64),
+//       for chunked entries the number of chunks (u64) followed by the indices
+//       of the blocks holding them (u64), and for files the SHA-256 hash of the data
+//    d. number of entries deleted since the previous archive (u64) and for each,
+//       length of name (u64), name
+//    e. 1 followed by the identifier of the previous archive (32 bytes) for
+//       incremental archives, 0 otherwise
+// 4. a footer: offset of the central directory (u64), number of entries (u64), magic
+//
+// As the central directory is written after all entries, archives can be written
+// to a stream that does not support seeking. Archives without a footer (written
+// before the central directory was introduced) are read by scanning the local records.
+//
+// An incremental archive lists all the entries of the archived files like a full
+// archive, but files left unchanged since the previous archive of the chain are
+// recorded without data, which is read from the previous archives when restoring.
+//
+// Names and link targets are written as the raw bytes of the file names, which
+// are not required to be valid UTF-8. Entries with the same contents share the
+// offset (and solid block) of the data, which is stored for the first one only.
+const ARCHIVE_MAGIC: &[u8; 8] = b"FCARDIRB";
+const FOOTER_SIZE: u64 = 24;
+const NO_SOLID_BLOCK: u64 = u64::MAX;
+/// set in the flags of an entry if its name is valid UTF-8
+const NAME_IS_UTF8: u8 = 0x01;
+/// set in the flags of an entry if its link target is valid UTF-8
+const LINK_TARGET_IS_UTF8: u8 = 0x02;
+/// set in the flags of an entry if it was archived from a sparse file,
+/// whose data regions follow the link target
+const IS_SPARSE: u8 = 0x04;
+/// set in the flags of an entry whose data is that of an earlier entry
+const IS_DUPLICATE: u8 = 0x08;
+/// set in the flags of an entry whose data is split into chunks,
+/// whose block indices follow the data regions
+const IS_CHUNKED: u8 = 0x10;
+/// set in the flags of an entry left unchanged since the previous archive
+const IS_UNCHANGED: u8 = 0x20;
+/// set in the flags of an entry whose hash follows the block indices
+const HAS_HASH: u8 = 0x40;
+/// size above which the data of a file is not read at once but split into pieces
+/// of this size, each compressed and stored as a block like the chunks of a file
+const MAX_BUFFERED_SIZE: u64 = 1 << 24;
+
+/// Type of the file system object an entry was archived from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// a hard link to the entry named by the link target,
+    /// which was archived earlier with the data of the file
+    HardLink,
+}
+
+impl EntryKind {
+    fn id(&self) -> u8 {
+        match self {
+            EntryKind::File => 0,
+            EntryKind::Directory => 1,
+            EntryKind::Symlink => 2,
+            EntryKind::HardLink => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<EntryKind> {
+        match id {
+            0 => Ok(EntryKind::File),
+            1 => Ok(EntryKind::Directory),
+            2 => Ok(EntryKind::Symlink),
+            3 => Ok(EntryKind::HardLink),
+            _ => bail!("unknown entry type {}", id),
+        }
+    }
+}
+
+/// An entry of an archive, as described in its central directory
+#[derive(Clone)]
+pub struct ArchiveEntry {
+    /// path of the entry relative to the root of the archive,
+    /// with components separated by '/'
+    pub name: OsString,
+    pub kind: EntryKind,
+    /// target of a symbolic link, or name of the entry a hard link refers to
+    pub link_target: OsString,
+    /// compression method applied to the entry's data
+    /// (or to the solid block holding it)
+    pub method: Method,
+    /// size of the archived file, including the holes of sparse files
+    pub size: u64,
+    /// size of the entry's data as stored in the archive
+    /// (zero for entries held in a solid block)
+    pub stored_size: u64,
+    /// offset of the entry's stored data from the start of the archive,
+    /// or from the start of the decompressed solid block holding it
+    pub offset: u64,
+    /// index of the solid block holding the entry's data
+    pub solid_block: Option<u64>,
+    /// Unix metadata of the archived file
+    /// (not recorded by archives written before the central directory was introduced)
+    pub metadata: Option<EntryMetadata>,
+    /// regions holding data if the file is sparse, the entry's data
+    /// being their concatenation (the rest of the file is made of holes)
+    pub data_regions: Option<Vec<DataRegion>>,
+    /// the entry has the same contents as an earlier entry and refers to its data,
+    /// which is stored only once
+    pub duplicate: bool,
+    /// indices of the blocks holding the chunks of the entry's data, in order,
+    /// if the entry was split into chunks
+    pub chunks: Option<Vec<u64>>,
+    /// SHA-256 hash of the entry's data, recorded for files
+    pub hash: Option<[u8; 32]>,
+    /// the file was left unchanged since the previous archive of an incremental
+    /// chain, the entry holds no data
+    pub unchanged: bool,
+    /// CRC-32 of the entry's data, checked when extracting it
+    /// (recorded by ZIP files, the native format records `hash` instead)
+    pub crc32: Option<u32>,
+}
+
+impl ArchiveEntry {
+    pub fn name_is_utf8(&self) -> bool {
+        self.name.to_str().is_some()
+    }
+
+    /// Size of the entry's data once decompressed, which excludes the holes of sparse files
+    pub fn data_size(&self) -> u64 {
+        match &self.data_regions {
+            Some(data_regions) => sparse::data_length(data_regions),
+            None => self.size,
+        }
+    }
+
+    /// Name of the entry in a form that can be printed, see `escape_name`
+    pub fn display_name(&self) -> String {
+        escape_name(&self.name)
+    }
+
+    /// Returns true if the data of the entry is stored in a local record of its own
+    fn has_local_record(&self) -> bool {
+        self.kind == EntryKind::File
+            && self.solid_block.is_none()
+            && self.chunks.is_none()
+            && !self.duplicate
+            && !self.unchanged
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.name_is_utf8() {
+            flags |= NAME_IS_UTF8;
+        }
+        if self.link_target.to_str().is_some() {
+            flags |= LINK_TARGET_IS_UTF8;
+        }
+        if self.data_regions.is_some() {
+            flags |= IS_SPARSE;
+        }
+        if self.duplicate {
+            flags |= IS_DUPLICATE;
+        }
+        if self.chunks.is_some() {
+            flags |= IS_CHUNKED;
+        }
+        if self.unchanged {
+            flags |= IS_UNCHANGED;
+        }
+        if self.hash.is_some() {
+            flags |= HAS_HASH;
+        }
+        flags
+    }
+}
+
+/// Formats a file name for display without losing information: names that are
+/// valid UTF-8 are returned unchanged, while in other names each byte that is not
+/// part of a UTF-8 sequence is written as `\xNN` and backslashes are doubled
+pub fn escape_name(name: &OsStr) -> String {
+    if let Some(name) = name.to_str() {
+        return name.to_string();
+    }
+    let mut escaped_name = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        escaped_name.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            escaped_name.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped_name
+}
+
+/// A block holding the concatenated data of several entries,
+/// compressed as a single stream
+#[derive(Clone)]
+pub struct SolidBlock {
+    pub method: Method,
+    /// size of the block once decompressed
+    pub size: u64,
+    /// size of the block as stored in the archive
+    pub stored_size: u64,
+    /// offset of the stored block from the start of the archive
+    pub offset: u64,
+}
+
+/// The central directory of an archive
+pub struct ArchiveIndex {
+    pub solid_blocks: Vec<SolidBlock>,
+    pub entries: Vec<ArchiveEntry>,
+    /// names of the entries of the previous archive deleted since it was written
+    pub deleted_names: Vec<OsString>,
+    /// identifier of the archive an incremental archive was made from
+    pub previous_archive: Option<[u8; 32]>,
+}
+
+/// Format of the archive files written by `archive_files`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Native,
+    /// ZIP files, whose entries are deflated unless the method is `Method::Store`
+    /// (archives in this format are read like native archives)
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub const NAMES: [&'static str; 2] = ["native", "zip"];
+
+    pub fn from_name(name: &str) -> Result<ArchiveFormat> {
+        match name {
+            "native" => Ok(ArchiveFormat::Native),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => bail!("unknown archive format {}", name),
+        }
+    }
+
+    /// Format of an archive named `path`: ZIP for `.zip` files, native otherwise
+    pub fn from_path(path: &str) -> ArchiveFormat {
+        match Path::new(path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("zip") => ArchiveFormat::Zip,
+            _ => ArchiveFormat::Native,
+        }
+    }
+}
+
+/// Options controlling how files are written to an archive
+pub struct ArchiveOptions {
+    /// format of the archive file
+    pub format: ArchiveFormat,
+    /// compression method applied to each entry, or to each solid block
+    pub method: Method,
+    /// concatenate the data of all entries and compress it as a single stream
+    pub solid: bool,
+    /// size after which a solid block is closed and a new one is started,
+    /// bounding the data to decompress when extracting a single entry
+    pub solid_block_size: Option<u64>,
+    /// archive the files symbolic links point to instead of the links themselves
+    pub dereference: bool,
+    /// glob patterns of the files to archive (all files if empty),
+    /// directories are walked whether they match or not
+    pub include: Vec<String>,
+    /// glob patterns of the files and directories to skip
+    pub exclude: Vec<String>,
+    /// files listing further patterns to skip, one per line
+    pub exclude_from: Vec<PathBuf>,
+    /// skip the files ignored by the `.gitignore` files of the walked directories
+    pub respect_gitignore: bool,
+    /// record the extended attributes (and POSIX ACLs) of the archived files
+    pub xattrs: bool,
+    /// split the data of the entries into content-defined chunks, storing each
+    /// unique chunk once (takes precedence over `solid`)
+    pub chunked: bool,
+    /// average size of the chunks, 64 KiB if not set
+    pub chunk_size: Option<u64>,
+    /// previous archive to make an incremental archive from: the files left unchanged
+    /// since it was written are recorded without their data
+    pub incremental_from: Option<PathBuf>,
+    /// split the archive into volumes of at most this size, written to files named
+    /// after the archive with the volume number as extension (`.001`, `.002`, ...)
+    pub volume_size: Option<u64>,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            format: ArchiveFormat::Native,
+            method: Method::Huffmann,
+            solid: false,
+            solid_block_size: None,
+            dereference: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_from: Vec::new(),
+            respect_gitignore: false,
+            xattrs: false,
+            chunked: false,
+            chunk_size: None,
+            incremental_from: None,
+            volume_size: None,
+        }
+    }
+}
+
+/// Options controlling how entries are extracted from an archive
+#[derive(Default)]
+pub struct UnarchiveOptions {
+    /// restore the owner and group of the extracted files,
+    /// even when not running as root
+    pub same_owner: bool,
+    /// restore the extended attributes (and POSIX ACLs) recorded in the archive
+    pub xattrs: bool,
+}
+
+/// Data of the solid block currently being filled by an `ArchiveWriter`
+struct SolidBlockBuffer {
+    method: Method,
+    block_size: Option<u64>,
+    data: Vec<u8>,
+}
+
+/// Splitting of the data of entries into chunks by an `ArchiveWriter`
+struct ChunkingOptions {
+    method: Method,
+    chunker: Chunker,
+}
+
+/// Writes an archive to `writer` entry by entry, tracking the offset of each
+/// entry so that the central directory can be written when the archive is finished
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    position: u64,
+    solid_block_buffer: Option<SolidBlockBuffer>,
+    index: ArchiveIndex,
+    /// index of the first entry added with each content, by SHA-256 hash of the data
+    data_hashes: HashMap<[u8; 32], usize>,
+    chunking: Option<ChunkingOptions>,
+    /// index of the block holding each chunk (or piece of a large file),
+    /// by SHA-256 hash of the chunk
+    chunk_hashes: HashMap<[u8; 32], u64>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(writer: W, num_entries: u64) -> Result<Self> {
+        let mut archive_writer = ArchiveWriter {
+            writer,
+            position: 0,
+            solid_block_buffer: None,
+            index: ArchiveIndex {
+                solid_blocks: Vec::new(),
+                entries: Vec::new(),
+                deleted_names: Vec::new(),
+                previous_archive: None,
+            },
+            data_hashes: HashMap::new(),
+            chunking: None,
+            chunk_hashes: HashMap::new(),
+        };
+        archive_writer.write_u64(num_entries)?;
+        Ok(archive_writer)
+    }
+
+    /// Creates a writer for a solid archive, where the data of the entries is
+    /// compressed with `method` in blocks of (approximately) `block_size` bytes
+    pub fn new_solid(
+        writer: W,
+        num_entries: u64,
+        method: Method,
+        block_size: Option<u64>,
+    ) -> Result<Self> {
+        let mut archive_writer = ArchiveWriter::new(writer, num_entries)?;
+        archive_writer.enable_solid_blocks(method, block_size);
+        Ok(archive_writer)
+    }
+
+    /// Creates a writer adding entries to an existing archive described by `index`.
+    /// `writer` must be positioned at `position`, the offset of the central directory
+    /// of the archive, which is overwritten by the new entries.
+    /// New entries are only deduplicated against each other, as the data of the
+    /// existing entries is not read.
+    pub fn append(writer: W, index: ArchiveIndex, position: u64) -> Self {
+        ArchiveWriter {
+            writer,
+            position,
+            solid_block_buffer: None,
+            index,
+            data_hashes: HashMap::new(),
+            chunking: None,
+            chunk_hashes: HashMap::new(),
+        }
+    }
+
+    /// Writes the data of the entries added from now on to solid blocks
+    /// compressed with `method`
+    pub fn enable_solid_blocks(&mut self, method: Method, block_size: Option<u64>) {
+        self.solid_block_buffer = Some(SolidBlockBuffer {
+            method,
+            block_size,
+            data: Vec::new(),
+        });
+    }
+
+    /// Splits the data of the entries added from now on into content-defined chunks
+    /// of `average_chunk_size` bytes on average (64 KiB if not set), each unique chunk
+    /// being compressed with `method` and stored once as a block. Chunking takes
+    /// precedence over solid blocks.
+    pub fn enable_chunking(
+        &mut self,
+        method: Method,
+        average_chunk_size: Option<u64>,
+    ) -> Result<()> {
+        self.chunking = Some(ChunkingOptions {
+            method,
+            chunker: Chunker::new(average_chunk_size.unwrap_or(chunking::DEFAULT_AVERAGE_SIZE))?,
+        });
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.index.entries
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<()> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    /// Writes the local record of an entry, reading `size` bytes from `reader` and
+    /// compressing them with `method`. The data is stored as-is if compressing
+    /// it does not reduce its size, and is not stored again if an entry with the
+    /// same contents was added before.
+    ///
+    /// In a solid archive, the data is appended to the current solid block instead
+    /// and `method` is ignored in favour of the method of the archive. Files larger
+    /// than `MAX_BUFFERED_SIZE` bytes, and all files of a chunked archive, are read
+    /// and written as blocks one piece at a time (see `add_entry_in_blocks`).
+    pub fn add_entry<R: Read>(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        method: Method,
+        size: u64,
+        reader: &mut R,
+    ) -> Result<()> {
+        if self.chunking.is_some() || size > MAX_BUFFERED_SIZE {
+            return self.add_entry_in_blocks(name, metadata, method, size, reader);
+        }
+        let mut data = Vec::new();
+        reader.take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            bail!("entry {} was truncated while archiving", name.display());
+        }
+
+        let data_hash: [u8; 32] = Sha256::digest(&data).into();
+        if self.add_duplicate_entry(name, metadata, size, data_hash) {
+            return Ok(());
+        }
+
+        if let Some(solid_block_buffer) = self.solid_block_buffer.as_mut() {
+            let offset = solid_block_buffer.data.len() as u64;
+            solid_block_buffer.data.extend_from_slice(&data);
+            let method = solid_block_buffer.method;
+            let block_full = solid_block_buffer
+                .block_size
+                .is_some_and(|block_size| solid_block_buffer.data.len() as u64 >= block_size);
+            self.index.entries.push(ArchiveEntry {
+                name: name.to_os_string(),
+                kind: EntryKind::File,
+                link_target: OsString::new(),
+                method,
+                size,
+                stored_size: 0,
+                offset,
+                solid_block: Some(self.index.solid_blocks.len() as u64),
+                metadata: Some(metadata.clone()),
+                data_regions: None,
+                duplicate: false,
+                chunks: None,
+                hash: Some(data_hash),
+                unchanged: false,
+                crc32: None,
+            });
+            if block_full {
+                self.write_solid_block()?;
+            }
+            return Ok(());
+        }
+
+        self.write_u64(name.len() as u64)?;
+        self.write_bytes(name.as_bytes())?;
+
+        let mut stored_method = Method::Store;
+        let mut stored_data = data;
+        if method != Method::Store {
+            let compressed_data = codec::compress(method, &stored_data)?;
+            if (compressed_data.len() as u64) < size {
+                stored_method = method;
+                stored_data = compressed_data;
+            }
+        }
+        self.write_u64(stored_data.len() as u64)?;
+        let offset = self.position;
+        self.write_bytes(&stored_data)?;
+
+        self.index.entries.push(ArchiveEntry {
+            name: name.to_os_string(),
+            kind: EntryKind::File,
+            link_target: OsString::new(),
+            method: stored_method,
+            size,
+            stored_size: stored_data.len() as u64,
+            offset,
+            solid_block: None,
+            metadata: Some(metadata.clone()),
+            data_regions: None,
+            duplicate: false,
+            chunks: None,
+            hash: Some(data_hash),
+            unchanged: false,
+            crc32: None,
+        });
+        Ok(())
+    }
+
+    /// Writes the data of an entry as a list of blocks, reading it one piece at a time:
+    /// as content-defined chunks if chunking is enabled, in pieces of
+    /// `MAX_BUFFERED_SIZE` bytes otherwise. A piece that was written before is not
+    /// written again, so that the data of a duplicate entry is not stored twice.
+    fn add_entry_in_blocks<R: Read>(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        method: Method,
+        size: u64,
+        reader: &mut R,
+    ) -> Result<()> {
+        // close the block being filled, so that its index is not taken
+        self.write_solid_block()?;
+        let method = self
+            .chunking
+            .as_ref()
+            .map_or(method, |chunking| chunking.method);
+        let buffer_size = self
+            .chunking
+            .as_ref()
+            .map_or(MAX_BUFFERED_SIZE, |chunking| chunking.chunker.max_size());
+        let mut reader = reader.take(size);
+        let mut hasher = Sha256::new();
+        let mut buffer = Vec::new();
+        let mut bytes_read = 0;
+        let mut chunks = Vec::new();
+        loop {
+            let buffer_len = buffer.len();
+            (&mut reader)
+                .take(buffer_size - buffer_len as u64)
+                .read_to_end(&mut buffer)?;
+            hasher.update(&buffer[buffer_len..]);
+            bytes_read += (buffer.len() - buffer_len) as u64;
+            if buffer.is_empty() {
+                break;
+            }
+            // a chunk boundary depends on at most `buffer_size` bytes of data
+            let chunk_len = match self.chunking.as_ref() {
+                Some(chunking) => chunking.chunker.next_chunk_len(&buffer),
+                None => buffer.len(),
+            };
+            chunks.push(self.write_chunk(method, &buffer[..chunk_len])?);
+            buffer.drain(..chunk_len);
+        }
+        if bytes_read != size {
+            bail!("entry {} was truncated while archiving", name.display());
+        }
+
+        let data_hash: [u8; 32] = hasher.finalize().into();
+        if self.add_duplicate_entry(name, metadata, size, data_hash) {
+            return Ok(());
+        }
+        self.index.entries.push(ArchiveEntry {
+            name: name.to_os_string(),
+            kind: EntryKind::File,
+            link_target: OsString::new(),
+            method,
+            size,
+            stored_size: 0,
+            offset: 0,
+            solid_block: None,
+            metadata: Some(metadata.clone()),
+            data_regions: None,
+            duplicate: false,
+            chunks: Some(chunks),
+            hash: Some(data_hash),
+            unchanged: false,
+            crc32: None,
+        });
+        Ok(())
+    }
+
+    /// Adds an entry referring to the data of an earlier entry whose data has the hash
+    /// `data_hash`, returning false (and recording the hash for the entry about to be
+    /// added) if there is none
+    fn add_duplicate_entry(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        size: u64,
+        data_hash: [u8; 32],
+    ) -> bool {
+        let Some(&original_index) = self.data_hashes.get(&data_hash) else {
+            self.data_hashes.insert(data_hash, self.index.entries.len());
+            return false;
+        };
+        let original = &self.index.entries[original_index];
+        self.index.entries.push(ArchiveEntry {
+            name: name.to_os_string(),
+            size,
+            metadata: Some(metadata.clone()),
+            data_regions: None,
+            duplicate: true,
+            ..original.clone()
+        });
+        true
+    }
+
+    /// Writes the local record of a sparse file of `size` bytes, reading the
+    /// concatenation of its `data_regions` from `reader` (see `add_entry`)
+    pub fn add_sparse_entry<R: Read>(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        method: Method,
+        size: u64,
+        data_regions: &[DataRegion],
+        reader: &mut R,
+    ) -> Result<()> {
+        self.add_entry(
+            name,
+            metadata,
+            method,
+            sparse::data_length(data_regions),
+            reader,
+        )?;
+        let entry = self.index.entries.last_mut().unwrap();
+        entry.size = size;
+        entry.data_regions = Some(data_regions.to_vec());
+        Ok(())
+    }
+
+    /// Adds an entry taken from another archive, copying its data as stored there
+    /// from `stored_data` without decompressing it. No data is copied for entries
+    /// held in blocks, which must be added first with `add_stored_solid_block`, or for
+    /// duplicate entries, whose offset must refer to data already copied.
+    pub fn add_stored_entry<R: Read>(
+        &mut self,
+        entry: &ArchiveEntry,
+        stored_data: &mut R,
+    ) -> Result<()> {
+        let mut entry = entry.clone();
+        if entry.has_local_record() {
+            self.write_u64(entry.name.len() as u64)?;
+            self.write_bytes(entry.name.as_bytes())?;
+            self.write_u64(entry.stored_size)?;
+            entry.offset = self.position;
+            let bytes_copied = copy(&mut stored_data.take(entry.stored_size), &mut self.writer)?;
+            if bytes_copied != entry.stored_size {
+                bail!("entry {} was truncated while copying", entry.name.display());
+            }
+            self.position += entry.stored_size;
+        }
+        self.index.entries.push(entry);
+        Ok(())
+    }
+
+    /// Adds a solid block taken from another archive, copying it as stored there
+    /// from `stored_data`, and returns its index in this archive. The entries held
+    /// in the block must then be added with `add_stored_entry`.
+    pub fn add_stored_solid_block<R: Read>(
+        &mut self,
+        solid_block: &SolidBlock,
+        stored_data: &mut R,
+    ) -> Result<u64> {
+        // close the block being filled, so that its index is not taken
+        self.write_solid_block()?;
+        self.write_u64(solid_block.stored_size)?;
+        let offset = self.position;
+        let bytes_copied = copy(
+            &mut stored_data.take(solid_block.stored_size),
+            &mut self.writer,
+        )?;
+        if bytes_copied != solid_block.stored_size {
+            bail!("solid block was truncated while copying");
+        }
+        self.position += solid_block.stored_size;
+        self.index.solid_blocks.push(SolidBlock {
+            offset,
+            ..solid_block.clone()
+        });
+        Ok(self.index.solid_blocks.len() as u64 - 1)
+    }
+
+    /// Adds an entry that holds no data: a directory, a symbolic link or a hard link
+    /// (whose data is held by the entry named by `link_target`)
+    pub fn add_entry_without_data(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        kind: EntryKind,
+        link_target: &OsStr,
+    ) -> Result<()> {
+        if kind == EntryKind::File {
+            bail!(
+                "entry {} is a regular file and must be added with its data",
+                name.display()
+            );
+        }
+        self.index.entries.push(ArchiveEntry {
+            name: name.to_os_string(),
+            kind,
+            link_target: link_target.to_os_string(),
+            method: Method::Store,
+            size: 0,
+            stored_size: 0,
+            offset: 0,
+            solid_block: None,
+            metadata: Some(metadata.clone()),
+            data_regions: None,
+            duplicate: false,
+            chunks: None,
+            hash: None,
+            unchanged: false,
+            crc32: None,
+        });
+        Ok(())
+    }
+
+    /// Adds a file left unchanged since `previous_entry` was written to the previous
+    /// archive of an incremental chain, whose data is not stored again
+    pub fn add_unchanged_entry(
+        &mut self,
+        name: &OsStr,
+        metadata: &EntryMetadata,
+        previous_entry: &ArchiveEntry,
+    ) -> Result<()> {
+        if previous_entry.kind != EntryKind::File || previous_entry.hash.is_none() {
+            bail!(
+                "entry {} of the previous archive cannot be referred to",
+                previous_entry.name.display()
+            );
+        }
+        self.index.entries.push(ArchiveEntry {
+            name: name.to_os_string(),
+            kind: EntryKind::File,
+            link_target: OsString::new(),
+            method: previous_entry.method,
+            size: previous_entry.size,
+            stored_size: 0,
+            offset: 0,
+            solid_block: None,
+            metadata: Some(metadata.clone()),
+            data_regions: previous_entry.data_regions.clone(),
+            duplicate: false,
+            chunks: None,
+            hash: previous_entry.hash,
+            unchanged: true,
+            crc32: None,
+        });
+        Ok(())
+    }
+
+    /// Makes the archive an increment over the archive identified by `previous_archive`
+    /// (see `archive_id`), recording the names of the entries deleted since then
+    pub fn set_previous_archive(
+        &mut self,
+        previous_archive: [u8; 32],
+        deleted_names: Vec<OsString>,
+    ) {
+        self.index.previous_archive = Some(previous_archive);
+        self.index.deleted_names = deleted_names;
+    }
+
+    /// Compresses and writes the current solid block, if it holds any data
+    fn write_solid_block(&mut self) -> Result<()> {
+        let Some(solid_block_buffer) = self.solid_block_buffer.as_mut() else {
+            return Ok(());
+        };
+        if solid_block_buffer.data.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut solid_block_buffer.data);
+        let method = solid_block_buffer.method;
+        let block_index = self.write_block(method, &data)?;
+
+        // entries of the block record the method of the block
+        let method = self.index.solid_blocks[block_index as usize].method;
+        for entry in self
+            .index
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.solid_block == Some(block_index))
+        {
+            entry.method = method;
+        }
+        Ok(())
+    }
+
+    /// Compresses `data` with `method` (or stores it as-is if compressing it does not
+    /// reduce its size) and writes it as a block, returning the index of the block
+    fn write_block(&mut self, method: Method, data: &[u8]) -> Result<u64> {
+        let mut method = method;
+        let mut compressed_data = codec::compress(method, data)?;
+        if compressed_data.len() >= data.len() {
+            method = Method::Store;
+            compressed_data = data.to_vec();
+        }
+        self.write_u64(compressed_data.len() as u64)?;
+        let offset = self.position;
+        self.write_bytes(&compressed_data)?;
+        self.index.solid_blocks.push(SolidBlock {
+            method,
+            size: data.len() as u64,
+            stored_size: compressed_data.len() as u64,
+            offset,
+        });
+        Ok(self.index.solid_blocks.len() as u64 - 1)
+    }
 
-fn read_u64(file: &mut File) -> Result<u64> {
+    /// Writes `chunk` as a block unless it was written before, returning the index
+    /// of the block holding it
+    fn write_chunk(&mut self, method: Method, chunk: &[u8]) -> Result<u64> {
+        let chunk_hash: [u8; 32] = Sha256::digest(chunk).into();
+        if let Some(block_index) = self.chunk_hashes.get(&chunk_hash) {
+            return Ok(*block_index);
+        }
+        let block_index = self.write_block(method, chunk)?;
+        self.chunk_hashes.insert(chunk_hash, block_index);
+        Ok(block_index)
+    }
+
+    /// Writes the central directory and the footer, returning the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.write_solid_block()?;
+        let directory_offset = self.position;
+        let solid_blocks = std::mem::take(&mut self.index.solid_blocks);
+        let entries = std::mem::take(&mut self.index.entries);
+        self.write_u64(solid_blocks.len() as u64)?;
+        for solid_block in solid_blocks.iter() {
+            self.write_bytes(&[solid_block.method.id()])?;
+            self.write_u64(solid_block.size)?;
+            self.write_u64(solid_block.stored_size)?;
+            self.write_u64(solid_block.offset)?;
+        }
+        for entry in entries.iter() {
+            self.write_u64(entry.name.len() as u64)?;
+            self.write_bytes(entry.name.as_bytes())?;
+            self.write_bytes(&[entry.method.id()])?;
+            self.write_u64(entry.size)?;
+            self.write_u64(entry.stored_size)?;
+            self.write_u64(entry.offset)?;
+            self.write_u64(entry.solid_block.unwrap_or(NO_SOLID_BLOCK))?;
+            let metadata = entry.metadata.clone().unwrap_or_default();
+            self.write_bytes(&metadata.to_bytes())?;
+            self.write_bytes(&[entry.kind.id()])?;
+            self.write_bytes(&[entry.flags()])?;
+            self.write_u64(entry.link_target.len() as u64)?;
+            self.write_bytes(entry.link_target.as_bytes())?;
+            if let Some(data_regions) = &entry.data_regions {
+                self.write_u64(data_regions.len() as u64)?;
+                for data_region in data_regions.iter() {
+                    self.write_u64(data_region.offset)?;
+                    self.write_u64(data_region.length)?;
+                }
+            }
+            if let Some(chunks) = &entry.chunks {
+                self.write_u64(chunks.len() as u64)?;
+                for block_index in chunks.iter() {
+                    self.write_u64(*block_index)?;
+                }
+            }
+            if let Some(hash) = &entry.hash {
+                self.write_bytes(hash)?;
+            }
+        }
+        let deleted_names = std::mem::take(&mut self.index.deleted_names);
+        self.write_u64(deleted_names.len() as u64)?;
+        for name in deleted_names.iter() {
+            self.write_u64(name.len() as u64)?;
+            self.write_bytes(name.as_bytes())?;
+        }
+        match self.index.previous_archive {
+            Some(previous_archive) => {
+                self.write_bytes(&[1])?;
+                self.write_bytes(&previous_archive)?;
+            }
+            None => self.write_bytes(&[0])?,
+        }
+        self.write_u64(directory_offset)?;
+        self.write_u64(entries.len() as u64)?;
+        self.write_bytes(ARCHIVE_MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
     let mut buffer = [0u8; 8];
-    file.read_exact(&mut buffer)?;
+    reader.read_exact(&mut buffer)?;
     Ok(u64::from_be_bytes(buffer))
 }
 
-pub fn archive_files(paths: &[&str], archive_file_path: &str) -> Result<()> {
-    let mut archive_file = File::create_new(archive_file_path)?;
-    archive_file.write(&(paths.len() as u64).to_be_bytes())?;
-    for path in paths.into_iter() {
-        let mut file = File::open(path)?;
-        let file_path = Path::new(path);
-        let file_name = file_path.file_name().unwrap();
-        let file_size = file.metadata().unwrap().len() as u64;
-        archive_file.write(&(file_name.len() as u64).to_be_bytes())?;
-        archive_file.write(file_name.as_bytes())?;
-        archive_file.write(&(file_size.to_be_bytes()))?;
-        copy(&mut file, &mut archive_file)?;
+/// Reads a length followed by that many bytes, without trusting the length to
+/// allocate before the bytes are there
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        bail!("archive is truncated or corrupted");
+    }
+    Ok(bytes)
+}
+
+fn read_name<R: Read>(reader: &mut R) -> Result<OsString> {
+    Ok(OsString::from_vec(read_bytes(reader)?))
+}
+
+/// Reads the footer of the archive, returning the offset of the central directory
+/// and the number of entries, or `None` if the archive has no central directory
+fn read_footer<R: Read + Seek>(archive: &mut R) -> Result<Option<(u64, u64)>> {
+    let archive_len = archive.seek(SeekFrom::End(0))?;
+    if archive_len < 8 + FOOTER_SIZE {
+        return Ok(None);
+    }
+    archive.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    let directory_offset = read_u64(archive)?;
+    let num_entries = read_u64(archive)?;
+    let mut magic = [0u8; 8];
+    archive.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Ok(None);
+    }
+    Ok(Some((directory_offset, num_entries)))
+}
+
+/// Reads the central directory of the archive, or scans the local records
+/// if the archive has no central directory. ZIP files are read as archives.
+pub fn read_index<R: Read + Seek>(archive: &mut R) -> Result<ArchiveIndex> {
+    if zip::is_zip(archive)? {
+        return zip::read_index(archive);
+    }
+    if let Some((directory_offset, num_entries)) = read_footer(archive)? {
+        archive.seek(SeekFrom::Start(directory_offset))?;
+        let num_solid_blocks = read_u64(archive)?;
+        let mut solid_blocks = Vec::new();
+        for _ in 0..num_solid_blocks {
+            let method = Method::from_id(read_u8(archive)?)?;
+            let size = read_u64(archive)?;
+            let stored_size = read_u64(archive)?;
+            let offset = read_u64(archive)?;
+            solid_blocks.push(SolidBlock {
+                method,
+                size,
+                stored_size,
+                offset,
+            });
+        }
+        let mut entries = Vec::new();
+        for _ in 0..num_entries {
+            let name = read_name(archive)?;
+            let method = Method::from_id(read_u8(archive)?)?;
+            let size = read_u64(archive)?;
+            let stored_size = read_u64(archive)?;
+            let offset = read_u64(archive)?;
+            let solid_block = match read_u64(archive)? {
+                NO_SOLID_BLOCK => None,
+                block_index if block_index < num_solid_blocks => Some(block_index),
+                block_index => bail!(
+                    "entry {} refers to missing block {}",
+                    name.display(),
+                    block_index
+                ),
+            };
+            let metadata = EntryMetadata::read(archive)?;
+            let kind = EntryKind::from_id(read_u8(archive)?)?;
+            let flags = read_u8(archive)?;
+            let link_target = read_name(archive)?;
+            if (flags & NAME_IS_UTF8 != 0 && name.to_str().is_none())
+                || (flags & LINK_TARGET_IS_UTF8 != 0 && link_target.to_str().is_none())
+            {
+                bail!("entry {} is corrupted", escape_name(&name));
+            }
+            let mut data_regions = None;
+            if flags & IS_SPARSE != 0 {
+                let num_data_regions = read_u64(archive)?;
+                let mut regions = Vec::new();
+                for _ in 0..num_data_regions {
+                    let offset = read_u64(archive)?;
+                    let length = read_u64(archive)?;
+                    if offset.checked_add(length).is_none_or(|end| end > size) {
+                        bail!("entry {} is corrupted", escape_name(&name));
+                    }
+                    regions.push(DataRegion { offset, length });
+                }
+                data_regions = Some(regions);
+            }
+            let mut chunks = None;
+            if flags & IS_CHUNKED != 0 {
+                let num_chunks = read_u64(archive)?;
+                let mut block_indices = Vec::new();
+                for _ in 0..num_chunks {
+                    let block_index = read_u64(archive)?;
+                    if block_index >= num_solid_blocks {
+                        bail!(
+                            "entry {} refers to missing block {}",
+                            name.display(),
+                            block_index
+                        );
+                    }
+                    block_indices.push(block_index);
+                }
+                chunks = Some(block_indices);
+            }
+            let mut hash = None;
+            if flags & HAS_HASH != 0 {
+                let mut data_hash = [0u8; 32];
+                archive.read_exact(&mut data_hash)?;
+                hash = Some(data_hash);
+            }
+            entries.push(ArchiveEntry {
+                name,
+                kind,
+                link_target,
+                method,
+                size,
+                stored_size,
+                offset,
+                solid_block,
+                metadata: Some(metadata),
+                data_regions,
+                duplicate: flags & IS_DUPLICATE != 0,
+                chunks,
+                hash,
+                unchanged: flags & IS_UNCHANGED != 0,
+                crc32: None,
+            });
+        }
+        let num_deleted_names = read_u64(archive)?;
+        let mut deleted_names = Vec::new();
+        for _ in 0..num_deleted_names {
+            deleted_names.push(read_name(archive)?);
+        }
+        let mut previous_archive = None;
+        if read_u8(archive)? != 0 {
+            let mut archive_id = [0u8; 32];
+            archive.read_exact(&mut archive_id)?;
+            previous_archive = Some(archive_id);
+        }
+        return Ok(ArchiveIndex {
+            solid_blocks,
+            entries,
+            deleted_names,
+            previous_archive,
+        });
+    }
+
+    // no central directory, scan the local records from the start of the archive
+    // (such archives only contain stored entries)
+    archive.seek(SeekFrom::Start(0))?;
+    let num_entries = read_u64(archive)?;
+    let mut entries = Vec::new();
+    for _ in 0..num_entries {
+        let name = read_name(archive)?;
+        let size = read_u64(archive)?;
+        let offset = archive.stream_position()?;
+        archive.seek(SeekFrom::Current(size as i64))?;
+        entries.push(ArchiveEntry {
+            name,
+            kind: EntryKind::File,
+            link_target: OsString::new(),
+            method: Method::Store,
+            size,
+            stored_size: size,
+            offset,
+            solid_block: None,
+            metadata: None,
+            data_regions: None,
+            duplicate: false,
+            chunks: None,
+            hash: None,
+            unchanged: false,
+            crc32: None,
+        });
+    }
+    Ok(ArchiveIndex {
+        solid_blocks: Vec::new(),
+        entries,
+        deleted_names: Vec::new(),
+        previous_archive: None,
+    })
+}
+
+/// Identifies an archive by the SHA-256 hash of its central directory, which
+/// describes all of its entries, so that an incremental archive can check that it
+/// is restored on top of the archive it was made from
+pub fn archive_id<R: Read + Seek>(archive: &mut R) -> Result<[u8; 32]> {
+    let directory_offset = match read_footer(archive)? {
+        Some((directory_offset, _)) => directory_offset,
+        // hash the whole archive if it has no central directory
+        None => 0,
+    };
+    archive.seek(SeekFrom::Start(directory_offset))?;
+    let mut directory = Vec::new();
+    archive.read_to_end(&mut directory)?;
+    Ok(Sha256::digest(&directory).into())
+}
+
+/// Passes the data of an entry on to `writer`, counting its size and
+/// computing its CRC-32 as it is written
+struct CheckedWriter<W: Write> {
+    writer: W,
+    size: u64,
+    crc32: u32,
+}
+
+impl<W: Write> CheckedWriter<W> {
+    fn new(writer: W) -> Self {
+        CheckedWriter {
+            writer,
+            size: 0,
+            crc32: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for CheckedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let bytes_written = self.writer.write(buf)?;
+        self.size += bytes_written as u64;
+        self.crc32 = checksum::update_crc32(self.crc32, &buf[..bytes_written]);
+        std::result::Result::Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads entries from an archive through its central directory, keeping the
+/// last decompressed solid block so that consecutive entries of a block
+/// only require decompressing it once
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    index: ArchiveIndex,
+    cached_solid_block: Option<(u64, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let index = read_index(&mut reader)?;
+        Ok(ArchiveReader {
+            reader,
+            index,
+            cached_solid_block: None,
+        })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.index.entries
+    }
+
+    /// Identifier of the archive this incremental archive was made from
+    pub fn previous_archive(&self) -> Option<[u8; 32]> {
+        self.index.previous_archive
+    }
+
+    fn read_stored_data(&mut self, offset: u64, stored_size: u64) -> Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut stored_data = Vec::new();
+        (&mut self.reader)
+            .take(stored_size)
+            .read_to_end(&mut stored_data)?;
+        if stored_data.len() as u64 != stored_size {
+            bail!("archive is truncated");
+        }
+        Ok(stored_data)
+    }
+
+    fn read_solid_block(&mut self, block_index: u64) -> Result<&[u8]> {
+        let is_cached = self
+            .cached_solid_block
+            .as_ref()
+            .is_some_and(|(cached_index, _)| *cached_index == block_index);
+        if !is_cached {
+            let solid_block = self.index.solid_blocks[block_index as usize].clone();
+            let stored_data = self.read_stored_data(solid_block.offset, solid_block.stored_size)?;
+            let data = codec::decompress(solid_block.method, &stored_data)?;
+            if data.len() as u64 != solid_block.size {
+                bail!("solid block {} is corrupted", block_index);
+            }
+            self.cached_solid_block = Some((block_index, data));
+        }
+        Ok(&self.cached_solid_block.as_ref().unwrap().1)
+    }
+
+    /// Writes the data of the entry at `entry_index` to `writer`,
+    /// decompressing it if required and checking its CRC-32 if recorded
+    pub fn extract_entry<W: Write>(&mut self, entry_index: usize, writer: &mut W) -> Result<()> {
+        let Some(expected_crc32) = self.index.entries[entry_index].crc32 else {
+            return self.extract_data(entry_index, writer);
+        };
+        // the data is checked as it is written, so a mismatch is only reported at the end
+        let mut checked_writer = CheckedWriter::new(writer);
+        self.extract_data(entry_index, &mut checked_writer)?;
+        if checked_writer.crc32 != expected_crc32 {
+            bail!(
+                "entry {} is corrupted, its CRC-32 does not match",
+                self.index.entries[entry_index].name.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn extract_data<W: Write>(&mut self, entry_index: usize, writer: &mut W) -> Result<()> {
+        let entry = self.index.entries[entry_index].clone();
+        if entry.unchanged {
+            bail!(
+                "the data of entry {} is held by a previous archive",
+                entry.name.display()
+            );
+        }
+        if let Some(chunks) = &entry.chunks {
+            let mut data_size = 0;
+            for block_index in chunks.iter() {
+                let chunk_data = self.read_solid_block(*block_index)?;
+                data_size += chunk_data.len() as u64;
+                writer.write_all(chunk_data)?;
+            }
+            if data_size != entry.data_size() {
+                bail!("entry {} is corrupted", entry.name.display());
+            }
+        } else if let Some(block_index) = entry.solid_block {
+            let block_data = self.read_solid_block(block_index)?;
+            let start = entry.offset as usize;
+            let end = start + entry.data_size() as usize;
+            if end > block_data.len() {
+                bail!("entry {} is corrupted", entry.name.display());
+            }
+            writer.write_all(&block_data[start..end])?;
+        } else if entry.method == Method::Store {
+            self.reader.seek(SeekFrom::Start(entry.offset))?;
+            copy(&mut (&mut self.reader).take(entry.stored_size), writer)?;
+        } else if entry.method == Method::Deflate {
+            // entries of ZIP files may be of any size, so they are inflated as they are read
+            self.reader.seek(SeekFrom::Start(entry.offset))?;
+            let mut checked_writer = CheckedWriter::new(writer);
+            deflate::inflate_stream(
+                BufReader::new((&mut self.reader).take(entry.stored_size)),
+                &[],
+                &mut checked_writer,
+            )?;
+            if checked_writer.size != entry.data_size() {
+                bail!("entry {} is corrupted", entry.name.display());
+            }
+        } else {
+            // larger files are written in blocks, so these entries hold at most MAX_BUFFERED_SIZE bytes
+            let compressed_data = self.read_stored_data(entry.offset, entry.stored_size)?;
+            let data = codec::decompress(entry.method, &compressed_data)?;
+            if data.len() as u64 != entry.data_size() {
+                bail!("entry {} is corrupted", entry.name.display());
+            }
+            writer.write_all(&data)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the file at `path` with the data of the entry at `entry_index`,
+    /// recreating the holes of sparse files
+    pub fn extract_file(&mut self, entry_index: usize, path: &Path) -> Result<()> {
+        let mut file = File::create_new(path)?;
+        let Some(data_regions) = self.index.entries[entry_index].data_regions.clone() else {
+            return self.extract_entry(entry_index, &mut file);
+        };
+        self.extract_entry(
+            entry_index,
+            &mut SparseWriter::new(&mut file, &data_regions),
+        )?;
+        file.set_len(self.index.entries[entry_index].size)?;
+        Ok(())
+    }
+}
+
+/// A file system object to be written to an archive
+struct ArchiveSource {
+    path: PathBuf,
+    /// name of the entry in the archive
+    name: OsString,
+    metadata: std::fs::Metadata,
+}
+
+/// Collects `path` and, if it is a directory, everything below it, naming
+/// the entries relative to `name` and skipping the files rejected by `filter`.
+/// `ancestors` holds the (device, inode) pairs of the enclosing directories
+/// to detect loops through symbolic links.
+fn collect_sources(
+    path: &Path,
+    name: OsString,
+    options: &ArchiveOptions,
+    filter: &mut SourceFilter,
+    ancestors: &mut Vec<(u64, u64)>,
+    sources: &mut Vec<ArchiveSource>,
+) -> Result<()> {
+    let metadata = if options.dereference {
+        std::fs::metadata(path)?
+    } else {
+        std::fs::symlink_metadata(path)?
+    };
+    let file_type = metadata.file_type();
+    if !filter.is_included(path, &name, file_type.is_dir()) {
+        return Ok(());
+    }
+    if !(file_type.is_file() || file_type.is_dir() || file_type.is_symlink()) {
+        eprintln!(
+            "skipping {}: not a regular file, directory or symbolic link",
+            path.display()
+        );
+        return Ok(());
+    }
+    let is_dir = file_type.is_dir();
+    let inode = (metadata.dev(), metadata.ino());
+    sources.push(ArchiveSource {
+        path: path.to_path_buf(),
+        name: name.clone(),
+        metadata,
+    });
+    if is_dir {
+        if ancestors.contains(&inode) {
+            bail!("{} is part of a directory loop", path.display());
+        }
+        let mut children = read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+        ancestors.push(inode);
+        filter.enter_directory(path)?;
+        for child in children {
+            let mut child_name = name.clone();
+            child_name.push("/");
+            child_name.push(child.file_name());
+            collect_sources(
+                &child.path(),
+                child_name,
+                options,
+                filter,
+                ancestors,
+                sources,
+            )?;
+        }
+        filter.leave_directory();
+        ancestors.pop();
+    }
+    Ok(())
+}
+
+/// Collects the file system objects to archive for each of `paths`
+fn collect_archive_sources(paths: &[&str], options: &ArchiveOptions) -> Result<Vec<ArchiveSource>> {
+    let mut sources = Vec::new();
+    let mut filter = SourceFilter::new(options)?;
+    for path in paths.iter() {
+        let path = Path::new(path);
+        // paths such as '.' have no file name, name them after the directory they resolve to
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_os_string(),
+            None => match path.canonicalize()?.file_name() {
+                Some(file_name) => file_name.to_os_string(),
+                None => bail!("cannot archive {}", path.display()),
+            },
+        };
+        collect_sources(
+            path,
+            file_name,
+            options,
+            &mut filter,
+            &mut Vec::new(),
+            &mut sources,
+        )?;
+    }
+    Ok(sources)
+}
+
+/// Returns the SHA-256 hash of the data read from `reader`, read piece by piece
+fn hash_data<R: Read>(reader: &mut R) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+}
+
+/// Returns true if the file of `source` has the same contents as `previous_entry`,
+/// its entry in the previous archive. Files of the same size and mtime are taken as
+/// unchanged, other files of the same size are compared by the hash of their data.
+fn is_unchanged(source: &ArchiveSource, previous_entry: &ArchiveEntry) -> Result<bool> {
+    let size = source.metadata.len();
+    let Some(previous_hash) = previous_entry.hash else {
+        return Ok(false);
+    };
+    if size != previous_entry.size {
+        return Ok(false);
+    }
+    let source_mtime = (source.metadata.mtime(), source.metadata.mtime_nsec() as u32);
+    if let Some(metadata) = &previous_entry.metadata {
+        if source_mtime == (metadata.mtime, metadata.mtime_nsec) {
+            return Ok(true);
+        }
+    }
+    let mut file = File::open(&source.path)?;
+    let data_regions = sparse::data_regions(&mut file, size)?;
+    if data_regions != previous_entry.data_regions {
+        return Ok(false);
+    }
+    let data_hash = match &data_regions {
+        Some(data_regions) => hash_data(&mut SparseReader::new(&mut file, data_regions))?,
+        None => hash_data(&mut file)?,
+    };
+    Ok(data_hash == previous_hash)
+}
+
+/// Adds `sources` to the archive, storing files that share an inode as hard links.
+/// Files found unchanged in `previous_entries`, the file entries of the previous
+/// archive by name, are added without their data.
+fn add_sources<W: Write>(
+    archive_writer: &mut ArchiveWriter<W>,
+    sources: &[ArchiveSource],
+    options: &ArchiveOptions,
+    previous_entries: &HashMap<OsString, ArchiveEntry>,
+) -> Result<()> {
+    // name of the first entry archived for each (device, inode) pair with several links
+    let mut hard_links: HashMap<(u64, u64), OsString> = HashMap::new();
+    for source in sources.iter() {
+        let mut entry_metadata = EntryMetadata::from_metadata(&source.metadata);
+        if options.xattrs {
+            entry_metadata.xattrs = xattr::read_xattrs(&source.path, options.dereference)?;
+        }
+        let file_type = source.metadata.file_type();
+        if file_type.is_dir() {
+            archive_writer.add_entry_without_data(
+                &source.name,
+                &entry_metadata,
+                EntryKind::Directory,
+                OsStr::new(""),
+            )?;
+        } else if file_type.is_symlink() {
+            let target = read_link(&source.path)?;
+            archive_writer.add_entry_without_data(
+                &source.name,
+                &entry_metadata,
+                EntryKind::Symlink,
+                target.as_os_str(),
+            )?;
+        } else {
+            if source.metadata.nlink() > 1 {
+                let inode = (source.metadata.dev(), source.metadata.ino());
+                if let Some(target) = hard_links.get(&inode) {
+                    archive_writer.add_entry_without_data(
+                        &source.name,
+                        &entry_metadata,
+                        EntryKind::HardLink,
+                        target,
+                    )?;
+                    continue;
+                }
+                hard_links.insert(inode, source.name.clone());
+            }
+            if let Some(previous_entry) = previous_entries.get(&source.name) {
+                if is_unchanged(source, previous_entry)? {
+                    archive_writer.add_unchanged_entry(
+                        &source.name,
+                        &entry_metadata,
+                        previous_entry,
+                    )?;
+                    continue;
+                }
+            }
+            let mut file = File::open(&source.path)?;
+            let size = source.metadata.len();
+            match sparse::data_regions(&mut file, size)? {
+                Some(data_regions) => archive_writer.add_sparse_entry(
+                    &source.name,
+                    &entry_metadata,
+                    options.method,
+                    size,
+                    &data_regions,
+                    &mut SparseReader::new(&mut file, &data_regions),
+                )?,
+                None => archive_writer.add_entry(
+                    &source.name,
+                    &entry_metadata,
+                    options.method,
+                    size,
+                    &mut file,
+                )?,
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn archive_files(
+    paths: &[&str],
+    archive_file_path: &str,
+    options: &ArchiveOptions,
+) -> Result<()> {
+    let sources = collect_archive_sources(paths, options)?;
+    if options.format == ArchiveFormat::Zip {
+        if options.solid
+            || options.chunked
+            || options.incremental_from.is_some()
+            || options.volume_size.is_some()
+            || options.xattrs
+        {
+            bail!("ZIP files cannot be solid, chunked, incremental, split or hold extended attributes");
+        }
+        let archive_file = BufWriter::new(File::create_new(archive_file_path)?);
+        write_zip(ZipWriter::new(archive_file), &sources, options)?;
+        return Ok(());
+    }
+    // identifier, deleted entries and file entries of the previous archive
+    let mut previous_archive = None;
+    let mut previous_entries = HashMap::new();
+    if let Some(previous_path) = &options.incremental_from {
+        let mut previous_file = ArchiveFile::open(previous_path)?;
+        let previous_id = archive_id(&mut previous_file)?;
+        let previous_index = read_index(&mut previous_file)?;
+        let source_names: HashSet<&OsString> = sources.iter().map(|source| &source.name).collect();
+        let deleted_names: Vec<OsString> = previous_index
+            .entries
+            .iter()
+            .filter(|entry| !source_names.contains(&entry.name))
+            .map(|entry| entry.name.clone())
+            .collect();
+        previous_archive = Some((previous_id, deleted_names));
+        previous_entries = previous_index
+            .entries
+            .into_iter()
+            .filter(|entry| entry.kind == EntryKind::File)
+            .map(|entry| (entry.name.clone(), entry))
+            .collect();
+    }
+    match options.volume_size {
+        Some(volume_size) => {
+            let volume_writer = VolumeWriter::new(Path::new(archive_file_path), volume_size)?;
+            write_archive(
+                volume_writer,
+                &sources,
+                options,
+                previous_archive,
+                &previous_entries,
+            )?
+            .finish()?;
+        }
+        None => {
+            let archive_file = BufWriter::new(File::create_new(archive_file_path)?);
+            write_archive(
+                archive_file,
+                &sources,
+                options,
+                previous_archive,
+                &previous_entries,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes an archive of `sources` to `writer`, as an increment over the archive
+/// identified by `previous_archive` (with the names of the entries deleted since
+/// then) if set, returning the writer
+fn write_archive<W: Write>(
+    writer: W,
+    sources: &[ArchiveSource],
+    options: &ArchiveOptions,
+    previous_archive: Option<([u8; 32], Vec<OsString>)>,
+    previous_entries: &HashMap<OsString, ArchiveEntry>,
+) -> Result<W> {
+    let num_entries = sources.len() as u64;
+    let mut archive_writer = if options.solid {
+        ArchiveWriter::new_solid(
+            writer,
+            num_entries,
+            options.method,
+            options.solid_block_size,
+        )?
+    } else {
+        ArchiveWriter::new(writer, num_entries)?
+    };
+    if options.chunked {
+        archive_writer.enable_chunking(options.method, options.chunk_size)?;
+    }
+    if let Some((previous_id, deleted_names)) = previous_archive {
+        archive_writer.set_previous_archive(previous_id, deleted_names);
+    }
+    add_sources(&mut archive_writer, sources, options, previous_entries)?;
+    archive_writer.finish()
+}
+
+/// Writes `sources` to a ZIP file. ZIP files have no hard links,
+/// files sharing an inode are each written with their data.
+fn write_zip<W: Write>(
+    mut zip_writer: ZipWriter<W>,
+    sources: &[ArchiveSource],
+    options: &ArchiveOptions,
+) -> Result<W> {
+    for source in sources.iter() {
+        let metadata = EntryMetadata::from_metadata(&source.metadata);
+        let file_type = source.metadata.file_type();
+        if file_type.is_dir() {
+            zip_writer.add_entry(
+                &source.name,
+                EntryKind::Directory,
+                &metadata,
+                Method::Store,
+                0,
+                &mut [].as_slice(),
+            )?;
+        } else if file_type.is_symlink() {
+            let target = read_link(&source.path)?;
+            let target = target.as_os_str().as_bytes();
+            zip_writer.add_entry(
+                &source.name,
+                EntryKind::Symlink,
+                &metadata,
+                Method::Store,
+                target.len() as u64,
+                &mut &target[..],
+            )?;
+        } else {
+            zip_writer.add_entry(
+                &source.name,
+                EntryKind::File,
+                &metadata,
+                options.method,
+                source.metadata.len(),
+                &mut File::open(&source.path)?,
+            )?;
+        }
+    }
+    zip_writer.finish()
+}
+
+/// Fails if the archive at `archive_file_path` is split into volumes or is a ZIP
+/// file, as such archives cannot be modified
+fn check_modifiable(archive_file_path: &str) -> Result<()> {
+    let mut archive_file = ArchiveFile::open(archive_file_path)?;
+    if let ArchiveFile::Volumes(_) = archive_file {
+        bail!(
+            "archive {} is split into volumes and cannot be modified",
+            archive_file_path
+        );
+    }
+    if zip::is_zip(&mut archive_file)? {
+        bail!(
+            "archive {} is a ZIP file and cannot be modified",
+            archive_file_path
+        );
+    }
+    Ok(())
+}
+
+/// Adds files to an existing archive in place, writing the new entries over its
+/// central directory and rewriting the directory after them. If `only_newer` is set,
+/// entries already in the archive are replaced by files modified after they were
+/// archived (the data of the replaced entries is left in place but no longer
+/// referenced).
+///
+/// The central directory and footer overwritten are kept in memory and written back
+/// if archiving fails, rather than extending a copy of the archive, which would copy
+/// all of its data for each change. An archive interrupted by a crash while it is
+/// extended is left without a central directory.
+fn extend_archive(
+    paths: &[&str],
+    archive_file_path: &str,
+    options: &ArchiveOptions,
+    only_newer: bool,
+) -> Result<()> {
+    check_modifiable(archive_file_path)?;
+    let sources = collect_archive_sources(paths, options)?;
+    let mut archive_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(archive_file_path)?;
+    let index = read_index(&mut archive_file)?;
+    let directory_offset = match read_footer(&mut archive_file)? {
+        Some((directory_offset, _)) => directory_offset,
+        None => archive_file.seek(SeekFrom::End(0))?,
+    };
+    let mut directory = Vec::new();
+    archive_file.seek(SeekFrom::Start(directory_offset))?;
+    archive_file.read_to_end(&mut directory)?;
+
+    let result = extend_archive_file(
+        archive_file_path,
+        sources,
+        options,
+        only_newer,
+        index,
+        directory_offset,
+    );
+    if let Err(error) = result {
+        let mut archive_file = OpenOptions::new().write(true).open(archive_file_path)?;
+        archive_file.set_len(directory_offset)?;
+        archive_file.seek(SeekFrom::Start(directory_offset))?;
+        archive_file.write_all(&directory)?;
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Adds `sources` to the archive at `archive_file_path`, whose central directory
+/// read in `index` starts at `directory_offset` (see `extend_archive`)
+fn extend_archive_file(
+    archive_file_path: &str,
+    sources: Vec<ArchiveSource>,
+    options: &ArchiveOptions,
+    only_newer: bool,
+    mut index: ArchiveIndex,
+    directory_offset: u64,
+) -> Result<()> {
+    let mut archive_file = OpenOptions::new().write(true).open(archive_file_path)?;
+
+    let mut new_sources = Vec::new();
+    let mut replaced_names = HashSet::new();
+    for source in sources {
+        let Some(entry_index) = index
+            .entries
+            .iter()
+            .position(|entry| entry.name == source.name)
+        else {
+            new_sources.push(source);
+            continue;
+        };
+        if !only_newer {
+            bail!(
+                "entry {} is already in archive {}, use update to replace it",
+                source.name.display(),
+                archive_file_path
+            );
+        }
+        let source_mtime = (source.metadata.mtime(), source.metadata.mtime_nsec() as u32);
+        let is_newer = match &index.entries[entry_index].metadata {
+            Some(metadata) => source_mtime > (metadata.mtime, metadata.mtime_nsec),
+            None => true,
+        };
+        if is_newer {
+            replaced_names.insert(index.entries.remove(entry_index).name);
+            new_sources.push(source);
+        }
+    }
+    // hard links to a replaced file are moved after its new entry, added at the end,
+    // so that they still follow the entry holding their data
+    let (moved_links, entries): (Vec<ArchiveEntry>, Vec<ArchiveEntry>) =
+        index.entries.into_iter().partition(|entry| {
+            entry.kind == EntryKind::HardLink && replaced_names.contains(&entry.link_target)
+        });
+    index.entries = entries;
+
+    archive_file.set_len(directory_offset)?;
+    archive_file.seek(SeekFrom::Start(directory_offset))?;
+    let mut archive_writer =
+        ArchiveWriter::append(BufWriter::new(archive_file), index, directory_offset);
+    if options.solid {
+        archive_writer.enable_solid_blocks(options.method, options.solid_block_size);
+    }
+    if options.chunked {
+        archive_writer.enable_chunking(options.method, options.chunk_size)?;
+    }
+    add_sources(&mut archive_writer, &new_sources, options, &HashMap::new())?;
+    for link in moved_links.iter() {
+        archive_writer.add_stored_entry(link, &mut std::io::empty())?;
     }
+    let num_entries = archive_writer.entries().len() as u64;
+    let mut archive_file = archive_writer.finish()?;
+
+    // keep the header consistent with the central directory
+    archive_file.seek(SeekFrom::Start(0))?;
+    archive_file.write_all(&num_entries.to_be_bytes())?;
+    archive_file.flush()?;
     Ok(())
 }
 
-pub fn unarchive_files(archive_file_path: &str, target_dir_path: &str) -> Result<()> {
+/// Appends files to an existing archive, failing if an entry with the same name exists
+pub fn append_files(
+    paths: &[&str],
+    archive_file_path: &str,
+    options: &ArchiveOptions,
+) -> Result<()> {
+    extend_archive(paths, archive_file_path, options, false)
+}
+
+/// Adds files to an existing archive, replacing entries only by newer files
+pub fn update_files(
+    paths: &[&str],
+    archive_file_path: &str,
+    options: &ArchiveOptions,
+) -> Result<()> {
+    extend_archive(paths, archive_file_path, options, true)
+}
+
+/// Copies the block at `block_index` of the archive read from `archive` unless it
+/// was copied before, returning its index in the archive written by `archive_writer`
+fn copy_block<W: Write>(
+    archive: &mut File,
+    archive_writer: &mut ArchiveWriter<W>,
+    solid_blocks: &[SolidBlock],
+    block_indices: &mut HashMap<u64, u64>,
+    block_index: u64,
+) -> Result<u64> {
+    if let Some(new_block_index) = block_indices.get(&block_index) {
+        return Ok(*new_block_index);
+    }
+    let solid_block = &solid_blocks[block_index as usize];
+    archive.seek(SeekFrom::Start(solid_block.offset))?;
+    let new_block_index = archive_writer.add_stored_solid_block(solid_block, archive)?;
+    block_indices.insert(block_index, new_block_index);
+    Ok(new_block_index)
+}
+
+/// Creates a file with a name of its own next to `file_path`, to write a file that
+/// then replaces it, returning the file and its path
+fn create_temp_file(file_path: &str) -> Result<(File, String)> {
+    loop {
+        let random = RandomState::new().build_hasher().finish();
+        let temp_file_path = format!("{}.{}.{:016x}.tmp", file_path, std::process::id(), random);
+        match File::create_new(&temp_file_path) {
+            std::result::Result::Ok(file) => return Ok((file, temp_file_path)),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Writes `entries` of the archive read from `archive` to a new archive written to
+/// `archive_file`, copying their data (and the solid blocks holding them) without
+/// decompressing it
+fn copy_entries(
+    archive: &mut File,
+    index: &ArchiveIndex,
+    entries: &[ArchiveEntry],
+    archive_file: File,
+) -> Result<()> {
+    let solid_blocks = &index.solid_blocks;
+    let archive_file = BufWriter::new(archive_file);
+    let mut archive_writer = ArchiveWriter::new(archive_file, entries.len() as u64)?;
+    if let Some(previous_archive) = index.previous_archive {
+        archive_writer.set_previous_archive(previous_archive, index.deleted_names.clone());
+    }
+    let mut block_indices: HashMap<u64, u64> = HashMap::new();
+    // offset in the new archive of the data copied for each (solid block, offset)
+    // of the archive, entries sharing data being duplicates of the first one copied
+    let mut copied_data: HashMap<(Option<u64>, u64), u64> = HashMap::new();
+    // chunk lists of the chunked entries copied
+    let mut copied_chunk_lists: HashSet<Vec<u64>> = HashSet::new();
+    for entry in entries.iter() {
+        let mut entry = entry.clone();
+        let data_key = (entry.solid_block, entry.offset);
+        if let Some(chunks) = &entry.chunks {
+            entry.duplicate = !copied_chunk_lists.insert(chunks.clone());
+            let mut new_chunks = Vec::new();
+            for block_index in chunks.iter() {
+                new_chunks.push(copy_block(
+                    archive,
+                    &mut archive_writer,
+                    solid_blocks,
+                    &mut block_indices,
+                    *block_index,
+                )?);
+            }
+            entry.chunks = Some(new_chunks);
+        } else if entry.kind == EntryKind::File && !entry.unchanged {
+            match copied_data.get(&data_key) {
+                Some(offset) => {
+                    entry.duplicate = true;
+                    entry.offset = *offset;
+                }
+                None => entry.duplicate = false,
+            }
+        }
+        if let Some(block_index) = entry.solid_block {
+            entry.solid_block = Some(copy_block(
+                archive,
+                &mut archive_writer,
+                solid_blocks,
+                &mut block_indices,
+                block_index,
+            )?);
+        } else if entry.has_local_record() {
+            archive.seek(SeekFrom::Start(entry.offset))?;
+        }
+        archive_writer.add_stored_entry(&entry, archive)?;
+        if entry.kind == EntryKind::File
+            && entry.chunks.is_none()
+            && !entry.duplicate
+            && !entry.unchanged
+        {
+            let offset = archive_writer.entries().last().unwrap().offset;
+            copied_data.insert(data_key, offset);
+        }
+    }
+    archive_writer.finish()?;
+    Ok(())
+}
+
+/// Removes the entries named in `entry_names` (and the contents of directories named
+/// in it) from the archive, rewriting it without their data. Hard links to a removed
+/// file take over its data.
+pub fn delete_entries<S: AsRef<OsStr>>(archive_file_path: &str, entry_names: &[S]) -> Result<()> {
+    check_modifiable(archive_file_path)?;
     let mut archive_file = File::open(archive_file_path)?;
-    let num_files = read_u64(&mut archive_file)?;
-    for _ in 0..num_files {
-        // read length of file-name
-        let file_name_len = read_u64(&mut archive_file)? as usize;
-        let mut file_name_bytes = vec![0u8; file_name_len];
-        archive_file.read_exact(&mut file_name_bytes)?;
-
-        // read file-size and file-contents
-        let file_size = read_u64(&mut archive_file)? as usize;
-        let mut file_bytes = vec![0u8; file_size];
-        archive_file.read_exact(&mut file_bytes)?;
-
-        // create a new file and write file_bytes
-        let file_name = String::from_utf8(file_name_bytes)?;
-        let mut file = File::create_new(Path::new(target_dir_path).join(file_name))?;
-        file.write(&file_bytes)?;
+    let index = read_index(&mut archive_file)?;
+    for name in entry_names.iter() {
+        if !index
+            .entries
+            .iter()
+            .any(|entry| is_selected(&entry.name, &[name.as_ref()]))
+        {
+            bail!(
+                "entry {} not found in archive {}",
+                name.as_ref().display(),
+                archive_file_path
+            );
+        }
+    }
+    let (deleted_entries, mut kept_entries): (Vec<ArchiveEntry>, Vec<ArchiveEntry>) = index
+        .entries
+        .iter()
+        .cloned()
+        .partition(|entry| is_selected(&entry.name, entry_names));
+
+    for deleted_entry in deleted_entries.iter() {
+        if deleted_entry.kind != EntryKind::File {
+            continue;
+        }
+        let mut new_data_holder: Option<OsString> = None;
+        for entry in kept_entries.iter_mut() {
+            if entry.kind != EntryKind::HardLink || entry.link_target != deleted_entry.name {
+                continue;
+            }
+            match &new_data_holder {
+                Some(data_holder) => entry.link_target = data_holder.clone(),
+                None => {
+                    new_data_holder = Some(entry.name.clone());
+                    *entry = ArchiveEntry {
+                        name: entry.name.clone(),
+                        metadata: entry.metadata.clone(),
+                        ..deleted_entry.clone()
+                    };
+                }
+            }
+        }
+    }
+
+    let (temp_file, temp_file_path) = create_temp_file(archive_file_path)?;
+    let result = copy_entries(&mut archive_file, &index, &kept_entries, temp_file);
+    if result.is_err() {
+        let _ = remove_file(&temp_file_path);
+        return result;
+    }
+    rename(&temp_file_path, archive_file_path)?;
+    Ok(())
+}
+
+pub fn list_entries(archive_file_path: &str) -> Result<Vec<ArchiveEntry>> {
+    let mut archive_file = ArchiveFile::open(archive_file_path)?;
+    Ok(read_index(&mut archive_file)?.entries)
+}
+
+/// Summary of the contents of an archive
+pub struct ArchiveInfo {
+    pub num_entries: u64,
+    pub num_solid_blocks: u64,
+    /// number of unique chunks held by the archive
+    pub num_chunks: u64,
+    /// total size of the archived files
+    pub size: u64,
+    /// size of the archive file (of all its volumes if it is split)
+    pub archive_size: u64,
+    /// number of files holding the archive
+    pub num_volumes: u64,
+    /// number of entries whose data is that of an earlier entry
+    pub num_duplicates: u64,
+    /// size of the data that was not stored again thanks to deduplication,
+    /// of whole entries or of chunks
+    pub deduplicated_size: u64,
+    /// the archive is an increment over a previous archive
+    pub incremental: bool,
+    /// number of files left unchanged since the previous archive
+    pub num_unchanged: u64,
+    /// number of entries deleted since the previous archive
+    pub num_deleted: u64,
+}
+
+pub fn archive_info(archive_file_path: &str) -> Result<ArchiveInfo> {
+    let mut archive_file = ArchiveFile::open(archive_file_path)?;
+    let index = read_index(&mut archive_file)?;
+    let duplicates = index.entries.iter().filter(|entry| entry.duplicate);
+    // size of the chunks referenced by the entries, and of the unique chunks
+    let mut chunked_size = 0;
+    let mut chunk_indices = HashSet::new();
+    for entry in index.entries.iter().filter(|entry| !entry.duplicate) {
+        for block_index in entry.chunks.iter().flatten() {
+            chunked_size += index.solid_blocks[*block_index as usize].size;
+            chunk_indices.insert(*block_index);
+        }
+    }
+    let unique_chunk_size: u64 = chunk_indices
+        .iter()
+        .map(|block_index| index.solid_blocks[*block_index as usize].size)
+        .sum();
+    Ok(ArchiveInfo {
+        num_entries: index.entries.len() as u64,
+        num_solid_blocks: (index.solid_blocks.len() - chunk_indices.len()) as u64,
+        num_chunks: chunk_indices.len() as u64,
+        size: index.entries.iter().map(|entry| entry.size).sum(),
+        archive_size: archive_file.len()?,
+        num_volumes: archive_file.num_volumes() as u64,
+        num_duplicates: duplicates.clone().count() as u64,
+        deduplicated_size: duplicates.map(|entry| entry.data_size()).sum::<u64>() + chunked_size
+            - unique_chunk_size,
+        incremental: index.previous_archive.is_some(),
+        num_unchanged: index.entries.iter().filter(|entry| entry.unchanged).count() as u64,
+        num_deleted: index.deleted_names.len() as u64,
+    })
+}
+
+/// Converts the tar stream read from `tar` into an archive at `archive_file_path`,
+/// compressing the entries as set by `options`. Entries of the stream that are not
+/// files, directories or links (such as devices) are skipped.
+pub fn import_tar<R: Read>(
+    tar: R,
+    archive_file_path: &str,
+    options: &ArchiveOptions,
+) -> Result<()> {
+    let archive_file = BufWriter::new(File::create_new(archive_file_path)?);
+    // the number of entries is only known once the stream is read
+    let mut archive_writer = if options.solid {
+        ArchiveWriter::new_solid(archive_file, 0, options.method, options.solid_block_size)?
+    } else {
+        ArchiveWriter::new(archive_file, 0)?
+    };
+    if options.chunked {
+        archive_writer.enable_chunking(options.method, options.chunk_size)?;
+    }
+    let mut tar_reader = TarReader::new(tar);
+    while let Some(tar_entry) = tar_reader.next_entry()? {
+        match tar_entry.kind {
+            EntryKind::File => {
+                archive_writer.add_entry(
+                    &tar_entry.name,
+                    &tar_entry.metadata,
+                    options.method,
+                    tar_entry.size,
+                    &mut tar_reader,
+                )?;
+            }
+            kind => archive_writer.add_entry_without_data(
+                &tar_entry.name,
+                &tar_entry.metadata,
+                kind,
+                &tar_entry.link_target,
+            )?,
+        }
+    }
+    let num_entries = archive_writer.entries().len() as u64;
+    let mut archive_file = archive_writer.finish()?;
+    archive_file.seek(SeekFrom::Start(0))?;
+    archive_file.write_all(&num_entries.to_be_bytes())?;
+    archive_file.flush()?;
+    Ok(())
+}
+
+/// Writes the entries of the archive at `archive_file_path` to `tar` as a POSIX
+/// tar stream, with pax extended headers for the names, sizes and metadata that
+/// ustar headers cannot hold. Sparse files are written with their holes filled.
+pub fn export_tar<W: Write>(archive_file_path: &str, tar: W) -> Result<()> {
+    let mut archive_reader = ArchiveReader::new(ArchiveFile::open(archive_file_path)?)?;
+    let mut tar_writer = TarWriter::new(tar);
+    for entry_index in 0..archive_reader.entries().len() {
+        let entry = archive_reader.entries()[entry_index].clone();
+        // archives written before metadata was recorded hold regular files
+        let metadata = entry.metadata.clone().unwrap_or(EntryMetadata {
+            mode: 0o644,
+            ..Default::default()
+        });
+        let size = match entry.kind {
+            EntryKind::File => entry.size,
+            _ => 0,
+        };
+        tar_writer.write_entry(&entry.name, entry.kind, &entry.link_target, &metadata, size)?;
+        if entry.kind == EntryKind::File {
+            // the holes of sparse files are written as zeros as they are reached
+            match &entry.data_regions {
+                Some(data_regions) => {
+                    let mut writer = HoleFillingWriter::new(&mut tar_writer, data_regions);
+                    archive_reader.extract_entry(entry_index, &mut writer)?;
+                    writer.finish(entry.size)?;
+                }
+                None => archive_reader.extract_entry(entry_index, &mut tar_writer)?,
+            }
+        }
+    }
+    tar_writer.finish()?;
+    Ok(())
+}
+
+/// Resolves the name of an entry to a path inside `target_dir_path`,
+/// rejecting names that would escape it, either directly or through a symbolic
+/// link in the target directory (such as one extracted from the archive)
+fn entry_path(target_dir_path: &str, name: &OsStr) -> Result<PathBuf> {
+    let mut path = PathBuf::from(target_dir_path);
+    for component in Path::new(name).components() {
+        if path.as_os_str() != target_dir_path && is_symlink(&path) {
+            bail!(
+                "entry {} escapes the target directory through a symbolic link",
+                name.display()
+            );
+        }
+        match component {
+            Component::Normal(component) => path.push(component),
+            Component::CurDir => {}
+            _ => bail!("entry {} escapes the target directory", name.display()),
+        }
+    }
+    Ok(path)
+}
+
+fn is_symlink(path: &Path) -> bool {
+    symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Returns true if `name` is one of `entry_names` or lies in a directory named by them
+fn is_selected<S: AsRef<OsStr>>(name: &OsStr, entry_names: &[S]) -> bool {
+    let name = name.as_bytes();
+    entry_names.is_empty()
+        || entry_names.iter().any(|selected| {
+            let mut selected = selected.as_ref().as_bytes();
+            while let [rest @ .., b'/'] = selected {
+                selected = rest;
+            }
+            name == selected
+                || (name.starts_with(selected) && name.get(selected.len()) == Some(&b'/'))
+        })
+}
+
+/// Opens the archives of an incremental chain, checking that each archive
+/// was made from the one before it
+fn open_chain(archive_file_paths: &[&str]) -> Result<Vec<ArchiveReader<ArchiveFile>>> {
+    let mut archive_readers = Vec::new();
+    let mut last_archive: Option<(&str, [u8; 32])> = None;
+    for archive_file_path in archive_file_paths.iter() {
+        let mut archive_file = ArchiveFile::open(archive_file_path)?;
+        let id = archive_id(&mut archive_file)?;
+        let archive_reader = ArchiveReader::new(archive_file)?;
+        match (archive_reader.previous_archive(), last_archive) {
+            (None, None) => {}
+            (Some(_), None) => bail!(
+                "archive {} is incremental, the archive it was made from must be given first",
+                archive_file_path
+            ),
+            (None, Some((last_path, _))) => bail!(
+                "archive {} is not incremental and cannot follow {}",
+                archive_file_path,
+                last_path
+            ),
+            (Some(previous_id), Some((last_path, last_id))) => {
+                if previous_id != last_id {
+                    bail!(
+                        "archive {} was not made from {}, archives of the chain are missing or out of order",
+                        archive_file_path,
+                        last_path
+                    );
+                }
+            }
+        }
+        last_archive = Some((archive_file_path, id));
+        archive_readers.push(archive_reader);
+    }
+    if archive_readers.is_empty() {
+        bail!("no archive to extract");
+    }
+    Ok(archive_readers)
+}
+
+/// Creates the file at `path` with the data of the entry at `entry_index` of the
+/// last archive of `chain`, reading it from the most recent archive holding it
+/// if the file was unchanged
+fn extract_file_from_chain(
+    chain: &mut [ArchiveReader<ArchiveFile>],
+    entry_index: usize,
+    path: &Path,
+) -> Result<()> {
+    let (archive_reader, previous_readers) = chain.split_last_mut().unwrap();
+    let entry = &archive_reader.entries()[entry_index];
+    if !entry.unchanged {
+        return archive_reader.extract_file(entry_index, path);
+    }
+    let name = entry.name.clone();
+    for previous_reader in previous_readers.iter_mut().rev() {
+        let Some(previous_index) = previous_reader
+            .entries()
+            .iter()
+            .position(|previous| previous.kind == EntryKind::File && previous.name == name)
+        else {
+            break;
+        };
+        if !previous_reader.entries()[previous_index].unchanged {
+            return previous_reader.extract_file(previous_index, path);
+        }
+    }
+    bail!(
+        "the data of entry {} is missing from the previous archives",
+        name.display()
+    )
+}
+
+/// Extracts the entries named in `entry_names` (and the contents of directories
+/// named in it) from the archive, seeking directly to their data.
+/// All entries are extracted if `entry_names` is empty.
+pub fn unarchive_entries<S: AsRef<OsStr>>(
+    archive_file_path: &str,
+    target_dir_path: &str,
+    entry_names: &[S],
+    options: &UnarchiveOptions,
+) -> Result<()> {
+    unarchive_chain(&[archive_file_path], target_dir_path, entry_names, options)
+}
+
+/// Restores the latest state of an incremental chain: `archive_file_paths` lists a
+/// full archive followed by the incremental archives made from it, oldest first.
+/// The entries of the last archive are extracted (see `unarchive_entries`), reading
+/// the data of unchanged files from the earlier archives.
+pub fn unarchive_chain<S: AsRef<OsStr>>(
+    archive_file_paths: &[&str],
+    target_dir_path: &str,
+    entry_names: &[S],
+    options: &UnarchiveOptions,
+) -> Result<()> {
+    let restore_ownership = options.same_owner || metadata::is_root();
+    let mut chain = open_chain(archive_file_paths)?;
+    let archive_file_path = archive_file_paths[archive_file_paths.len() - 1];
+    let entries = chain[chain.len() - 1].entries().to_vec();
+    for name in entry_names.iter() {
+        if !entries
+            .iter()
+            .any(|entry| is_selected(&entry.name, &[name.as_ref()]))
+        {
+            bail!(
+                "entry {} not found in archive {}",
+                name.as_ref().display(),
+                archive_file_path
+            );
+        }
+    }
+
+    // the metadata of directories is restored once their contents are extracted,
+    // as creating files in a directory updates its mtime
+    let mut directories = Vec::new();
+    for (entry_index, entry) in entries.iter().cloned().enumerate() {
+        if !is_selected(&entry.name, entry_names) {
+            continue;
+        }
+        let path = entry_path(target_dir_path, &entry.name)?;
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        match entry.kind {
+            EntryKind::Directory => {
+                // the metadata of the directory would be restored on the target of the link
+                if is_symlink(&path) {
+                    bail!(
+                        "entry {} escapes the target directory through a symbolic link",
+                        entry.name.display()
+                    );
+                }
+                create_dir_all(&path)?;
+                if let Some(metadata) = entry.metadata {
+                    directories.push((path, metadata));
+                }
+                continue;
+            }
+            EntryKind::Symlink => symlink(&entry.link_target, &path)?,
+            EntryKind::HardLink => {
+                let target_path = entry_path(target_dir_path, &entry.link_target)?;
+                if is_selected(&entry.link_target, entry_names) {
+                    hard_link(&target_path, &path)?;
+                    continue;
+                }
+                // the entry holding the data was not extracted, extract it under this name
+                let Some(target_index) = entries
+                    .iter()
+                    .position(|target| target.name == entry.link_target)
+                else {
+                    bail!(
+                        "entry {} links to missing entry {}",
+                        entry.name.display(),
+                        entry.link_target.display()
+                    );
+                };
+                extract_file_from_chain(&mut chain, target_index, &path)?;
+            }
+            EntryKind::File => extract_file_from_chain(&mut chain, entry_index, &path)?,
+        }
+        if let Some(metadata) = entry.metadata {
+            metadata.restore(&path, restore_ownership)?;
+            if options.xattrs {
+                xattr::restore_xattrs(&path, &metadata.xattrs)?;
+            }
+        }
+    }
+    for (path, metadata) in directories.iter().rev() {
+        metadata.restore(path, restore_ownership)?;
+        if options.xattrs {
+            xattr::restore_xattrs(path, &metadata.xattrs)?;
+        }
     }
     Ok(())
 }
 
+pub fn unarchive_files(
+    archive_file_path: &str,
+    target_dir_path: &str,
+    options: &UnarchiveOptions,
+) -> Result<()> {
+    unarchive_entries::<&OsStr>(archive_file_path, target_dir_path, &[], options)
+}
+
 #[cfg(test)]
 mod tests {
 
     use anyhow::Ok;
 
+    use super::append_files;
     use super::archive_files;
+    use super::archive_info;
+    use super::delete_entries;
+    use super::escape_name;
+    use super::export_tar;
+    use super::import_tar;
+    use super::list_entries;
+    use super::unarchive_chain;
+    use super::unarchive_entries;
     use super::unarchive_files;
+    use super::update_files;
+    use super::xattr;
+    use super::xattr::ExtendedAttribute;
+    use super::ArchiveEntry;
+    use super::ArchiveFormat;
+    use super::ArchiveOptions;
+    use super::ArchiveWriter;
+    use super::EntryKind;
+    use super::EntryMetadata;
+    use super::UnarchiveOptions;
+    use crate::codec::Method;
+    use crate::test_util::pseudo_random_bytes;
     use anyhow::Result;
+    use std::ffi::CString;
+    use std::ffi::OsStr;
     use std::fs::create_dir;
+    use std::fs::create_dir_all;
+    use std::fs::hard_link;
+    use std::fs::metadata;
+    use std::fs::read;
+    use std::fs::read_dir;
+    use std::fs::read_link;
+    use std::fs::read_to_string;
     use std::fs::remove_dir;
+    use std::fs::remove_dir_all;
     use std::fs::remove_file;
+    use std::fs::set_permissions;
+    use std::fs::write;
     use std::fs::File;
+    use std::fs::FileTimes;
+    use std::fs::Permissions;
+    use std::io::Seek;
+    use std::io::SeekFrom;
     use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
 
     const FILE_CONTENTS: &str = "Rust is a general-purpose programming language emphasizing performance, type safety, and concurrency. It enforces memory safety, meaning that all references point to valid memory. It does so without a traditional garbage collector; instead, both memory safety errors and data races are prevented by the \"borrow checker\", which tracks the object lifetime of references at compile time.
 
@@ -83,12 +2506,20 @@ mod tests {
     #[test]
     fn test_archiver() -> Result<()> {
         let mut file1 = File::create_new("sample1.txt")?;
-        file1.write(FILE_CONTENTS.as_bytes())?;
+        file1.write_all(FILE_CONTENTS.as_bytes())?;
+        // identical files would be deduplicated
         let mut file2 = File::create_new("sample2.txt")?;
-        file2.write(FILE_CONTENTS.as_bytes())?;
+        file2.write_all(FILE_CONTENTS.to_uppercase().as_bytes())?;
 
         let input_file_paths = ["sample1.txt", "sample2.txt"];
-        archive_files(&input_file_paths, "archive")?;
+        archive_files(
+            &input_file_paths,
+            "archive",
+            &ArchiveOptions {
+                method: Method::Store,
+                ..Default::default()
+            },
+        )?;
 
         assert!(
             get_file_size_bytes("archive")?
@@ -96,7 +2527,7 @@ mod tests {
         );
 
         create_dir("output")?;
-        unarchive_files("archive", "output")?;
+        unarchive_files("archive", "output", &UnarchiveOptions::default())?;
 
         assert!(get_file_size_bytes("output/sample1.txt")? > 0);
         assert!(get_file_size_bytes("output/sample2.txt")? > 0);
@@ -109,4 +2540,1006 @@ mod tests {
         remove_dir("output")?;
         Ok(())
     }
+
+    #[test]
+    fn test_central_directory() -> Result<()> {
+        create_dir("index_test")?;
+        let file_names = ["index_test/a.txt", "index_test/b.txt", "index_test/c.txt"];
+        for (i, file_name) in file_names.iter().enumerate() {
+            let mut file = File::create_new(file_name)?;
+            file.write_all(&FILE_CONTENTS.as_bytes()[..100 * (i + 1)])?;
+        }
+        archive_files(
+            &file_names,
+            "index_test/archive",
+            &ArchiveOptions::default(),
+        )?;
+
+        let entries = list_entries("index_test/archive")?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].name, "b.txt");
+        assert_eq!(entries[1].size, 200);
+
+        create_dir("index_test/output")?;
+        unarchive_entries(
+            "index_test/archive",
+            "index_test/output",
+            &["b.txt"],
+            &UnarchiveOptions::default(),
+        )?;
+        assert!(!Path::new("index_test/output/a.txt").exists());
+        assert_eq!(
+            read_to_string("index_test/output/b.txt")?,
+            FILE_CONTENTS[..200]
+        );
+        assert!(unarchive_entries(
+            "index_test/archive",
+            "index_test/output",
+            &["d.txt"],
+            &UnarchiveOptions::default()
+        )
+        .is_err());
+
+        // a corrupted name length fails instead of allocating that many bytes
+        let mut corrupted = 1u64.to_be_bytes().to_vec();
+        corrupted.extend_from_slice(&u64::MAX.to_be_bytes());
+        corrupted.extend_from_slice(b"a.txt");
+        write("index_test/corrupted", corrupted)?;
+        assert!(list_entries("index_test/corrupted").is_err());
+
+        remove_dir_all("index_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_compression() -> Result<()> {
+        create_dir("compression_test")?;
+        let mut file = File::create_new("compression_test/text.txt")?;
+        file.write_all(FILE_CONTENTS.as_bytes())?;
+        // random-looking bytes that do not compress
+        let noise: Vec<u8> = (0..64u32)
+            .map(|i| (i.wrapping_mul(2654435761u32) >> 24) as u8)
+            .collect();
+        let mut file = File::create_new("compression_test/noise.bin")?;
+        file.write_all(&noise)?;
+
+        let file_names = ["compression_test/text.txt", "compression_test/noise.bin"];
+        for method in [Method::Huffmann, Method::Lzw] {
+            let archive_path = format!("compression_test/{}.archive", method.name());
+            archive_files(
+                &file_names,
+                &archive_path,
+                &ArchiveOptions {
+                    method,
+                    ..Default::default()
+                },
+            )?;
+
+            let entries = list_entries(&archive_path)?;
+            assert_eq!(entries[0].method, method);
+            assert!(entries[0].stored_size < entries[0].size);
+            assert_eq!(entries[1].method, Method::Store);
+            assert_eq!(entries[1].stored_size, entries[1].size);
+
+            let output_dir = format!("compression_test/{}", method.name());
+            create_dir(&output_dir)?;
+            unarchive_files(&archive_path, &output_dir, &UnarchiveOptions::default())?;
+            assert_eq!(
+                read_to_string(format!("{}/text.txt", output_dir))?,
+                FILE_CONTENTS
+            );
+            assert_eq!(read(format!("{}/noise.bin", output_dir))?, noise);
+        }
+
+        remove_dir_all("compression_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_solid_archive() -> Result<()> {
+        create_dir("solid_test")?;
+        let mut file_names = Vec::new();
+        for i in 0..6 {
+            let file_name = format!("solid_test/config{}.json", i);
+            let mut file = File::create_new(&file_name)?;
+            write!(
+                file,
+                "{{ \"id\": {}, \"name\": \"service\", \"replicas\": 3 }}",
+                i
+            )?;
+            file_names.push(file_name);
+        }
+        let file_names: Vec<&str> = file_names.iter().map(|s| s.as_str()).collect();
+
+        archive_files(
+            &file_names,
+            "solid_test/separate",
+            &ArchiveOptions::default(),
+        )?;
+        archive_files(
+            &file_names,
+            "solid_test/solid",
+            &ArchiveOptions {
+                solid: true,
+                ..Default::default()
+            },
+        )?;
+        archive_files(
+            &file_names,
+            "solid_test/blocks",
+            &ArchiveOptions {
+                solid: true,
+                solid_block_size: Some(80),
+                ..Default::default()
+            },
+        )?;
+        assert!(
+            get_file_size_bytes("solid_test/solid")? < get_file_size_bytes("solid_test/separate")?
+        );
+
+        let entries = list_entries("solid_test/solid")?;
+        assert!(entries.iter().all(|entry| entry.solid_block == Some(0)));
+        let entries = list_entries("solid_test/blocks")?;
+        assert_eq!(entries[0].solid_block, Some(0));
+        assert_eq!(entries[5].solid_block, Some(2));
+
+        create_dir("solid_test/output")?;
+        unarchive_entries(
+            "solid_test/blocks",
+            "solid_test/output",
+            &["config4.json"],
+            &UnarchiveOptions::default(),
+        )?;
+        assert_eq!(
+            read_to_string("solid_test/output/config4.json")?,
+            read_to_string("solid_test/config4.json")?
+        );
+
+        remove_dir_all("solid_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_round_trip() -> Result<()> {
+        create_dir("metadata_test")?;
+        let mut file = File::create_new("metadata_test/run.sh")?;
+        file.write_all(b"#!/bin/sh\necho hello\n")?;
+        set_permissions("metadata_test/run.sh", Permissions::from_mode(0o750))?;
+        let mtime = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        file.set_times(FileTimes::new().set_modified(mtime))?;
+        drop(file);
+
+        archive_files(
+            &["metadata_test/run.sh"],
+            "metadata_test/archive",
+            &ArchiveOptions::default(),
+        )?;
+        let entries = list_entries("metadata_test/archive")?;
+        let entry_metadata = entries[0].metadata.clone().unwrap();
+        assert_eq!(entry_metadata.mode, 0o750);
+        assert_eq!(entry_metadata.permissions_string(), "rwxr-x---");
+        assert_eq!(entry_metadata.mtime, 1_600_000_000);
+
+        create_dir("metadata_test/output")?;
+        unarchive_files(
+            "metadata_test/archive",
+            "metadata_test/output",
+            &UnarchiveOptions::default(),
+        )?;
+        let restored = metadata("metadata_test/output/run.sh")?;
+        assert_eq!(restored.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(restored.modified()?, mtime);
+
+        remove_dir_all("metadata_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_links_and_directories() -> Result<()> {
+        create_dir_all("links_test/src/empty")?;
+        let mut file = File::create_new("links_test/src/script.sh")?;
+        file.write_all(b"#!/bin/sh\n")?;
+        symlink("script.sh", "links_test/src/latest")?;
+        hard_link("links_test/src/script.sh", "links_test/src/copy.sh")?;
+
+        archive_files(
+            &["links_test/src"],
+            "links_test/archive",
+            &ArchiveOptions::default(),
+        )?;
+        let entries = list_entries("links_test/archive")?;
+        let kinds: Vec<(&str, EntryKind, &str)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name.to_str().unwrap(),
+                    entry.kind,
+                    entry.link_target.to_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("src", EntryKind::Directory, ""),
+                ("src/copy.sh", EntryKind::File, ""),
+                ("src/empty", EntryKind::Directory, ""),
+                ("src/latest", EntryKind::Symlink, "script.sh"),
+                ("src/script.sh", EntryKind::HardLink, "src/copy.sh"),
+            ]
+        );
+
+        create_dir("links_test/output")?;
+        unarchive_files(
+            "links_test/archive",
+            "links_test/output",
+            &UnarchiveOptions::default(),
+        )?;
+        assert!(metadata("links_test/output/src/empty")?.is_dir());
+        assert_eq!(
+            read_link("links_test/output/src/latest")?.to_str(),
+            Some("script.sh")
+        );
+        assert_eq!(
+            metadata("links_test/output/src/script.sh")?.ino(),
+            metadata("links_test/output/src/copy.sh")?.ino()
+        );
+
+        // extracting a hard link alone extracts the data it refers to
+        create_dir("links_test/single")?;
+        unarchive_entries(
+            "links_test/archive",
+            "links_test/single",
+            &["src/script.sh"],
+            &UnarchiveOptions::default(),
+        )?;
+        assert_eq!(read("links_test/single/src/script.sh")?, b"#!/bin/sh\n");
+
+        archive_files(
+            &["links_test/src"],
+            "links_test/dereferenced",
+            &ArchiveOptions {
+                dereference: true,
+                ..Default::default()
+            },
+        )?;
+        let entries = list_entries("links_test/dereferenced")?;
+        assert!(entries.iter().all(|entry| entry.kind != EntryKind::Symlink));
+
+        remove_dir_all("links_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_symlink_traversal() -> Result<()> {
+        create_dir_all("traversal_test/outside")?;
+        write("traversal_test/outside/secret", "secret")?;
+        set_permissions("traversal_test/outside", Permissions::from_mode(0o755))?;
+        let file_metadata = EntryMetadata {
+            mode: 0o644,
+            ..Default::default()
+        };
+        let directory_metadata = EntryMetadata {
+            mode: 0o700,
+            ..Default::default()
+        };
+        // each archive holds a link out of the target directory, then an entry through it
+        let hostile_entries: [(&str, EntryKind, &str); 3] = [
+            ("link/passwd", EntryKind::File, ""),
+            ("link", EntryKind::Directory, ""),
+            ("hard", EntryKind::HardLink, "link/secret"),
+        ];
+        for (name, kind, link_target) in hostile_entries {
+            let mut archive_writer =
+                ArchiveWriter::new(File::create_new("traversal_test/archive")?, 2)?;
+            archive_writer.add_entry_without_data(
+                OsStr::new("link"),
+                &file_metadata,
+                EntryKind::Symlink,
+                OsStr::new("../outside"),
+            )?;
+            match kind {
+                EntryKind::File => archive_writer.add_entry(
+                    OsStr::new(name),
+                    &file_metadata,
+                    Method::Store,
+                    4,
+                    &mut b"root".as_slice(),
+                )?,
+                kind => archive_writer.add_entry_without_data(
+                    OsStr::new(name),
+                    &directory_metadata,
+                    kind,
+                    OsStr::new(link_target),
+                )?,
+            }
+            archive_writer.finish()?;
+            assert!(unarchive_files(
+                "traversal_test/archive",
+                "traversal_test/output",
+                &UnarchiveOptions::default()
+            )
+            .is_err());
+            remove_file("traversal_test/archive")?;
+            remove_dir_all("traversal_test/output")?;
+        }
+        assert!(!Path::new("traversal_test/outside/passwd").exists());
+        assert_eq!(
+            metadata("traversal_test/outside")?.permissions().mode() & 0o777,
+            0o755
+        );
+        assert_eq!(metadata("traversal_test/outside/secret")?.nlink(), 1);
+
+        remove_dir_all("traversal_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_update_delete() -> Result<()> {
+        create_dir("modify_test")?;
+        let mut file = File::create_new("modify_test/a.txt")?;
+        file.write_all(b"first version")?;
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(1000)))?;
+        let mut file = File::create_new("modify_test/b.txt")?;
+        file.write_all(FILE_CONTENTS.as_bytes())?;
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(1000)))?;
+        hard_link("modify_test/b.txt", "modify_test/c.txt")?;
+
+        let options = ArchiveOptions {
+            solid: true,
+            ..Default::default()
+        };
+        archive_files(&["modify_test/a.txt"], "modify_test/archive", &options)?;
+        append_files(
+            &["modify_test/b.txt", "modify_test/c.txt"],
+            "modify_test/archive",
+            &options,
+        )?;
+        assert!(append_files(&["modify_test/a.txt"], "modify_test/archive", &options).is_err());
+
+        // a.txt is only replaced once it is newer than the archived entry
+        update_files(&["modify_test/a.txt"], "modify_test/archive", &options)?;
+        assert_eq!(list_entries("modify_test/archive")?[0].name, "a.txt");
+        let mut file = File::create("modify_test/a.txt")?;
+        file.write_all(b"second version")?;
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(2000)))?;
+        update_files(&["modify_test/a.txt"], "modify_test/archive", &options)?;
+
+        // the hard link to a replaced file is moved after the new entry of the file
+        let mut file = File::create("modify_test/b.txt")?;
+        file.write_all(b"contents of b and c")?;
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(3000)))?;
+        update_files(&["modify_test/b.txt"], "modify_test/archive", &options)?;
+        // an append failing once the central directory is overwritten restores it
+        let archive_data = read("modify_test/archive")?;
+        let invalid_options = ArchiveOptions {
+            chunked: true,
+            chunk_size: Some(16),
+            ..Default::default()
+        };
+        write("modify_test/d.txt", "d")?;
+        assert!(append_files(
+            &["modify_test/d.txt"],
+            "modify_test/archive",
+            &invalid_options
+        )
+        .is_err());
+        assert_eq!(read("modify_test/archive")?, archive_data);
+        let entry_names = |entries: &[ArchiveEntry]| -> Vec<String> {
+            entries
+                .iter()
+                .map(|entry| entry.name.to_str().unwrap().to_string())
+                .collect()
+        };
+        let entries = list_entries("modify_test/archive")?;
+        assert_eq!(entry_names(&entries), ["a.txt", "b.txt", "c.txt"]);
+        create_dir("modify_test/linked")?;
+        unarchive_files(
+            "modify_test/archive",
+            "modify_test/linked",
+            &UnarchiveOptions::default(),
+        )?;
+        assert_eq!(
+            read_to_string("modify_test/linked/c.txt")?,
+            "contents of b and c"
+        );
+
+        // the archive is rewritten to a file of its own, leaving other files alone
+        write("modify_test/archive.tmp", "not a temporary file")?;
+        delete_entries("modify_test/archive", &["b.txt"])?;
+        assert_eq!(
+            read_to_string("modify_test/archive.tmp")?,
+            "not a temporary file"
+        );
+        assert_eq!(read_dir("modify_test")?.count(), 7);
+        let entries = list_entries("modify_test/archive")?;
+        assert_eq!(entry_names(&entries), ["a.txt", "c.txt"]);
+        assert_eq!(entries[1].kind, EntryKind::File);
+        let num_entries = u64::from_be_bytes(read("modify_test/archive")?[..8].try_into()?);
+        assert_eq!(num_entries, 2);
+
+        create_dir("modify_test/output")?;
+        unarchive_files(
+            "modify_test/archive",
+            "modify_test/output",
+            &UnarchiveOptions::default(),
+        )?;
+        assert_eq!(
+            read_to_string("modify_test/output/a.txt")?,
+            "second version"
+        );
+        assert_eq!(
+            read_to_string("modify_test/output/c.txt")?,
+            "contents of b and c"
+        );
+
+        remove_dir_all("modify_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_utf8_names() -> Result<()> {
+        // 'café.txt' encoded in Latin-1
+        let latin1_name = OsStr::from_bytes(b"caf\xe9.txt");
+        create_dir("non_utf8_test")?;
+        let mut file = File::create_new(Path::new("non_utf8_test").join(latin1_name))?;
+        file.write_all(b"latin-1")?;
+        symlink(latin1_name, "non_utf8_test/link")?;
+
+        archive_files(
+            &["non_utf8_test"],
+            "non_utf8_test.archive",
+            &ArchiveOptions::default(),
+        )?;
+        let entries = list_entries("non_utf8_test.archive")?;
+        assert_eq!(entries[1].name.as_bytes(), b"non_utf8_test/caf\xe9.txt");
+        assert!(!entries[1].name_is_utf8());
+        assert!(entries[2].name_is_utf8());
+        assert_eq!(entries[1].display_name(), "non_utf8_test/caf\\xe9.txt");
+        assert_eq!(escape_name(&entries[2].link_target), "caf\\xe9.txt");
+        assert_eq!(escape_name(OsStr::from_bytes(b"a\\b\xff")), "a\\\\b\\xff");
+        assert_eq!(escape_name(OsStr::new("a\\b")), "a\\b");
+
+        create_dir("non_utf8_test/output")?;
+        unarchive_entries(
+            "non_utf8_test.archive",
+            "non_utf8_test/output",
+            &[
+                OsStr::from_bytes(b"non_utf8_test/caf\xe9.txt"),
+                OsStr::new("non_utf8_test/link"),
+            ],
+            &UnarchiveOptions::default(),
+        )?;
+        let output_dir = Path::new("non_utf8_test/output/non_utf8_test");
+        assert_eq!(read(output_dir.join(latin1_name))?, b"latin-1");
+        assert_eq!(read_link(output_dir.join("link"))?, Path::new(latin1_name));
+
+        remove_dir_all("non_utf8_test")?;
+        remove_file("non_utf8_test.archive")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_filters() -> Result<()> {
+        create_dir_all("filter_test/project/src/sub")?;
+        create_dir_all("filter_test/project/target/debug")?;
+        create_dir_all("filter_test/project/.git")?;
+        write("filter_test/project/.git/HEAD", "ref: refs/heads/main")?;
+        write("filter_test/project/target/debug/app", "binary")?;
+        write("filter_test/project/.gitignore", "*.log\n!keep.log\n")?;
+        write("filter_test/project/build.log", "log")?;
+        write("filter_test/project/keep.log", "log")?;
+        write("filter_test/project/src/main.rs", "fn main() {}")?;
+        write("filter_test/project/src/sub/.gitignore", "generated.rs\n")?;
+        write("filter_test/project/src/sub/generated.rs", "")?;
+        write("filter_test/project/src/sub/lib.rs", "")?;
+        write("filter_test/excludes", "# version control\n.git/\n\n")?;
+
+        let entry_names = |archive_file_path: &str| -> Result<Vec<String>> {
+            Ok(list_entries(archive_file_path)?
+                .iter()
+                .map(|entry| entry.display_name())
+                .collect())
+        };
+        archive_files(
+            &["filter_test/project"],
+            "filter_test/excluded.archive",
+            &ArchiveOptions {
+                exclude: vec!["target".to_string()],
+                exclude_from: vec!["filter_test/excludes".into()],
+                respect_gitignore: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            entry_names("filter_test/excluded.archive")?,
+            [
+                "project",
+                "project/.gitignore",
+                "project/keep.log",
+                "project/src",
+                "project/src/main.rs",
+                "project/src/sub",
+                "project/src/sub/.gitignore",
+                "project/src/sub/lib.rs",
+            ]
+        );
+
+        archive_files(
+            &["filter_test/project"],
+            "filter_test/included.archive",
+            &ArchiveOptions {
+                include: vec!["project/src/**/*.rs".to_string()],
+                exclude: vec!["/project/.git".to_string(), "target/".to_string()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            entry_names("filter_test/included.archive")?,
+            [
+                "project",
+                "project/src",
+                "project/src/main.rs",
+                "project/src/sub",
+                "project/src/sub/generated.rs",
+                "project/src/sub/lib.rs",
+            ]
+        );
+
+        // a trailing '/' restricts a pattern to directories
+        write("filter_test/project/src/target", "not a build directory")?;
+        archive_files(
+            &["filter_test/project/src"],
+            "filter_test/directories.archive",
+            &ArchiveOptions {
+                exclude: vec!["target/".to_string(), "sub/".to_string()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            entry_names("filter_test/directories.archive")?,
+            ["src", "src/main.rs", "src/target"]
+        );
+
+        remove_dir_all("filter_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_files() -> Result<()> {
+        const MIB: u64 = 1 << 20;
+        create_dir_all("sparse_test/src")?;
+        let mut file = File::create_new("sparse_test/src/disk.img")?;
+        file.set_len(4 * MIB)?;
+        file.seek(SeekFrom::Start(MIB))?;
+        file.write_all(b"first data region")?;
+        file.seek(SeekFrom::Start(3 * MIB))?;
+        file.write_all(FILE_CONTENTS.as_bytes())?;
+        drop(file);
+        File::create_new("sparse_test/src/holes.img")?.set_len(MIB)?;
+
+        for solid in [false, true] {
+            let archive_path = format!("sparse_test/{}.archive", solid);
+            archive_files(
+                &["sparse_test/src"],
+                &archive_path,
+                &ArchiveOptions {
+                    solid,
+                    ..Default::default()
+                },
+            )?;
+            let entries = list_entries(&archive_path)?;
+            assert_eq!(entries[1].size, 4 * MIB);
+            let data_regions = entries[1].data_regions.as_ref().unwrap();
+            assert!(data_regions.iter().any(|region| region.offset == MIB));
+            assert!(entries[1].data_size() < MIB);
+            assert_eq!(entries[2].data_regions.as_deref(), Some(&[][..]));
+
+            let output_dir = format!("sparse_test/output_{}", solid);
+            create_dir(&output_dir)?;
+            unarchive_files(&archive_path, &output_dir, &UnarchiveOptions::default())?;
+            for file_name in ["disk.img", "holes.img"] {
+                let original_path = format!("sparse_test/src/{}", file_name);
+                let restored_path = format!("{}/src/{}", output_dir, file_name);
+                assert_eq!(read(&restored_path)?, read(&original_path)?);
+                // the holes are recreated rather than written as zeros
+                assert!(metadata(&restored_path)?.blocks() <= metadata(&original_path)?.blocks());
+            }
+        }
+
+        // exporting to tar writes the holes as zeros
+        let mut tar = Vec::new();
+        export_tar("sparse_test/false.archive", &mut tar)?;
+        assert!(tar.len() as u64 > 5 * MIB);
+        import_tar(
+            tar.as_slice(),
+            "sparse_test/tar.archive",
+            &ArchiveOptions::default(),
+        )?;
+        create_dir("sparse_test/output_tar")?;
+        unarchive_files(
+            "sparse_test/tar.archive",
+            "sparse_test/output_tar",
+            &UnarchiveOptions::default(),
+        )?;
+        for file_name in ["disk.img", "holes.img"] {
+            assert_eq!(
+                read(format!("sparse_test/output_tar/src/{}", file_name))?,
+                read(format!("sparse_test/src/{}", file_name))?
+            );
+        }
+
+        remove_dir_all("sparse_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_attributes() -> Result<()> {
+        create_dir_all("xattr_test/src")?;
+        write("xattr_test/src/app", "#!/bin/sh\n")?;
+        symlink("app", "xattr_test/src/link")?;
+        let path = CString::new("xattr_test/src/app")?;
+        let name = CString::new("user.origin")?;
+        let status = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                b"build-server".as_ptr() as *const libc::c_void,
+                12,
+                0,
+            )
+        };
+        assert_eq!(status, 0);
+        let origin = ExtendedAttribute {
+            name: "user.origin".into(),
+            value: b"build-server".to_vec(),
+        };
+
+        archive_files(
+            &["xattr_test/src"],
+            "xattr_test/archive",
+            &ArchiveOptions {
+                xattrs: true,
+                ..Default::default()
+            },
+        )?;
+        let entries = list_entries("xattr_test/archive")?;
+        let entry_xattrs = &entries[1].metadata.as_ref().unwrap().xattrs;
+        assert!(entry_xattrs.contains(&origin));
+
+        for restore in [false, true] {
+            let output_dir = format!("xattr_test/output_{}", restore);
+            create_dir(&output_dir)?;
+            unarchive_files(
+                "xattr_test/archive",
+                &output_dir,
+                &UnarchiveOptions {
+                    xattrs: restore,
+                    ..Default::default()
+                },
+            )?;
+            let restored =
+                xattr::read_xattrs(Path::new(&format!("{}/src/app", output_dir)), false)?;
+            assert_eq!(restored.contains(&origin), restore);
+        }
+
+        // user attributes are not allowed on symbolic links, they are skipped
+        xattr::restore_xattrs(
+            Path::new("xattr_test/src/link"),
+            std::slice::from_ref(&origin),
+        )?;
+        assert!(xattr::read_xattrs(Path::new("xattr_test/src/link"), false)?.is_empty());
+
+        // the system namespace only holds the attributes the kernel knows, such as
+        // ACLs: an unknown one is skipped along with its namespace, not the others
+        write("xattr_test/restored", "")?;
+        let unknown_system_xattrs =
+            ["system.unknown", "system.posix_acl_access"].map(|name| ExtendedAttribute {
+                name: name.into(),
+                value: b"value".to_vec(),
+            });
+        xattr::restore_xattrs(
+            Path::new("xattr_test/restored"),
+            &[
+                unknown_system_xattrs[0].clone(),
+                origin.clone(),
+                unknown_system_xattrs[1].clone(),
+            ],
+        )?;
+        assert_eq!(
+            xattr::read_xattrs(Path::new("xattr_test/restored"), false)?,
+            [origin]
+        );
+
+        remove_dir_all("xattr_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deduplication() -> Result<()> {
+        create_dir_all("dedup_test/src/vendor")?;
+        write("dedup_test/src/a.txt", FILE_CONTENTS)?;
+        write("dedup_test/src/b.txt", "unique contents")?;
+        write("dedup_test/src/vendor/a.txt", FILE_CONTENTS)?;
+        write("dedup_test/src/vendor/c.txt", FILE_CONTENTS)?;
+
+        for solid in [false, true] {
+            let archive_path = format!("dedup_test/{}.archive", solid);
+            archive_files(
+                &["dedup_test/src"],
+                &archive_path,
+                &ArchiveOptions {
+                    solid,
+                    ..Default::default()
+                },
+            )?;
+            let entries = list_entries(&archive_path)?;
+            let duplicates: Vec<String> = entries
+                .iter()
+                .filter(|entry| entry.duplicate)
+                .map(|entry| entry.display_name())
+                .collect();
+            assert_eq!(duplicates, ["src/vendor/a.txt", "src/vendor/c.txt"]);
+            assert_eq!(entries[1].offset, entries[5].offset);
+
+            let info = archive_info(&archive_path)?;
+            assert_eq!(info.num_duplicates, 2);
+            assert_eq!(info.deduplicated_size, 2 * FILE_CONTENTS.len() as u64);
+
+            // the first duplicate takes over the data once the original is deleted
+            delete_entries(&archive_path, &["src/a.txt"])?;
+            let entries = list_entries(&archive_path)?;
+            let duplicates = entries.iter().filter(|entry| entry.duplicate).count();
+            assert_eq!(duplicates, 1);
+
+            let output_dir = format!("dedup_test/output_{}", solid);
+            create_dir(&output_dir)?;
+            unarchive_files(&archive_path, &output_dir, &UnarchiveOptions::default())?;
+            for file_name in ["b.txt", "vendor/a.txt", "vendor/c.txt"] {
+                assert_eq!(
+                    read(format!("{}/src/{}", output_dir, file_name))?,
+                    read(format!("dedup_test/src/{}", file_name))?
+                );
+            }
+        }
+
+        remove_dir_all("dedup_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunked_archive() -> Result<()> {
+        create_dir_all("chunked_test/src")?;
+        let records: Vec<String> = (0..20000u64)
+            .map(|i| format!("record {}: {}\n", i, i * 7919 % 100_003))
+            .collect();
+        write("chunked_test/src/dump1.sql", records.concat())?;
+        let mut edited_records = records.clone();
+        edited_records[10000] = "record 10000: edited\n".to_string();
+        edited_records.push("record 20000: appended\n".to_string());
+        write("chunked_test/src/dump2.sql", edited_records.concat())?;
+        write("chunked_test/src/dump3.sql", records.concat())?;
+
+        let options = ArchiveOptions {
+            chunked: true,
+            chunk_size: Some(4096),
+            ..Default::default()
+        };
+        archive_files(&["chunked_test/src"], "chunked_test/archive", &options)?;
+        let entries = list_entries("chunked_test/archive")?;
+        let chunks1 = entries[1].chunks.as_ref().unwrap();
+        let chunks2 = entries[2].chunks.as_ref().unwrap();
+        let num_shared_chunks = chunks2
+            .iter()
+            .filter(|block_index| chunks1.contains(block_index))
+            .count();
+        assert!(num_shared_chunks >= chunks2.len() - 3);
+        assert!(entries[3].duplicate);
+
+        let info = archive_info("chunked_test/archive")?;
+        let dump_size = records.concat().len() as u64;
+        assert_eq!(info.num_solid_blocks, 0);
+        assert!(info.num_chunks < (chunks1.len() + chunks2.len()) as u64);
+        assert!(info.deduplicated_size > 2 * dump_size - 3 * 4 * 4096);
+
+        // the chunks of a deleted entry that are used by other entries are kept
+        delete_entries("chunked_test/archive", &["src/dump1.sql"])?;
+        assert!(!list_entries("chunked_test/archive")?[2].duplicate);
+        create_dir("chunked_test/output")?;
+        unarchive_files(
+            "chunked_test/archive",
+            "chunked_test/output",
+            &UnarchiveOptions::default(),
+        )?;
+        for file_name in ["dump2.sql", "dump3.sql"] {
+            assert_eq!(
+                read(format!("chunked_test/output/src/{}", file_name))?,
+                read(format!("chunked_test/src/{}", file_name))?
+            );
+        }
+
+        remove_dir_all("chunked_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_files() -> Result<()> {
+        create_dir_all("large_test/src")?;
+        // larger than the data read at once, which is split into pieces
+        // (stored, as compressing this much data takes long in debug builds)
+        let records: Vec<String> = (0..1_000_000u64)
+            .map(|i| format!("record {}: {}\n", i, i * 7919 % 100_003))
+            .collect();
+        let data = records.concat();
+        assert!(data.len() > 1 << 24);
+        write("large_test/src/large.txt", &data)?;
+        write("large_test/src/copy.txt", &data)?;
+
+        for format in [ArchiveFormat::Native, ArchiveFormat::Zip] {
+            let options = ArchiveOptions {
+                format,
+                method: Method::Store,
+                ..Default::default()
+            };
+            archive_files(&["large_test/src"], "large_test/archive", &options)?;
+            let entries = list_entries("large_test/archive")?;
+            let entry = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap();
+            if format == ArchiveFormat::Native {
+                assert_eq!(entry("src/large.txt").chunks.as_ref().unwrap().len(), 2);
+                assert!(entry("src/large.txt").duplicate || entry("src/copy.txt").duplicate);
+            }
+            unarchive_files(
+                "large_test/archive",
+                "large_test/output",
+                &UnarchiveOptions::default(),
+            )?;
+            for file_name in ["large.txt", "copy.txt"] {
+                assert!(read(format!("large_test/output/src/{}", file_name))? == data.as_bytes());
+            }
+            remove_file("large_test/archive")?;
+            remove_dir_all("large_test/output")?;
+        }
+
+        remove_dir_all("large_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_archive() -> Result<()> {
+        create_dir_all("incremental_test/src")?;
+        write("incremental_test/src/a.txt", FILE_CONTENTS)?;
+        write("incremental_test/src/b.txt", "first version")?;
+        write("incremental_test/src/c.txt", "deleted later")?;
+        archive_files(
+            &["incremental_test/src"],
+            "incremental_test/full",
+            &ArchiveOptions::default(),
+        )?;
+
+        // a.txt is touched without being modified
+        File::options()
+            .write(true)
+            .open("incremental_test/src/a.txt")?
+            .set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(1)))?;
+        write("incremental_test/src/b.txt", "second version")?;
+        remove_file("incremental_test/src/c.txt")?;
+        write("incremental_test/src/d.txt", "added")?;
+        archive_files(
+            &["incremental_test/src"],
+            "incremental_test/incremental1",
+            &ArchiveOptions {
+                incremental_from: Some("incremental_test/full".into()),
+                ..Default::default()
+            },
+        )?;
+        let entries = list_entries("incremental_test/incremental1")?;
+        let unchanged: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.unchanged)
+            .map(|entry| entry.display_name())
+            .collect();
+        assert_eq!(unchanged, ["src/a.txt"]);
+        let info = archive_info("incremental_test/incremental1")?;
+        assert!(info.incremental);
+        assert_eq!(info.num_deleted, 1);
+
+        write("incremental_test/src/d.txt", "modified")?;
+        archive_files(
+            &["incremental_test/src"],
+            "incremental_test/incremental2",
+            &ArchiveOptions {
+                incremental_from: Some("incremental_test/incremental1".into()),
+                ..Default::default()
+            },
+        )?;
+        let info = archive_info("incremental_test/incremental2")?;
+        assert_eq!((info.num_unchanged, info.num_deleted), (2, 0));
+
+        create_dir("incremental_test/output")?;
+        unarchive_chain::<&OsStr>(
+            &[
+                "incremental_test/full",
+                "incremental_test/incremental1",
+                "incremental_test/incremental2",
+            ],
+            "incremental_test/output",
+            &[],
+            &UnarchiveOptions::default(),
+        )?;
+        for file_name in ["a.txt", "b.txt", "d.txt"] {
+            assert_eq!(
+                read(format!("incremental_test/output/src/{}", file_name))?,
+                read(format!("incremental_test/src/{}", file_name))?
+            );
+        }
+        assert!(!Path::new("incremental_test/output/src/c.txt").exists());
+
+        // archives missing from the chain or out of order are detected
+        for chain in [
+            &["incremental_test/full", "incremental_test/incremental2"][..],
+            &["incremental_test/incremental1", "incremental_test/full"][..],
+            &["incremental_test/incremental1"][..],
+        ] {
+            assert!(unarchive_chain::<&OsStr>(
+                chain,
+                "incremental_test/output2",
+                &[],
+                &UnarchiveOptions::default()
+            )
+            .is_err());
+        }
+
+        remove_dir_all("incremental_test")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_volume_archive() -> Result<()> {
+        create_dir_all("volume_test/src")?;
+        let noise = pseudo_random_bytes(7, 5000);
+        write("volume_test/src/noise.bin", &noise)?;
+        write("volume_test/src/text.txt", FILE_CONTENTS)?;
+        archive_files(
+            &["volume_test/src"],
+            "volume_test/archive",
+            &ArchiveOptions {
+                volume_size: Some(1024),
+                ..Default::default()
+            },
+        )?;
+        assert!(!Path::new("volume_test/archive").exists());
+        assert_eq!(get_file_size_bytes("volume_test/archive.001")?, 1024);
+        let info = archive_info("volume_test/archive")?;
+        assert!(info.num_volumes >= 5);
+        assert!(Path::new(&format!("volume_test/archive.{:03}", info.num_volumes)).exists());
+
+        // the archive can be named by its base path or by its first volume
+        for (archive_path, output_dir) in [
+            ("volume_test/archive", "volume_test/output1"),
+            ("volume_test/archive.001", "volume_test/output2"),
+        ] {
+            create_dir(output_dir)?;
+            unarchive_files(archive_path, output_dir, &UnarchiveOptions::default())?;
+            assert_eq!(read(format!("{}/src/noise.bin", output_dir))?, noise);
+            assert_eq!(
+                read_to_string(format!("{}/src/text.txt", output_dir))?,
+                FILE_CONTENTS
+            );
+        }
+        assert!(delete_entries("volume_test/archive", &["src/text.txt"]).is_err());
+
+        // swapped and missing volumes are reported
+        std::fs::rename("volume_test/archive.002", "volume_test/volume")?;
+        std::fs::rename("volume_test/archive.003", "volume_test/archive.002")?;
+        std::fs::rename("volume_test/volume", "volume_test/archive.003")?;
+        let error = list_entries("volume_test/archive").err().unwrap();
+        assert!(error.to_string().contains("out of order"));
+        remove_file("volume_test/archive.002")?;
+        std::fs::rename("volume_test/archive.003", "volume_test/archive.002")?;
+        let error = list_entries("volume_test/archive").err().unwrap();
+        assert!(error.to_string().contains("missing"));
+
+        remove_dir_all("volume_test")?;
+        Ok(())
+    }
 }
@@ -0,0 +1,136 @@
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+
+/// Average size of the chunks if not set
+pub const DEFAULT_AVERAGE_SIZE: u64 = 1 << 16;
+
+/// Smallest and largest supported average chunk sizes
+const MIN_AVERAGE_SIZE: usize = 256;
+const MAX_AVERAGE_SIZE: usize = 1 << 26;
+
+/// Random values added to the rolling hash for each byte, generated with splitmix64
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Splits data into content-defined chunks with FastCDC: a boundary is placed where
+/// a gear rolling hash of the last 64 bytes matches a mask, so that chunk boundaries
+/// follow the content and realign after an insertion or deletion. The mask is harder
+/// to match before the average size and easier after it (normalized chunking),
+/// concentrating the chunk sizes around the average.
+pub struct Chunker {
+    min_size: usize,
+    average_size: usize,
+    max_size: usize,
+    /// mask used before the average size, with more bits set
+    mask_small: u64,
+    /// mask used after the average size, with fewer bits set
+    mask_large: u64,
+}
+
+impl Chunker {
+    /// Creates a chunker producing chunks of `average_size` bytes on average
+    /// (rounded down to a power of two), and between a quarter and four times that
+    pub fn new(average_size: u64) -> Result<Self> {
+        if !(MIN_AVERAGE_SIZE as u64..=MAX_AVERAGE_SIZE as u64).contains(&average_size) {
+            bail!(
+                "chunk size must be between {} and {} bytes",
+                MIN_AVERAGE_SIZE,
+                MAX_AVERAGE_SIZE
+            );
+        }
+        let bits = average_size.ilog2();
+        let average_size = 1usize << bits;
+        Ok(Chunker {
+            min_size: average_size / 4,
+            average_size,
+            max_size: average_size * 4,
+            // the high bits of the hash depend on the most bytes
+            mask_small: !0u64 << (64 - (bits + 1)),
+            mask_large: !0u64 << (64 - (bits - 1)),
+        })
+    }
+
+    /// Largest size of a chunk, the number of bytes a chunk boundary depends on
+    pub fn max_size(&self) -> u64 {
+        self.max_size as u64
+    }
+
+    /// Returns the length of the chunk starting at the beginning of `data`
+    pub fn next_chunk_len(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal_end = self.average_size.min(end);
+        let mut hash: u64 = 0;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal_end {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Chunker;
+    use crate::test_util::pseudo_random_bytes;
+    use anyhow::Ok;
+    use anyhow::Result;
+
+    fn split_chunks<'a>(chunker: &Chunker, mut data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let (chunk, rest) = data.split_at(chunker.next_chunk_len(data));
+            chunks.push(chunk);
+            data = rest;
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_content_defined_chunks() -> Result<()> {
+        let data = pseudo_random_bytes(1, 1 << 20);
+        let chunker = Chunker::new(8192)?;
+        let chunks = split_chunks(&chunker, &data);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| (2048..=32768).contains(&chunk.len())));
+        assert!(chunks.len() > 64 && chunks.len() < 512);
+
+        // inserting bytes only changes the chunks around the insertion
+        let mut edited_data = data.clone();
+        edited_data.splice(500_000..500_000, b"inserted bytes".iter().copied());
+        let edited_chunks = split_chunks(&chunker, &edited_data);
+        let num_shared_chunks = edited_chunks
+            .iter()
+            .filter(|chunk| chunks.contains(chunk))
+            .count();
+        assert!(num_shared_chunks >= chunks.len() - 3);
+
+        assert!(Chunker::new(16).is_err());
+        Ok(())
+    }
+}
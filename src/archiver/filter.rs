@@ -0,0 +1,156 @@
+use super::ArchiveOptions;
+use anyhow::Ok;
+use anyhow::Result;
+use globset::Glob;
+use globset::GlobBuilder;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use std::ffi::OsStr;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Glob patterns matched against the names of entries. Patterns containing a '/'
+/// are matched against the whole name, other patterns against its last component,
+/// so that `target` matches every file or directory named `target`. As in
+/// `.gitignore` files, patterns ending with '/' (such as `target/`) only match
+/// directories.
+struct Patterns {
+    names: GlobSet,
+    file_names: GlobSet,
+    /// whether each pattern of `names` only matches directories
+    directory_only_names: Vec<bool>,
+    /// whether each pattern of `file_names` only matches directories
+    directory_only_file_names: Vec<bool>,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut file_names = GlobSetBuilder::new();
+        let mut directory_only_names = Vec::new();
+        let mut directory_only_file_names = Vec::new();
+        for pattern in patterns.iter() {
+            let is_directory_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            if pattern.contains('/') {
+                // names are relative to the root of the archive, so '/x' matches 'x'
+                let glob = GlobBuilder::new(pattern.trim_start_matches('/'))
+                    .literal_separator(true)
+                    .build()?;
+                names.add(glob);
+                directory_only_names.push(is_directory_only);
+            } else {
+                file_names.add(Glob::new(pattern)?);
+                directory_only_file_names.push(is_directory_only);
+            }
+        }
+        Ok(Patterns {
+            names: names.build()?,
+            file_names: file_names.build()?,
+            directory_only_names,
+            directory_only_file_names,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.file_names.is_empty()
+    }
+
+    fn is_match(&self, name: &OsStr, is_dir: bool) -> bool {
+        let name = Path::new(name);
+        let applies = |directory_only: &[bool], index: usize| is_dir || !directory_only[index];
+        self.names
+            .matches(name)
+            .into_iter()
+            .any(|index| applies(&self.directory_only_names, index))
+            || name.file_name().is_some_and(|file_name| {
+                self.file_names
+                    .matches(file_name)
+                    .into_iter()
+                    .any(|index| applies(&self.directory_only_file_names, index))
+            })
+    }
+}
+
+/// Reads patterns from a file holding one pattern per line,
+/// skipping empty lines and comments starting with '#'
+fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    Ok(read_to_string(path)?
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Decides which of the files found while walking the paths to archive are
+/// written to the archive. A directory that is skipped is not walked.
+pub struct SourceFilter {
+    include: Patterns,
+    exclude: Patterns,
+    respect_gitignore: bool,
+    /// `.gitignore` files of the directories being walked, innermost last
+    gitignores: Vec<Option<Gitignore>>,
+}
+
+impl SourceFilter {
+    pub fn new(options: &ArchiveOptions) -> Result<Self> {
+        let mut exclude = options.exclude.clone();
+        for path in options.exclude_from.iter() {
+            exclude.extend(read_patterns(path)?);
+        }
+        Ok(SourceFilter {
+            include: Patterns::new(&options.include)?,
+            exclude: Patterns::new(&exclude)?,
+            respect_gitignore: options.respect_gitignore,
+            gitignores: Vec::new(),
+        })
+    }
+
+    /// Returns true if the file at `path`, to be archived as `name`, passes the filter.
+    /// Include patterns only apply to files, so that directories are still walked.
+    pub fn is_included(&self, path: &Path, name: &OsStr, is_dir: bool) -> bool {
+        if self.exclude.is_match(name, is_dir) {
+            return false;
+        }
+        if !is_dir && !self.include.is_empty() && !self.include.is_match(name, is_dir) {
+            return false;
+        }
+        // the innermost .gitignore with a matching pattern decides
+        for gitignore in self.gitignores.iter().rev().flatten() {
+            let matched = gitignore.matched(path, is_dir);
+            if matched.is_ignore() {
+                return false;
+            }
+            if matched.is_whitelist() {
+                break;
+            }
+        }
+        true
+    }
+
+    /// Reads the `.gitignore` file of the directory at `path` before walking it
+    pub fn enter_directory(&mut self, path: &Path) -> Result<()> {
+        let gitignore_path = path.join(".gitignore");
+        if !self.respect_gitignore || !gitignore_path.is_file() {
+            self.gitignores.push(None);
+            return Ok(());
+        }
+        let mut builder = GitignoreBuilder::new(path);
+        if let Some(error) = builder.add(&gitignore_path) {
+            eprintln!(
+                "skipping invalid patterns in {}: {}",
+                gitignore_path.display(),
+                error
+            );
+        }
+        self.gitignores.push(Some(builder.build()?));
+        Ok(())
+    }
+
+    pub fn leave_directory(&mut self) {
+        self.gitignores.pop();
+    }
+}
@@ -0,0 +1,279 @@
+use super::read_bytes;
+use super::read_u32;
+use super::read_u64;
+use super::xattr::ExtendedAttribute;
+use anyhow::Ok;
+use anyhow::Result;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::ffi::OsString;
+use std::fs::symlink_metadata;
+use std::fs::Metadata;
+use std::fs::Permissions;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::lchown;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const NAME_BUFFER_SIZE: usize = 16384;
+/// uid or gid recorded for entries whose owner is unknown, the id `chown` ignores
+const NO_OWNER: u32 = u32::MAX;
+
+/// Unix metadata of an archived file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// permission bits (including setuid, setgid and sticky bits)
+    pub mode: u32,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub atime: i64,
+    pub atime_nsec: u32,
+    /// owner and group ids, `None` if the archive does not record them
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// name of the owner, empty if the uid has no name
+    pub user_name: String,
+    /// name of the group, empty if the gid has no name
+    pub group_name: String,
+    /// extended attributes (including POSIX ACLs), empty unless they were recorded
+    pub xattrs: Vec<ExtendedAttribute>,
+}
+
+impl EntryMetadata {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        EntryMetadata {
+            mode: metadata.mode() & 0o7777,
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            atime: metadata.atime(),
+            atime_nsec: metadata.atime_nsec() as u32,
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            user_name: user_name(metadata.uid()).unwrap_or_default(),
+            group_name: group_name(metadata.gid()).unwrap_or_default(),
+            xattrs: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.mode.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime_nsec.to_be_bytes());
+        bytes.extend_from_slice(&self.atime.to_be_bytes());
+        bytes.extend_from_slice(&self.atime_nsec.to_be_bytes());
+        for id in [self.uid, self.gid] {
+            bytes.extend_from_slice(&id.unwrap_or(NO_OWNER).to_be_bytes());
+        }
+        for name in [&self.user_name, &self.group_name] {
+            bytes.extend_from_slice(&(name.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.xattrs.len() as u64).to_be_bytes());
+        for xattr in self.xattrs.iter() {
+            for field in [xattr.name.as_bytes(), &xattr.value] {
+                bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(field);
+            }
+        }
+        bytes
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mode = read_u32(reader)?;
+        let mtime = read_u64(reader)? as i64;
+        let mtime_nsec = read_u32(reader)?;
+        let atime = read_u64(reader)? as i64;
+        let atime_nsec = read_u32(reader)?;
+        let uid = Some(read_u32(reader)?).filter(|uid| *uid != NO_OWNER);
+        let gid = Some(read_u32(reader)?).filter(|gid| *gid != NO_OWNER);
+        let mut names = Vec::new();
+        for _ in 0..2 {
+            names.push(String::from_utf8(read_bytes(reader)?)?);
+        }
+        let group_name = names.pop().unwrap();
+        let user_name = names.pop().unwrap();
+        let num_xattrs = read_u64(reader)?;
+        let mut xattrs = Vec::new();
+        for _ in 0..num_xattrs {
+            let mut fields = Vec::new();
+            for _ in 0..2 {
+                fields.push(read_bytes(reader)?);
+            }
+            let value = fields.pop().unwrap();
+            let name = OsString::from_vec(fields.pop().unwrap());
+            xattrs.push(ExtendedAttribute { name, value });
+        }
+        Ok(EntryMetadata {
+            mode,
+            mtime,
+            mtime_nsec,
+            atime,
+            atime_nsec,
+            uid,
+            gid,
+            user_name,
+            group_name,
+            xattrs,
+        })
+    }
+
+    /// Permission bits formatted as in `ls -l`, e.g. `rwxr-xr-x`
+    pub fn permissions_string(&self) -> String {
+        let mut permissions = String::new();
+        for (shift, special_bit, special_char) in
+            [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')]
+        {
+            let bits = (self.mode >> shift) & 0o7;
+            permissions.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            permissions.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            permissions.push(match (bits & 0o1 != 0, self.mode & special_bit != 0) {
+                (true, true) => special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        permissions
+    }
+
+    /// Applies the metadata to the file at `path`, or to the link itself if
+    /// `path` is a symbolic link. Ownership is only restored if `restore_ownership`
+    /// is set and recorded, preferring the owner and group names over the numeric
+    /// ids when they exist on this system.
+    pub fn restore(&self, path: &Path, restore_ownership: bool) -> Result<()> {
+        let is_symlink = symlink_metadata(path)?.file_type().is_symlink();
+        if restore_ownership {
+            let uid = user_id(&self.user_name).or(self.uid);
+            let gid = group_id(&self.group_name).or(self.gid);
+            if uid.is_some() || gid.is_some() {
+                lchown(path, uid, gid)?;
+            }
+        }
+        // permissions are set after the owner, as changing the owner clears setuid bits
+        // (the permissions of symbolic links are not used on Linux and cannot be changed)
+        if !is_symlink {
+            std::fs::set_permissions(path, Permissions::from_mode(self.mode))?;
+        }
+        let times = [
+            libc::timespec {
+                tv_sec: self.atime as libc::time_t,
+                tv_nsec: self.atime_nsec as libc::c_long,
+            },
+            libc::timespec {
+                tv_sec: self.mtime as libc::time_t,
+                tv_nsec: self.mtime_nsec as libc::c_long,
+            },
+        ];
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let flags = if is_symlink {
+            libc::AT_SYMLINK_NOFOLLOW
+        } else {
+            0
+        };
+        let status =
+            unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), flags) };
+        if status != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the process runs with the privileges required to change ownership
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(passwd.pw_name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn group_name(gid: u32) -> Option<String> {
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrgid_r(
+            gid,
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(group.gr_name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn user_id(user_name: &str) -> Option<u32> {
+    let user_name = CString::new(user_name)
+        .ok()
+        .filter(|name| !name.is_empty())?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            user_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(passwd.pw_uid)
+}
+
+fn group_id(group_name: &str) -> Option<u32> {
+    let group_name = CString::new(group_name)
+        .ok()
+        .filter(|name| !name.is_empty())?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            group_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(group.gr_gid)
+}
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::repeat;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+
+/// A region of a sparse file holding data, the rest of the file being holes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataRegion {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Finds the regions of `file` holding data with SEEK_DATA and SEEK_HOLE,
+/// returning `None` if the file has no holes (or the file system cannot tell).
+/// The file is left positioned at its start.
+pub fn data_regions(file: &mut File, size: u64) -> Result<Option<Vec<DataRegion>>> {
+    let fd = file.as_raw_fd();
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let data_start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data_start < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::ENXIO) {
+                // no data after offset, the rest of the file is a hole
+                break;
+            }
+            file.seek(SeekFrom::Start(0))?;
+            return Ok(None);
+        }
+        let hole_start = unsafe { libc::lseek(fd, data_start, libc::SEEK_HOLE) };
+        if hole_start < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let data_start = data_start as u64;
+        let hole_start = (hole_start as u64).min(size);
+        if data_start >= size {
+            break;
+        }
+        regions.push(DataRegion {
+            offset: data_start,
+            length: hole_start - data_start,
+        });
+        offset = hole_start;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    let is_sparse = data_length(&regions) < size;
+    Ok(if is_sparse { Some(regions) } else { None })
+}
+
+/// Total length of the data held in `regions`
+pub fn data_length(regions: &[DataRegion]) -> u64 {
+    regions.iter().map(|region| region.length).sum()
+}
+
+/// Reads the data regions of a sparse file one after the other, skipping the holes
+pub struct SparseReader<'a> {
+    file: &'a mut File,
+    regions: &'a [DataRegion],
+    region_index: usize,
+    region_read: u64,
+}
+
+impl<'a> SparseReader<'a> {
+    pub fn new(file: &'a mut File, regions: &'a [DataRegion]) -> Self {
+        SparseReader {
+            file,
+            regions,
+            region_index: 0,
+            region_read: 0,
+        }
+    }
+}
+
+impl Read for SparseReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self
+            .regions
+            .get(self.region_index)
+            .is_some_and(|region| self.region_read == region.length)
+        {
+            self.region_index += 1;
+            self.region_read = 0;
+        }
+        let Some(region) = self.regions.get(self.region_index) else {
+            return Ok(0);
+        };
+        if self.region_read == 0 {
+            self.file.seek(SeekFrom::Start(region.offset))?;
+        }
+        let max_len = (buf.len() as u64).min(region.length - self.region_read) as usize;
+        let bytes_read = self.file.read(&mut buf[..max_len])?;
+        if bytes_read == 0 {
+            // the file was truncated, let the caller detect the missing data
+            self.region_index = self.regions.len();
+        }
+        self.region_read += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Writes the data regions of a sparse file, seeking over the holes between
+/// them so that they are not allocated
+pub struct SparseWriter<'a> {
+    file: &'a mut File,
+    regions: &'a [DataRegion],
+    region_index: usize,
+    region_written: u64,
+}
+
+impl<'a> SparseWriter<'a> {
+    pub fn new(file: &'a mut File, regions: &'a [DataRegion]) -> Self {
+        SparseWriter {
+            file,
+            regions,
+            region_index: 0,
+            region_written: 0,
+        }
+    }
+}
+
+impl Write for SparseWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        while self
+            .regions
+            .get(self.region_index)
+            .is_some_and(|region| self.region_written == region.length)
+        {
+            self.region_index += 1;
+            self.region_written = 0;
+        }
+        let Some(region) = self.regions.get(self.region_index) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "data exceeds the regions of the sparse file",
+            ));
+        };
+        if self.region_written == 0 {
+            self.file.seek(SeekFrom::Start(region.offset))?;
+        }
+        let max_len = (buf.len() as u64).min(region.length - self.region_written) as usize;
+        let bytes_written = self.file.write(&buf[..max_len])?;
+        self.region_written += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes the data regions of a sparse file to a stream at their offsets,
+/// writing zeros for the holes before and between them
+pub struct HoleFillingWriter<'a, W: Write> {
+    writer: W,
+    regions: &'a [DataRegion],
+    region_index: usize,
+    region_written: u64,
+    position: u64,
+}
+
+impl<'a, W: Write> HoleFillingWriter<'a, W> {
+    pub fn new(writer: W, regions: &'a [DataRegion]) -> Self {
+        HoleFillingWriter {
+            writer,
+            regions,
+            region_index: 0,
+            region_written: 0,
+            position: 0,
+        }
+    }
+
+    /// Writes zeros for the hole after the last region, up to `size`
+    pub fn finish(mut self, size: u64) -> std::io::Result<W> {
+        self.write_zeros(size.saturating_sub(self.position))?;
+        Ok(self.writer)
+    }
+
+    fn write_zeros(&mut self, length: u64) -> std::io::Result<()> {
+        std::io::copy(&mut repeat(0).take(length), &mut self.writer)?;
+        self.position += length;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HoleFillingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        while self
+            .regions
+            .get(self.region_index)
+            .is_some_and(|region| self.region_written == region.length)
+        {
+            self.region_index += 1;
+            self.region_written = 0;
+        }
+        let Some(region) = self.regions.get(self.region_index) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "data exceeds the regions of the sparse file",
+            ));
+        };
+        if self.region_written == 0 {
+            self.write_zeros(region.offset.saturating_sub(self.position))?;
+        }
+        let max_len = (buf.len() as u64).min(region.length - self.region_written) as usize;
+        let bytes_written = self.writer.write(&buf[..max_len])?;
+        self.region_written += bytes_written as u64;
+        self.position += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
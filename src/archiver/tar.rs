@@ -0,0 +1,753 @@
+use super::xattr::ExtendedAttribute;
+use super::EntryKind;
+use super::EntryMetadata;
+use anyhow::bail;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io::copy;
+use std::io::sink;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+
+// A tar stream is a sequence of 512-byte blocks: each entry has a header block
+// followed by its data padded to a whole number of blocks, and the stream ends
+// with two zero blocks. The ustar header holds the name (split into a prefix and
+// a name for longer names), octal numbers and the type of the entry. Values that
+// do not fit in it are given by a pax extended header ('x') preceding the entry,
+// holding "<length> <key>=<value>\n" records, or by the GNU long name ('L') and
+// long link ('K') entries.
+const BLOCK_SIZE: usize = 512;
+/// magic of POSIX headers (GNU headers have "ustar " and no prefix field)
+const USTAR_MAGIC: &[u8; 6] = b"ustar\0";
+/// largest value of the 12-byte octal fields (size and mtime)
+const MAX_OCTAL_12: u64 = 0o77777777777;
+/// largest value of the 8-byte octal fields (uid and gid)
+const MAX_OCTAL_8: u64 = 0o7777777;
+/// prefix of the pax keys holding extended attributes, as written by star and GNU tar
+const XATTR_PAX_PREFIX: &str = "SCHILY.xattr.";
+
+/// An entry read from a tar stream
+pub struct TarEntry {
+    /// name of the entry, without leading '/' or './' and trailing '/'
+    pub name: OsString,
+    pub kind: EntryKind,
+    pub link_target: OsString,
+    pub size: u64,
+    pub metadata: EntryMetadata,
+}
+
+/// Number of padding bytes after `size` bytes of data
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64
+}
+
+/// Returns the bytes of a header field up to the first NUL
+fn field_bytes(field: &[u8]) -> &[u8] {
+    match field.iter().position(|byte| *byte == 0) {
+        Some(end) => &field[..end],
+        None => field,
+    }
+}
+
+/// Parses a numeric header field, written in octal or, for values too large
+/// for it, in base 256 with the high bit of the first byte set (GNU extension)
+fn parse_number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        if field[0] & 0x40 != 0 {
+            bail!("negative numbers in tar headers are not supported");
+        }
+        let mut value: u64 = (field[0] & 0x3f) as u64;
+        for byte in field[1..].iter() {
+            value = value
+                .checked_mul(256)
+                .and_then(|value| value.checked_add(*byte as u64))
+                .ok_or_else(|| anyhow::anyhow!("number in tar header is too large"))?;
+        }
+        return Ok(value);
+    }
+    let text = std::str::from_utf8(field_bytes(field))?.trim_matches(' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    Ok(u64::from_str_radix(text, 8)?)
+}
+
+/// Parses a pax time, in seconds with an optional fraction, as seconds and nanoseconds
+fn parse_time(value: &[u8]) -> Result<(i64, u32)> {
+    let text = std::str::from_utf8(value)?;
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let mut seconds: i64 = seconds.parse()?;
+    let digits: String = fraction
+        .chars()
+        .chain("000000000".chars())
+        .take(9)
+        .collect();
+    let mut nanoseconds: u32 = digits.parse()?;
+    if text.starts_with('-') && nanoseconds > 0 {
+        seconds -= 1;
+        nanoseconds = 1_000_000_000 - nanoseconds;
+    }
+    Ok((seconds, nanoseconds))
+}
+
+/// Parses the records of a pax extended header
+fn parse_pax_records(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let Some(space) = rest.iter().position(|byte| *byte == b' ') else {
+            bail!("pax extended header is corrupted");
+        };
+        let length: usize = std::str::from_utf8(&rest[..space])?.parse()?;
+        if length <= space + 1 || length > rest.len() || rest[length - 1] != b'\n' {
+            bail!("pax extended header is corrupted");
+        }
+        let record = &rest[space + 1..length - 1];
+        let Some(equals) = record.iter().position(|byte| *byte == b'=') else {
+            bail!("pax extended header is corrupted");
+        };
+        let key = String::from_utf8(record[..equals].to_vec())?;
+        records.push((key, record[equals + 1..].to_vec()));
+        rest = &rest[length..];
+    }
+    Ok(records)
+}
+
+/// Removes the leading '/' and './' components and the trailing '/' of a name
+pub fn normalize_name(name: &[u8]) -> OsString {
+    let mut name = name;
+    loop {
+        if let [b'/', rest @ ..] = name {
+            name = rest;
+        } else if let [b'.', b'/', rest @ ..] = name {
+            name = rest;
+        } else {
+            break;
+        }
+    }
+    while let [rest @ .., b'/'] = name {
+        name = rest;
+    }
+    if name == b"." {
+        name = b"";
+    }
+    OsString::from_vec(name.to_vec())
+}
+
+/// Reads the entries of a tar stream in the ustar, pax or GNU format
+pub struct TarReader<R: Read> {
+    reader: R,
+    /// records of the pax global headers read so far
+    global_records: HashMap<String, Vec<u8>>,
+    /// bytes of data of the last entry that were not read
+    unread_size: u64,
+    /// bytes of padding after the data of the last entry
+    padding_size: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(reader: R) -> Self {
+        TarReader {
+            reader,
+            global_records: HashMap::new(),
+            unread_size: 0,
+            padding_size: 0,
+        }
+    }
+
+    /// Reads `size` bytes of data and the padding after them
+    fn read_padded(&mut self, size: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.reader).take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            bail!("tar stream is truncated");
+        }
+        self.skip(padding(size))?;
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: u64) -> Result<()> {
+        if copy(&mut (&mut self.reader).take(size), &mut sink())? != size {
+            bail!("tar stream is truncated");
+        }
+        Ok(())
+    }
+
+    /// Reads the header of the next entry, returning `None` at the end of the stream.
+    /// Entries of other types than files, directories and links are skipped.
+    pub fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        let unread_size = std::mem::take(&mut self.unread_size);
+        let padding_size = std::mem::take(&mut self.padding_size);
+        self.skip(unread_size + padding_size)?;
+        let mut records: HashMap<String, Vec<u8>> = self.global_records.clone();
+        let mut long_name = None;
+        let mut long_link_target = None;
+        loop {
+            let mut block = Vec::new();
+            (&mut self.reader)
+                .take(BLOCK_SIZE as u64)
+                .read_to_end(&mut block)?;
+            if block.is_empty() {
+                // streams are expected to end with zero blocks, but accept a missing end
+                return Ok(None);
+            }
+            let Ok(header) = <[u8; BLOCK_SIZE]>::try_from(block) else {
+                bail!("tar stream is truncated");
+            };
+            if header.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+            let checksum = parse_number(&header[148..156])?;
+            let computed_checksum: u64 = header
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| if (148..156).contains(&i) { b' ' } else { *byte } as u64)
+                .sum();
+            if checksum != computed_checksum {
+                bail!("tar header is corrupted");
+            }
+
+            let type_flag = header[156];
+            let size = parse_number(&header[124..136])?;
+            match type_flag {
+                b'x' => {
+                    let data = self.read_padded(size)?;
+                    records.extend(parse_pax_records(&data)?);
+                    continue;
+                }
+                b'g' => {
+                    let data = self.read_padded(size)?;
+                    let global_records = parse_pax_records(&data)?;
+                    self.global_records.extend(global_records.clone());
+                    records.extend(global_records);
+                    continue;
+                }
+                b'L' => {
+                    long_name = Some(field_bytes(&self.read_padded(size)?).to_vec());
+                    continue;
+                }
+                b'K' => {
+                    long_link_target = Some(field_bytes(&self.read_padded(size)?).to_vec());
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut name = field_bytes(&header[0..100]).to_vec();
+            if &header[257..263] == USTAR_MAGIC && header[345] != 0 {
+                let mut prefixed_name = field_bytes(&header[345..500]).to_vec();
+                prefixed_name.push(b'/');
+                prefixed_name.extend_from_slice(&name);
+                name = prefixed_name;
+            }
+            if let Some(long_name) = long_name.take() {
+                name = long_name;
+            }
+            if let Some(path) = records.remove("path") {
+                name = path;
+            }
+            let mut link_target = field_bytes(&header[157..257]).to_vec();
+            if let Some(long_link_target) = long_link_target.take() {
+                link_target = long_link_target;
+            }
+            if let Some(linkpath) = records.remove("linkpath") {
+                link_target = linkpath;
+            }
+            let size = match records.get("size") {
+                Some(size) => std::str::from_utf8(size)?.parse()?,
+                None => size,
+            };
+
+            let kind = match type_flag {
+                b'0' | 0 | b'7' => EntryKind::File,
+                b'1' => EntryKind::HardLink,
+                b'2' => EntryKind::Symlink,
+                b'5' => EntryKind::Directory,
+                _ => {
+                    if type_flag != b'V' {
+                        eprintln!(
+                            "skipping {}: unsupported tar entry type '{}'",
+                            String::from_utf8_lossy(&name),
+                            type_flag as char
+                        );
+                    }
+                    self.skip(size + padding(size))?;
+                    records = self.global_records.clone();
+                    continue;
+                }
+            };
+            let name = normalize_name(&name);
+            let link_target = match kind {
+                EntryKind::HardLink => normalize_name(&link_target),
+                _ => OsString::from_vec(link_target),
+            };
+
+            let mtime = parse_number(&header[136..148])?;
+            let (mtime, mtime_nsec) = match records.get("mtime") {
+                Some(mtime) => parse_time(mtime)?,
+                None => (mtime as i64, 0),
+            };
+            let (atime, atime_nsec) = match records.get("atime") {
+                Some(atime) => parse_time(atime)?,
+                None => (mtime, mtime_nsec),
+            };
+            let mut metadata = EntryMetadata {
+                mode: parse_number(&header[100..108])? as u32 & 0o7777,
+                mtime,
+                mtime_nsec,
+                atime,
+                atime_nsec,
+                uid: Some(parse_number(&header[108..116])? as u32),
+                gid: Some(parse_number(&header[116..124])? as u32),
+                user_name: String::from_utf8_lossy(field_bytes(&header[265..297])).to_string(),
+                group_name: String::from_utf8_lossy(field_bytes(&header[297..329])).to_string(),
+                xattrs: Vec::new(),
+            };
+            for (key, value) in records.iter() {
+                match key.as_str() {
+                    "uid" => metadata.uid = Some(std::str::from_utf8(value)?.parse()?),
+                    "gid" => metadata.gid = Some(std::str::from_utf8(value)?.parse()?),
+                    "uname" => metadata.user_name = String::from_utf8_lossy(value).to_string(),
+                    "gname" => metadata.group_name = String::from_utf8_lossy(value).to_string(),
+                    _ => {
+                        if let Some(xattr_name) = key.strip_prefix(XATTR_PAX_PREFIX) {
+                            metadata.xattrs.push(ExtendedAttribute {
+                                name: xattr_name.into(),
+                                value: value.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+            metadata.xattrs.sort_by(|a, b| a.name.cmp(&b.name));
+
+            if name.is_empty() {
+                // the root of the archived tree, as in './'
+                self.skip(size + padding(size))?;
+                records = self.global_records.clone();
+                continue;
+            }
+            if kind == EntryKind::File {
+                self.unread_size = size;
+                self.padding_size = padding(size);
+            } else {
+                self.padding_size = size + padding(size);
+            }
+            return Ok(Some(TarEntry {
+                name,
+                kind,
+                link_target,
+                size: if kind == EntryKind::File { size } else { 0 },
+                metadata,
+            }));
+        }
+    }
+}
+
+/// Reads the data of the entry last returned by `next_entry`, which is read
+/// as a stream of the size of the entry
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max_len = buf
+            .len()
+            .min(self.unread_size.try_into().unwrap_or(usize::MAX));
+        if max_len == 0 {
+            return Ok(0);
+        }
+        let bytes_read = self.reader.read(&mut buf[..max_len])?;
+        if bytes_read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "tar stream is truncated",
+            ));
+        }
+        self.unread_size -= bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Writes entries to a POSIX tar stream, adding pax extended headers
+/// for the values that ustar headers cannot hold
+pub struct TarWriter<W: Write> {
+    writer: W,
+    /// size of the data of the last entry written
+    entry_size: u64,
+    /// bytes of the data of the last entry still to be written
+    unwritten_size: u64,
+}
+
+/// Appends a pax record to `records`
+fn push_pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    // the length of the record includes the digits of the length itself
+    let unprefixed_len = key.len() + value.len() + 3;
+    let mut length = unprefixed_len + 1;
+    while length.to_string().len() + unprefixed_len > length {
+        length += 1;
+    }
+    records.extend_from_slice(format!("{} {}=", length, key).as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Writes `value` as a NUL terminated octal number filling `field`
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+/// Splits a name into the prefix and name fields of a ustar header, if it fits
+fn split_name(name: &[u8]) -> Option<(&[u8], &[u8])> {
+    if name.len() <= 100 {
+        return Some((&[], name));
+    }
+    (0..name.len())
+        .filter(|i| name[*i] == b'/')
+        .find(|i| *i <= 155 && name.len() - i - 1 <= 100 && name.len() - i > 1)
+        .map(|i| (&name[..i], &name[i + 1..]))
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TarWriter {
+            writer,
+            entry_size: 0,
+            unwritten_size: 0,
+        }
+    }
+
+    fn write_padded(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        self.writer
+            .write_all(&vec![0u8; padding(data.len() as u64) as usize])?;
+        Ok(())
+    }
+
+    fn write_header(
+        &mut self,
+        name: &[u8],
+        type_flag: u8,
+        link_target: &[u8],
+        size: u64,
+        metadata: &EntryMetadata,
+    ) -> Result<()> {
+        let mut header = [0u8; BLOCK_SIZE];
+        let (prefix, name) = split_name(name).unwrap_or((&[], &name[..name.len().min(100)]));
+        header[..name.len()].copy_from_slice(name);
+        write_octal(&mut header[100..108], metadata.mode as u64 & 0o7777);
+        // entries without a recorded owner are written as owned by root
+        for (field, id) in [(108..116, metadata.uid), (116..124, metadata.gid)] {
+            write_octal(
+                &mut header[field],
+                (id.unwrap_or_default() as u64).min(MAX_OCTAL_8),
+            );
+        }
+        write_octal(&mut header[124..136], size.min(MAX_OCTAL_12));
+        write_octal(
+            &mut header[136..148],
+            metadata.mtime.clamp(0, MAX_OCTAL_12 as i64) as u64,
+        );
+        header[156] = type_flag;
+        let link_target = &link_target[..link_target.len().min(100)];
+        header[157..157 + link_target.len()].copy_from_slice(link_target);
+        header[257..263].copy_from_slice(USTAR_MAGIC);
+        header[263..265].copy_from_slice(b"00");
+        // names that do not fit are given by pax records
+        for (start, name) in [(265, &metadata.user_name), (297, &metadata.group_name)] {
+            if name.len() < 32 {
+                header[start..start + name.len()].copy_from_slice(name.as_bytes());
+            }
+        }
+        header[345..345 + prefix.len()].copy_from_slice(prefix);
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|byte| *byte as u64).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        self.writer.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Writes the headers of an entry of the given kind, the `size` bytes of
+    /// the contents of files being then written to the tar writer itself
+    pub fn write_entry(
+        &mut self,
+        name: &OsStr,
+        kind: EntryKind,
+        link_target: &OsStr,
+        metadata: &EntryMetadata,
+        size: u64,
+    ) -> Result<()> {
+        self.end_entry()?;
+        let mut name = name.as_bytes().to_vec();
+        let type_flag = match kind {
+            EntryKind::File => b'0',
+            EntryKind::HardLink => b'1',
+            EntryKind::Symlink => b'2',
+            EntryKind::Directory => {
+                name.push(b'/');
+                b'5'
+            }
+        };
+        let link_target = link_target.as_bytes();
+
+        let mut records = Vec::new();
+        if split_name(&name).is_none() {
+            push_pax_record(&mut records, "path", &name);
+        }
+        if link_target.len() > 100 {
+            push_pax_record(&mut records, "linkpath", link_target);
+        }
+        if size > MAX_OCTAL_12 {
+            push_pax_record(&mut records, "size", size.to_string().as_bytes());
+        }
+        if metadata.mtime_nsec != 0 || !(0..=MAX_OCTAL_12 as i64).contains(&metadata.mtime) {
+            let mtime = match (metadata.mtime, metadata.mtime_nsec) {
+                (seconds, 0) => seconds.to_string(),
+                (seconds, nanoseconds) if seconds < 0 => {
+                    format!("-{}.{:09}", -(seconds + 1), 1_000_000_000 - nanoseconds)
+                }
+                (seconds, nanoseconds) => format!("{}.{:09}", seconds, nanoseconds),
+            };
+            push_pax_record(&mut records, "mtime", mtime.as_bytes());
+        }
+        for (key, id) in [("uid", metadata.uid), ("gid", metadata.gid)] {
+            if let Some(id) = id.filter(|id| *id as u64 > MAX_OCTAL_8) {
+                push_pax_record(&mut records, key, id.to_string().as_bytes());
+            }
+        }
+        for (key, value) in [
+            ("uname", &metadata.user_name),
+            ("gname", &metadata.group_name),
+        ] {
+            if value.len() >= 32 || !value.is_ascii() {
+                push_pax_record(&mut records, key, value.as_bytes());
+            }
+        }
+        for xattr in metadata.xattrs.iter() {
+            let Some(xattr_name) = xattr.name.to_str() else {
+                eprintln!(
+                    "skipping extended attribute {} of {}: name is not valid UTF-8",
+                    xattr.name.display(),
+                    String::from_utf8_lossy(&name)
+                );
+                continue;
+            };
+            push_pax_record(
+                &mut records,
+                &format!("{}{}", XATTR_PAX_PREFIX, xattr_name),
+                &xattr.value,
+            );
+        }
+
+        if !records.is_empty() {
+            let file_name = name
+                .rsplit(|byte| *byte == b'/')
+                .find(|component| !component.is_empty())
+                .unwrap_or(&[]);
+            let mut pax_name = b"PaxHeaders/".to_vec();
+            pax_name.extend_from_slice(&file_name[..file_name.len().min(89)]);
+            let pax_metadata = EntryMetadata {
+                mode: 0o644,
+                mtime: metadata.mtime,
+                ..Default::default()
+            };
+            self.write_header(&pax_name, b'x', &[], records.len() as u64, &pax_metadata)?;
+            self.write_padded(&records)?;
+        }
+        self.write_header(&name, type_flag, link_target, size, metadata)?;
+        self.entry_size = size;
+        self.unwritten_size = size;
+        Ok(())
+    }
+
+    /// Pads the data of the last entry to a whole block, checking it was fully written
+    fn end_entry(&mut self) -> Result<()> {
+        if self.unwritten_size != 0 {
+            bail!("data of tar entry is {} bytes short", self.unwritten_size);
+        }
+        self.writer
+            .write_all(&vec![0u8; padding(self.entry_size) as usize])?;
+        self.entry_size = 0;
+        Ok(())
+    }
+
+    /// Writes the end of the stream, returning the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.end_entry()?;
+        self.writer.write_all(&[0u8; 2 * BLOCK_SIZE])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for TarWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() as u64 > self.unwritten_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "data exceeds the size of the tar entry",
+            ));
+        }
+        let bytes_written = self.writer.write(buf)?;
+        self.unwritten_size -= bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archiver::archive_files;
+    use crate::archiver::export_tar;
+    use crate::archiver::import_tar;
+    use crate::archiver::list_entries;
+    use crate::archiver::unarchive_files;
+    use crate::archiver::ArchiveOptions;
+    use crate::archiver::EntryKind;
+    use crate::archiver::UnarchiveOptions;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs::create_dir_all;
+    use std::fs::metadata;
+    use std::fs::read_link;
+    use std::fs::read_to_string;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+    use std::fs::File;
+    use std::os::unix::fs::MetadataExt;
+
+    /// mtime of the files of the fixtures, 2024-03-04 05:06:07.25 UTC
+    const FIXTURE_MTIME: i64 = 1709528767;
+
+    #[test]
+    fn test_tar_round_trip() -> Result<()> {
+        create_dir_all("tar_test")?;
+        // written by GNU tar 1.34 with --format=gnu and --format=posix
+        for format in ["gnu", "pax"] {
+            let archive_path = format!("tar_test/{}.archive", format);
+            import_tar(
+                File::open(format!("testdata/{}.tar", format))?,
+                &archive_path,
+                &ArchiveOptions::default(),
+            )?;
+            let entries = list_entries(&archive_path)?;
+            let names: Vec<String> = entries.iter().map(|entry| entry.display_name()).collect();
+            assert_eq!(names.len(), 10);
+            assert!(names.contains(&"project/a_fairly_long_directory_name_used_to_exceed_the_ustar_name_field/and_a_long_file_name_that_only_fits_with_extensions.txt".to_string()));
+
+            let entry = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap();
+            let run = entry("project/run.sh");
+            let run_metadata = run.metadata.as_ref().unwrap();
+            assert_eq!(run_metadata.mode, 0o755);
+            assert_eq!(run_metadata.mtime, 1672628645);
+            assert_eq!(
+                (run_metadata.uid, run_metadata.gid),
+                (Some(1000), Some(100))
+            );
+            assert_eq!(run_metadata.user_name, "alice");
+            assert_eq!(run_metadata.group_name, "staff");
+            let empty = entry("project/empty");
+            assert_eq!(empty.kind, EntryKind::Directory);
+            assert_eq!(empty.metadata.as_ref().unwrap().mode, 0o700);
+            // only pax headers record fractions of seconds
+            let guide_metadata = entry("project/docs/guide.md").metadata.clone().unwrap();
+            assert_eq!(guide_metadata.mtime, FIXTURE_MTIME);
+            let mtime_nsec = if format == "pax" { 250_000_000 } else { 0 };
+            assert_eq!(guide_metadata.mtime_nsec, mtime_nsec);
+            let link = entry("project/docs/link");
+            assert_eq!(link.kind, EntryKind::Symlink);
+            assert_eq!(link.link_target, "../hello.txt");
+            let hello = entry("project/hello.txt");
+            assert_eq!(hello.kind, EntryKind::HardLink);
+            assert_eq!(hello.link_target, "project/docs/hard.txt");
+
+            // exporting and importing again preserves the entries
+            let mut tar = Vec::new();
+            export_tar(&archive_path, &mut tar)?;
+            let round_trip_path = format!("tar_test/{}_round_trip.archive", format);
+            import_tar(tar.as_slice(), &round_trip_path, &ArchiveOptions::default())?;
+            for (entry, round_trip_entry) in entries.iter().zip(list_entries(&round_trip_path)?) {
+                assert_eq!(entry.name, round_trip_entry.name);
+                assert_eq!(entry.kind, round_trip_entry.kind);
+                assert_eq!(entry.link_target, round_trip_entry.link_target);
+                assert_eq!(entry.size, round_trip_entry.size);
+                assert_eq!(entry.metadata, round_trip_entry.metadata);
+            }
+
+            let output_dir = format!("tar_test/output_{}", format);
+            unarchive_files(&round_trip_path, &output_dir, &UnarchiveOptions::default())?;
+            assert_eq!(
+                read_to_string(format!("{}/project/hello.txt", output_dir))?,
+                "hello from tar\n"
+            );
+            assert_eq!(
+                metadata(format!("{}/project/hello.txt", output_dir))?.ino(),
+                metadata(format!("{}/project/docs/hard.txt", output_dir))?.ino()
+            );
+            assert_eq!(
+                read_link(format!("{}/project/docs/link", output_dir))?,
+                std::path::Path::new("../hello.txt")
+            );
+        }
+
+        // names that do not fit in ustar headers are written to pax headers
+        let long_name = "x".repeat(120);
+        create_dir_all("tar_test/src")?;
+        write(format!("tar_test/src/{}", long_name), "long name")?;
+        archive_files(
+            &["tar_test/src"],
+            "tar_test/long.archive",
+            &ArchiveOptions::default(),
+        )?;
+        let mut tar = Vec::new();
+        export_tar("tar_test/long.archive", &mut tar)?;
+        import_tar(
+            tar.as_slice(),
+            "tar_test/long_round_trip.archive",
+            &ArchiveOptions::default(),
+        )?;
+        let entries = list_entries("tar_test/long_round_trip.archive")?;
+        assert_eq!(entries[1].name, format!("src/{}", long_name).as_str());
+
+        // the data of the entries is streamed into chunked archives as well
+        let chunked_options = ArchiveOptions {
+            chunked: true,
+            ..Default::default()
+        };
+        import_tar(tar.as_slice(), "tar_test/chunked.archive", &chunked_options)?;
+        let entries = list_entries("tar_test/chunked.archive")?;
+        assert!(entries[1].chunks.is_some());
+        unarchive_files(
+            "tar_test/chunked.archive",
+            "tar_test/output_chunked",
+            &UnarchiveOptions::default(),
+        )?;
+        assert_eq!(
+            read_to_string(format!("tar_test/output_chunked/src/{}", long_name))?,
+            "long name"
+        );
+
+        // streams ending in the data of an entry are rejected
+        let data_offset = tar
+            .windows(9)
+            .position(|window| window == b"long name")
+            .unwrap();
+        assert!(import_tar(
+            &tar[..data_offset + 4],
+            "tar_test/truncated.archive",
+            &ArchiveOptions::default(),
+        )
+        .is_err());
+
+        remove_dir_all("tar_test")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::bail;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Each volume of a split archive starts with a header: magic, identifier of the
+// archive (u64, shared by its volumes), number of the volume (u32, from 1) and
+// number of volumes (u32, only set in the first volume once the archive is finished),
+// followed by the next part of the archive.
+const VOLUME_MAGIC: &[u8; 8] = b"FCVOLUME";
+const VOLUME_HEADER_SIZE: u64 = 24;
+/// offset of the number of volumes in the header
+const NUM_VOLUMES_OFFSET: u64 = 20;
+
+/// Path of the volume numbered `number` of the archive at `base_path`,
+/// as in `backup.arc.001`
+pub fn volume_path(base_path: &Path, number: u32) -> PathBuf {
+    let mut path = base_path.as_os_str().to_os_string();
+    path.push(format!(".{:03}", number));
+    path.into()
+}
+
+struct VolumeHeader {
+    archive_id: u64,
+    number: u32,
+    num_volumes: u32,
+}
+
+/// Reads the header of the volume `file`, returning `None` if it is not a volume
+fn read_volume_header(file: &mut File) -> Result<Option<VolumeHeader>> {
+    let mut header = [0u8; VOLUME_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    let bytes_read = file.take(VOLUME_HEADER_SIZE).read(&mut header)?;
+    if bytes_read < VOLUME_HEADER_SIZE as usize || &header[..8] != VOLUME_MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(VolumeHeader {
+        archive_id: u64::from_be_bytes(header[8..16].try_into()?),
+        number: u32::from_be_bytes(header[16..20].try_into()?),
+        num_volumes: u32::from_be_bytes(header[20..24].try_into()?),
+    }))
+}
+
+/// Writes an archive as a sequence of volumes of at most `volume_size` bytes
+/// each (headers included), named after the archive with the volume number
+/// as extension. Volumes are created as data is written to them.
+pub struct VolumeWriter {
+    base_path: PathBuf,
+    volume_size: u64,
+    archive_id: u64,
+    volume: Option<BufWriter<File>>,
+    volume_len: u64,
+    num_volumes: u32,
+}
+
+impl VolumeWriter {
+    pub fn new(base_path: &Path, volume_size: u64) -> Result<Self> {
+        if volume_size <= VOLUME_HEADER_SIZE {
+            bail!(
+                "volume size must be larger than {} bytes",
+                VOLUME_HEADER_SIZE
+            );
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        Ok(VolumeWriter {
+            base_path: base_path.to_path_buf(),
+            volume_size,
+            archive_id: time.as_nanos() as u64 ^ ((std::process::id() as u64) << 32),
+            volume: None,
+            volume_len: 0,
+            num_volumes: 0,
+        })
+    }
+
+    fn start_volume(&mut self) -> std::io::Result<()> {
+        if let Some(volume) = self.volume.as_mut() {
+            volume.flush()?;
+        }
+        let number = self.num_volumes + 1;
+        let mut volume = BufWriter::new(File::create_new(volume_path(&self.base_path, number))?);
+        volume.write_all(VOLUME_MAGIC)?;
+        volume.write_all(&self.archive_id.to_be_bytes())?;
+        volume.write_all(&number.to_be_bytes())?;
+        volume.write_all(&0u32.to_be_bytes())?;
+        self.volume = Some(volume);
+        self.volume_len = VOLUME_HEADER_SIZE;
+        self.num_volumes = number;
+        Ok(())
+    }
+
+    /// Records the number of volumes in the first volume
+    pub fn finish(mut self) -> Result<()> {
+        if self.volume.is_none() {
+            self.start_volume()?;
+        }
+        self.flush()?;
+        let mut first_volume = OpenOptions::new()
+            .write(true)
+            .open(volume_path(&self.base_path, 1))?;
+        first_volume.seek(SeekFrom::Start(NUM_VOLUMES_OFFSET))?;
+        first_volume.write_all(&self.num_volumes.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.volume.is_none() || self.volume_len == self.volume_size {
+            self.start_volume()?;
+        }
+        let max_len = (buf.len() as u64).min(self.volume_size - self.volume_len) as usize;
+        let bytes_written = self.volume.as_mut().unwrap().write(&buf[..max_len])?;
+        self.volume_len += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.volume.as_mut() {
+            Some(volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the volumes of a split archive as a single stream
+pub struct VolumeReader {
+    volumes: Vec<File>,
+    /// offset in the archive of the part held by each volume, followed by
+    /// the size of the archive
+    volume_offsets: Vec<u64>,
+    position: u64,
+}
+
+impl VolumeReader {
+    /// Opens all the volumes of the archive at `base_path`, checking that
+    /// none is missing and that each holds the expected part of the archive
+    pub fn open(base_path: &Path) -> Result<Self> {
+        let first_path = volume_path(base_path, 1);
+        let mut first_volume = File::open(&first_path)?;
+        let Some(first_header) = read_volume_header(&mut first_volume)? else {
+            bail!(
+                "{} is not a volume of a split archive",
+                first_path.display()
+            );
+        };
+        if first_header.number != 1 {
+            bail!(
+                "{} holds volume {} of the archive, the volumes are out of order",
+                first_path.display(),
+                first_header.number
+            );
+        }
+        if first_header.num_volumes == 0 {
+            bail!(
+                "archive {} is incomplete, it was not finished",
+                base_path.display()
+            );
+        }
+
+        let mut volumes = vec![first_volume];
+        for number in 2..=first_header.num_volumes {
+            let path = volume_path(base_path, number);
+            if !path.exists() {
+                bail!(
+                    "volume {} of archive {} is missing ({} of {})",
+                    path.display(),
+                    base_path.display(),
+                    number,
+                    first_header.num_volumes
+                );
+            }
+            let mut volume = File::open(&path)?;
+            let Some(header) = read_volume_header(&mut volume)? else {
+                bail!("{} is not a volume of a split archive", path.display());
+            };
+            if header.archive_id != first_header.archive_id {
+                bail!(
+                    "{} is not a volume of archive {}",
+                    path.display(),
+                    base_path.display()
+                );
+            }
+            if header.number != number {
+                bail!(
+                    "{} holds volume {} of the archive, the volumes are out of order",
+                    path.display(),
+                    header.number
+                );
+            }
+            volumes.push(volume);
+        }
+
+        let mut volume_offsets = vec![0];
+        let mut offset = 0;
+        for volume in volumes.iter() {
+            offset += volume.metadata()?.len() - VOLUME_HEADER_SIZE;
+            volume_offsets.push(offset);
+        }
+        Ok(VolumeReader {
+            volumes,
+            volume_offsets,
+            position: 0,
+        })
+    }
+
+    pub fn num_volumes(&self) -> usize {
+        self.volumes.len()
+    }
+
+    /// Size of the volume files, headers included
+    pub fn len(&self) -> u64 {
+        self.volume_offsets[self.volumes.len()] + self.volumes.len() as u64 * VOLUME_HEADER_SIZE
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // index of the last volume starting at or before the position
+        let volume_index = self
+            .volume_offsets
+            .partition_point(|offset| *offset <= self.position)
+            - 1;
+        if volume_index >= self.volumes.len() {
+            return Ok(0);
+        }
+        let volume_start = self.volume_offsets[volume_index];
+        let volume_end = self.volume_offsets[volume_index + 1];
+        let volume = &mut self.volumes[volume_index];
+        volume.seek(SeekFrom::Start(
+            VOLUME_HEADER_SIZE + self.position - volume_start,
+        ))?;
+        let max_len = (buf.len() as u64).min(volume_end - self.position) as usize;
+        let bytes_read = volume.read(&mut buf[..max_len])?;
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let archive_len = self.volume_offsets[self.volumes.len()];
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => archive_len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// An archive opened for reading, held in a single file or split into volumes
+pub enum ArchiveFile {
+    Single(File),
+    Volumes(VolumeReader),
+}
+
+impl ArchiveFile {
+    /// Opens the archive at `path`, which may also name the base path of
+    /// a split archive or its first volume
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            if volume_path(path, 1).exists() {
+                return Ok(ArchiveFile::Volumes(VolumeReader::open(path)?));
+            }
+            if volume_path(path, 2).exists() {
+                bail!(
+                    "volume {} of archive {} is missing",
+                    volume_path(path, 1).display(),
+                    path.display()
+                );
+            }
+        }
+        let mut file = File::open(path)?;
+        let Some(header) = read_volume_header(&mut file)? else {
+            file.seek(SeekFrom::Start(0))?;
+            return Ok(ArchiveFile::Single(file));
+        };
+        match path.as_os_str().as_bytes().strip_suffix(b".001") {
+            Some(base_path) if header.number == 1 => Ok(ArchiveFile::Volumes(VolumeReader::open(
+                Path::new(OsStr::from_bytes(base_path)),
+            )?)),
+            _ => bail!(
+                "{} is volume {} of a split archive, open its first volume instead",
+                path.display(),
+                header.number
+            ),
+        }
+    }
+
+    /// Number of files holding the archive
+    pub fn num_volumes(&self) -> usize {
+        match self {
+            ArchiveFile::Single(_) => 1,
+            ArchiveFile::Volumes(volume_reader) => volume_reader.num_volumes(),
+        }
+    }
+
+    /// Size of the files holding the archive
+    pub fn len(&self) -> Result<u64> {
+        match self {
+            ArchiveFile::Single(file) => Ok(file.metadata()?.len()),
+            ArchiveFile::Volumes(volume_reader) => Ok(volume_reader.len()),
+        }
+    }
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ArchiveFile::Single(file) => file.read(buf),
+            ArchiveFile::Volumes(volume_reader) => volume_reader.read(buf),
+        }
+    }
+}
+
+impl Seek for ArchiveFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ArchiveFile::Single(file) => file.seek(pos),
+            ArchiveFile::Volumes(volume_reader) => volume_reader.seek(pos),
+        }
+    }
+}
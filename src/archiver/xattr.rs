@@ -0,0 +1,150 @@
+use anyhow::Result;
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+
+// Extended attributes of every namespace (`user.`, `trusted.`, `security.` and
+// `system.`) are archived. POSIX ACLs are stored by Linux as the
+// `system.posix_acl_access` and `system.posix_acl_default` attributes, so they are
+// archived along with the other attributes. Which namespaces can be restored depends
+// on the file system and on the privileges of the process extracting them.
+
+/// An extended attribute of an archived file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedAttribute {
+    pub name: OsString,
+    pub value: Vec<u8>,
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Calls `get` with a buffer grown until it holds the whole value, `get` returning
+/// the size of the value (when called with an empty buffer) or of the data written
+fn read_with_buffer<F>(mut get: F) -> std::io::Result<Vec<u8>>
+where
+    F: FnMut(&mut [u8]) -> isize,
+{
+    loop {
+        let size = get(&mut []);
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let size = get(&mut buffer);
+        if size >= 0 {
+            buffer.truncate(size as usize);
+            return Ok(buffer);
+        }
+        let error = std::io::Error::last_os_error();
+        // the value grew between both calls
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+    }
+}
+
+fn is_unsupported(error: &std::io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::ENOTSUP) | Some(libc::EPERM) | Some(libc::EACCES)
+    )
+}
+
+/// Reads the extended attributes of the file at `path` (or of the link itself if
+/// `path` is a symbolic link and `follow_symlinks` is not set). File systems without
+/// extended attributes yield none.
+pub fn read_xattrs(path: &Path, follow_symlinks: bool) -> Result<Vec<ExtendedAttribute>> {
+    let c_path = path_to_cstring(path)?;
+    let names = read_with_buffer(|buffer| unsafe {
+        let list = buffer.as_mut_ptr() as *mut libc::c_char;
+        if follow_symlinks {
+            libc::listxattr(c_path.as_ptr(), list, buffer.len())
+        } else {
+            libc::llistxattr(c_path.as_ptr(), list, buffer.len())
+        }
+    });
+    let names = match names {
+        Ok(names) => names,
+        Err(error) if is_unsupported(&error) => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut xattrs = Vec::new();
+    for name in names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let name = OsStr::from_bytes(name);
+        let c_name = CString::new(name.as_bytes())?;
+        let value = read_with_buffer(|buffer| unsafe {
+            let value = buffer.as_mut_ptr() as *mut libc::c_void;
+            if follow_symlinks {
+                libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), value, buffer.len())
+            } else {
+                libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), value, buffer.len())
+            }
+        });
+        match value {
+            Ok(value) => xattrs.push(ExtendedAttribute {
+                name: name.to_os_string(),
+                value,
+            }),
+            // the attribute was removed while reading the others
+            Err(error) if error.raw_os_error() == Some(libc::ENODATA) => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Namespace of the extended attribute `name`, such as `user.`
+fn namespace(name: &OsStr) -> &[u8] {
+    let name = name.as_bytes();
+    match name.iter().position(|byte| *byte == b'.') {
+        Some(end) => &name[..=end],
+        None => name,
+    }
+}
+
+/// Sets `xattrs` on the file at `path`, or on the link itself if `path` is a
+/// symbolic link. Once the file system or the privileges of the process do not allow
+/// to set an attribute, the attributes of its namespace are skipped with a warning.
+pub fn restore_xattrs(path: &Path, xattrs: &[ExtendedAttribute]) -> Result<()> {
+    let c_path = path_to_cstring(path)?;
+    let mut skipped_namespaces: Vec<&[u8]> = Vec::new();
+    for xattr in xattrs.iter() {
+        let namespace = namespace(&xattr.name);
+        if skipped_namespaces.contains(&namespace) {
+            continue;
+        }
+        let c_name = CString::new(xattr.name.clone().into_vec())?;
+        let status = unsafe {
+            libc::lsetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                xattr.value.as_ptr() as *const libc::c_void,
+                xattr.value.len(),
+                0,
+            )
+        };
+        if status != 0 {
+            let error = std::io::Error::last_os_error();
+            if !is_unsupported(&error) {
+                return Err(error.into());
+            }
+            eprintln!(
+                "skipping {} extended attributes of {}: {}",
+                OsStr::from_bytes(namespace).display(),
+                path.display(),
+                error
+            );
+            skipped_namespaces.push(namespace);
+        }
+    }
+    Ok(())
+}
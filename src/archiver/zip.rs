@@ -0,0 +1,846 @@
+use super::tar::normalize_name;
+use super::ArchiveEntry;
+use super::ArchiveIndex;
+use super::EntryKind;
+use super::EntryMetadata;
+use crate::checksum::crc32;
+use crate::checksum::update_crc32;
+use crate::codec;
+use crate::codec::Method;
+use crate::deflate;
+use anyhow::bail;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+
+// A ZIP file holds each entry as a local header followed by its data, then a central
+// directory with a header per entry (giving the offset of its local header) and an end
+// of central directory record locating it. Sizes and offsets that do not fit in 32 bits
+// (or counts in 16 bits) are set to all ones and given by ZIP64 extra fields and a ZIP64
+// end of central directory record. Entries written as a stream have their CRC and sizes
+// in a data descriptor after their data, and only the central directory is relied on.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE: usize = 56;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+const MAX_COMMENT_SIZE: usize = 0xffff;
+
+/// extra field holding the 64-bit sizes and offset
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// extra field holding the Unix mtime (Info-ZIP extended timestamp)
+const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+/// extra field holding the Unix uid and gid (Info-ZIP)
+const UNIX_OWNER_EXTRA_ID: u16 = 0x7875;
+
+const IS_ENCRYPTED: u16 = 0x0001;
+const HAS_DATA_DESCRIPTOR: u16 = 0x0008;
+const NAME_IS_UTF8: u16 = 0x0800;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// version needed to extract deflated entries and directories, and ZIP64 entries
+const VERSION_DEFLATE: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// host system of the "version made by" field whose external attributes hold a Unix mode
+const UNIX_HOST: u16 = 3;
+const MSDOS_READ_ONLY: u32 = 0x01;
+const MSDOS_DIRECTORY: u32 = 0x10;
+
+const FILE_TYPE_MASK: u32 = 0o170000;
+const REGULAR_FILE_TYPE: u32 = 0o100000;
+const DIRECTORY_TYPE: u32 = 0o040000;
+const SYMLINK_TYPE: u32 = 0o120000;
+
+/// size above which the data of an entry is not read at once but deflated one piece
+/// of this size at a time, its CRC and sizes following it in a data descriptor
+const MAX_BUFFERED_SIZE: u64 = 1 << 24;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads a little-endian unsigned number of 1 to 8 bytes
+fn uint_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+/// Converts a Unix time to an MS-DOS date and time in local time, as held by the
+/// headers (with a 2-second resolution, clamped to the years 1980 to 2107)
+fn to_dos_date_time(time: i64) -> (u16, u16) {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() || tm.tm_year < 80 {
+        return ((1 << 5) | 1, 0);
+    }
+    if tm.tm_year > 207 {
+        return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29);
+    }
+    let date = ((tm.tm_year - 80) << 9) | ((tm.tm_mon + 1) << 5) | tm.tm_mday;
+    let time = (tm.tm_hour << 11) | (tm.tm_min << 5) | (tm.tm_sec / 2);
+    (date as u16, time as u16)
+}
+
+/// Converts an MS-DOS date and time in local time to a Unix time
+fn from_dos_date_time(date: u16, time: u16) -> i64 {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (date >> 9) as i32 + 80;
+    tm.tm_mon = ((date >> 5) & 0xf) as i32 - 1;
+    tm.tm_mday = (date & 0x1f) as i32;
+    tm.tm_hour = (time >> 11) as i32;
+    tm.tm_min = ((time >> 5) & 0x3f) as i32;
+    tm.tm_sec = ((time & 0x1f) * 2) as i32;
+    tm.tm_isdst = -1;
+    unsafe { libc::mktime(&mut tm) as i64 }
+}
+
+/// Returns the data of each extra field of `extra` by identifier
+fn extra_fields(extra: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut fields = Vec::new();
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let id = u16_at(rest, 0);
+        let size = u16_at(rest, 2) as usize;
+        if rest.len() < 4 + size {
+            break;
+        }
+        fields.push((id, &rest[4..4 + size]));
+        rest = &rest[4 + size..];
+    }
+    fields
+}
+
+fn push_extra_field(extra: &mut Vec<u8>, id: u16, data: &[u8]) {
+    extra.extend_from_slice(&id.to_le_bytes());
+    extra.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    extra.extend_from_slice(data);
+}
+
+/// Returns true if `archive` starts with a local header or is an empty ZIP file
+pub fn is_zip<R: Read + Seek>(archive: &mut R) -> Result<bool> {
+    archive.seek(SeekFrom::Start(0))?;
+    let mut signature = [0u8; 4];
+    let bytes_read = archive.take(4).read(&mut signature)?;
+    archive.seek(SeekFrom::Start(0))?;
+    Ok(bytes_read == 4
+        && [LOCAL_HEADER_SIGNATURE, END_OF_CENTRAL_DIRECTORY_SIGNATURE]
+            .contains(&u32::from_le_bytes(signature)))
+}
+
+fn read_at<R: Read + Seek>(archive: &mut R, offset: u64, length: usize) -> Result<Vec<u8>> {
+    archive.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    archive.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        bail!("ZIP file is truncated");
+    }
+    Ok(bytes)
+}
+
+/// Finds the end of central directory record, returning the number of entries
+/// and the size and offset of the central directory
+fn read_end_of_central_directory<R: Read + Seek>(archive: &mut R) -> Result<(u64, u64, u64)> {
+    let archive_len = archive.seek(SeekFrom::End(0))?;
+    // the record is followed by a comment of up to 64 KiB
+    let tail_len = archive_len.min((END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_SIZE) as u64);
+    let tail_offset = archive_len - tail_len;
+    let tail = read_at(archive, tail_offset, tail_len as usize)?;
+    let record_start = match tail.len().checked_sub(END_OF_CENTRAL_DIRECTORY_SIZE) {
+        Some(last_start) => (0..=last_start)
+            .rev()
+            .find(|start| u32_at(&tail, *start) == END_OF_CENTRAL_DIRECTORY_SIGNATURE),
+        None => None,
+    };
+    let Some(record_start) = record_start else {
+        bail!("ZIP file has no end of central directory record");
+    };
+    let record = &tail[record_start..];
+    let num_entries = u16_at(record, 10);
+    let directory_size = u32_at(record, 12);
+    let directory_offset = u32_at(record, 16);
+    if num_entries != u16::MAX && directory_size != u32::MAX && directory_offset != u32::MAX {
+        return Ok((
+            num_entries as u64,
+            directory_size as u64,
+            directory_offset as u64,
+        ));
+    }
+
+    // the values are held by the ZIP64 record, found through the locator preceding this one
+    let record_offset = tail_offset + record_start as u64;
+    let Some(locator_offset) = record_offset.checked_sub(ZIP64_LOCATOR_SIZE as u64) else {
+        bail!("ZIP64 end of central directory locator is missing");
+    };
+    let locator = read_at(archive, locator_offset, ZIP64_LOCATOR_SIZE)?;
+    if u32_at(&locator, 0) != ZIP64_LOCATOR_SIGNATURE {
+        bail!("ZIP64 end of central directory locator is missing");
+    }
+    let zip64_record = read_at(
+        archive,
+        u64_at(&locator, 8),
+        ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE,
+    )?;
+    if u32_at(&zip64_record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+        bail!("ZIP64 end of central directory record is missing");
+    }
+    Ok((
+        u64_at(&zip64_record, 32),
+        u64_at(&zip64_record, 40),
+        u64_at(&zip64_record, 48),
+    ))
+}
+
+/// Reads the central directory of a ZIP file as the index of an archive. Stored and
+/// deflated entries are supported, the offset of each entry being that of its data.
+/// The Unix mode, mtime and owner are read from the external attributes and the
+/// extra fields written by Info-ZIP, falling back to the MS-DOS attributes and time.
+pub fn read_index<R: Read + Seek>(archive: &mut R) -> Result<ArchiveIndex> {
+    let (num_entries, directory_size, directory_offset) = read_end_of_central_directory(archive)?;
+    let directory = read_at(archive, directory_offset, directory_size as usize)?;
+    let mut entries = Vec::new();
+    let mut rest = directory.as_slice();
+    for _ in 0..num_entries {
+        if rest.len() < CENTRAL_HEADER_SIZE || u32_at(rest, 0) != CENTRAL_HEADER_SIGNATURE {
+            bail!("ZIP central directory is corrupted");
+        }
+        let header = &rest[..CENTRAL_HEADER_SIZE];
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        let record_len = CENTRAL_HEADER_SIZE + name_len + extra_len + comment_len;
+        if rest.len() < record_len {
+            bail!("ZIP central directory is corrupted");
+        }
+        let raw_name = &rest[CENTRAL_HEADER_SIZE..CENTRAL_HEADER_SIZE + name_len];
+        let extra =
+            &rest[CENTRAL_HEADER_SIZE + name_len..CENTRAL_HEADER_SIZE + name_len + extra_len];
+        rest = &rest[record_len..];
+
+        let name = normalize_name(raw_name);
+        let flags = u16_at(header, 8);
+        if flags & IS_ENCRYPTED != 0 {
+            bail!(
+                "entry {} is encrypted, which is not supported",
+                name.display()
+            );
+        }
+        let method = match u16_at(header, 10) {
+            STORED => Method::Store,
+            DEFLATED => Method::Deflate,
+            method => bail!(
+                "entry {} uses unsupported compression method {}",
+                name.display(),
+                method
+            ),
+        };
+        let mut mtime = from_dos_date_time(u16_at(header, 14), u16_at(header, 12));
+        let data_crc32 = u32_at(header, 16);
+        let mut stored_size = u32_at(header, 20) as u64;
+        let mut size = u32_at(header, 24) as u64;
+        let mut local_header_offset = u32_at(header, 42) as u64;
+        let mut uid = None;
+        let mut gid = None;
+        for (id, data) in extra_fields(extra) {
+            match id {
+                // only the values set to all ones in the header are present, in this order
+                ZIP64_EXTRA_ID => {
+                    let mut values = data.chunks_exact(8).map(uint_le);
+                    for field in [&mut size, &mut stored_size, &mut local_header_offset] {
+                        if *field == u32::MAX as u64 {
+                            match values.next() {
+                                Some(value) => *field = value,
+                                None => bail!("entry {} is corrupted", name.display()),
+                            }
+                        }
+                    }
+                }
+                EXTENDED_TIMESTAMP_EXTRA_ID if data.len() >= 5 && data[0] & 0x01 != 0 => {
+                    mtime = i32::from_le_bytes(data[1..5].try_into()?) as i64;
+                }
+                UNIX_OWNER_EXTRA_ID if data.len() >= 2 && data[0] == 1 => {
+                    let uid_len = data[1] as usize;
+                    let Some(gid_len) = data.get(2 + uid_len) else {
+                        continue;
+                    };
+                    let gid_start = 3 + uid_len;
+                    if data.len() >= gid_start + *gid_len as usize {
+                        uid = Some(uint_le(&data[2..2 + uid_len]) as u32);
+                        gid = Some(uint_le(&data[gid_start..gid_start + *gid_len as usize]) as u32);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let external_attributes = u32_at(header, 38);
+        let unix_mode = if u16_at(header, 4) >> 8 == UNIX_HOST {
+            external_attributes >> 16
+        } else {
+            0
+        };
+        let kind = if unix_mode & FILE_TYPE_MASK == SYMLINK_TYPE {
+            EntryKind::Symlink
+        } else if unix_mode & FILE_TYPE_MASK == DIRECTORY_TYPE
+            || raw_name.ends_with(b"/")
+            || external_attributes & MSDOS_DIRECTORY != 0
+        {
+            EntryKind::Directory
+        } else {
+            EntryKind::File
+        };
+        let mode = match (unix_mode & 0o7777, kind) {
+            (0, EntryKind::Directory) => 0o755,
+            (0, _) if external_attributes & MSDOS_READ_ONLY != 0 => 0o444,
+            (0, _) => 0o644,
+            (mode, _) => mode,
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        // the local header may have extra fields of its own, the data follows them
+        let local_header = read_at(archive, local_header_offset, LOCAL_HEADER_SIZE)?;
+        if u32_at(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+            bail!("local header of entry {} is missing", name.display());
+        }
+        let offset = local_header_offset
+            + (LOCAL_HEADER_SIZE + u16_at(&local_header, 26) as usize) as u64
+            + u16_at(&local_header, 28) as u64;
+        let mut entry = ArchiveEntry {
+            name,
+            kind,
+            link_target: OsString::new(),
+            method,
+            size,
+            stored_size,
+            offset,
+            solid_block: None,
+            metadata: Some(EntryMetadata {
+                mode,
+                mtime,
+                atime: mtime,
+                uid,
+                gid,
+                ..Default::default()
+            }),
+            data_regions: None,
+            duplicate: false,
+            chunks: None,
+            hash: None,
+            unchanged: false,
+            crc32: Some(data_crc32),
+        };
+        // the target of a symbolic link is held as its data
+        if kind == EntryKind::Symlink {
+            let stored_data = read_at(archive, offset, stored_size as usize)?;
+            let target = codec::decompress(method, &stored_data)?;
+            if target.len() as u64 != size || crc32(&target) != data_crc32 {
+                bail!("entry {} is corrupted", entry.name.display());
+            }
+            entry.link_target = OsString::from_vec(target);
+        }
+        if kind != EntryKind::File {
+            entry.size = 0;
+            entry.stored_size = 0;
+            entry.crc32 = None;
+        }
+        entries.push(entry);
+    }
+    Ok(ArchiveIndex {
+        solid_blocks: Vec::new(),
+        entries,
+        deleted_names: Vec::new(),
+        previous_archive: None,
+    })
+}
+
+/// Writes a ZIP file, compressing the entries with DEFLATE (or storing them when that
+/// does not make them smaller) and recording their Unix mode, mtime and owner. Entries
+/// larger than `MAX_BUFFERED_SIZE` bytes are written as a stream: they are deflated
+/// even if that does not make them smaller, and followed by a data descriptor.
+pub struct ZipWriter<W: Write> {
+    writer: W,
+    position: u64,
+    central_directory: Vec<u8>,
+    num_entries: u64,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ZipWriter {
+            writer,
+            position: 0,
+            central_directory: Vec::new(),
+            num_entries: 0,
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Adds an entry whose contents are the `size` bytes read from `reader`: the data
+    /// of a file, the target of a symbolic link or nothing for a directory. Entries are
+    /// deflated unless `method` is `Method::Store`.
+    pub fn add_entry<R: Read>(
+        &mut self,
+        name: &OsStr,
+        kind: EntryKind,
+        metadata: &EntryMetadata,
+        method: Method,
+        size: u64,
+        reader: &mut R,
+    ) -> Result<()> {
+        let mut name = name.as_bytes().to_vec();
+        let file_type = match kind {
+            EntryKind::Directory => {
+                name.push(b'/');
+                DIRECTORY_TYPE
+            }
+            EntryKind::Symlink => SYMLINK_TYPE,
+            EntryKind::File => REGULAR_FILE_TYPE,
+            EntryKind::HardLink => bail!("ZIP files cannot hold hard links"),
+        };
+        let is_streamed = size > MAX_BUFFERED_SIZE;
+        let mut data = Vec::new();
+        let mut compressed_data = None;
+        if !is_streamed {
+            reader.take(size).read_to_end(&mut data)?;
+            if data.len() as u64 != size {
+                bail!(
+                    "entry {} was truncated while archiving",
+                    name.escape_ascii()
+                );
+            }
+            if method != Method::Store && !data.is_empty() {
+                let deflated_data = deflate::compress(&data)?;
+                if deflated_data.len() < data.len() {
+                    compressed_data = Some(deflated_data);
+                }
+            }
+        }
+        let zip_method = if compressed_data.is_some() || (is_streamed && method != Method::Store) {
+            DEFLATED
+        } else {
+            STORED
+        };
+        // the size of data deflated as a stream is only known after it is written, but
+        // its blocks are stored when deflating does not make them smaller
+        let max_stored_size = match &compressed_data {
+            Some(compressed_data) => compressed_data.len() as u64,
+            None if zip_method == DEFLATED => size + size / 1024 + 1024,
+            None => size,
+        };
+        let local_header_offset = self.position;
+        let has_zip64_sizes = max_stored_size >= u32::MAX as u64 || size >= u32::MAX as u64;
+        let has_zip64_offset = local_header_offset >= u32::MAX as u64;
+        let version_needed = if has_zip64_sizes || has_zip64_offset {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFLATE
+        };
+        let mut flags = if std::str::from_utf8(&name).is_ok() {
+            NAME_IS_UTF8
+        } else {
+            0
+        };
+        if is_streamed {
+            flags |= HAS_DATA_DESCRIPTOR;
+        }
+        let (dos_date, dos_time) = to_dos_date_time(metadata.mtime);
+
+        // extra fields shared by both headers, the mtime only fits if it is a 32-bit time
+        let mut extra = Vec::new();
+        if let Ok(mtime) = i32::try_from(metadata.mtime) {
+            let mut timestamp = vec![0x01];
+            timestamp.extend_from_slice(&mtime.to_le_bytes());
+            push_extra_field(&mut extra, EXTENDED_TIMESTAMP_EXTRA_ID, &timestamp);
+        }
+        if let (Some(uid), Some(gid)) = (metadata.uid, metadata.gid) {
+            let mut owner = vec![1, 4];
+            owner.extend_from_slice(&uid.to_le_bytes());
+            owner.push(4);
+            owner.extend_from_slice(&gid.to_le_bytes());
+            push_extra_field(&mut extra, UNIX_OWNER_EXTRA_ID, &owner);
+        }
+        // the CRC and sizes of a streamed entry are left as zeros in its local header
+        let (local_crc, local_size, local_stored_size) = match &compressed_data {
+            _ if is_streamed => (0, 0, 0),
+            Some(compressed_data) => (crc32(&data), size, compressed_data.len() as u64),
+            None => (crc32(&data), size, size),
+        };
+        let mut local_extra = extra.clone();
+        if has_zip64_sizes {
+            let mut sizes = local_size.to_le_bytes().to_vec();
+            sizes.extend_from_slice(&local_stored_size.to_le_bytes());
+            push_extra_field(&mut local_extra, ZIP64_EXTRA_ID, &sizes);
+        }
+        let (header_size, header_stored_size) = if has_zip64_sizes {
+            (u32::MAX, u32::MAX)
+        } else {
+            (local_size as u32, local_stored_size as u32)
+        };
+
+        let mut local_header = Vec::with_capacity(LOCAL_HEADER_SIZE);
+        local_header.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        local_header.extend_from_slice(&version_needed.to_le_bytes());
+        local_header.extend_from_slice(&flags.to_le_bytes());
+        local_header.extend_from_slice(&zip_method.to_le_bytes());
+        local_header.extend_from_slice(&dos_time.to_le_bytes());
+        local_header.extend_from_slice(&dos_date.to_le_bytes());
+        local_header.extend_from_slice(&local_crc.to_le_bytes());
+        local_header.extend_from_slice(&header_stored_size.to_le_bytes());
+        local_header.extend_from_slice(&header_size.to_le_bytes());
+        local_header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local_header.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        self.write_bytes(&local_header)?;
+        self.write_bytes(&name)?;
+        self.write_bytes(&local_extra)?;
+        let (crc, stored_size) = if is_streamed {
+            let (crc, stored_size) = self.write_streamed_data(&name, zip_method, size, reader)?;
+            let mut descriptor = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes().to_vec();
+            descriptor.extend_from_slice(&crc.to_le_bytes());
+            if has_zip64_sizes {
+                descriptor.extend_from_slice(&stored_size.to_le_bytes());
+                descriptor.extend_from_slice(&size.to_le_bytes());
+            } else {
+                descriptor.extend_from_slice(&(stored_size as u32).to_le_bytes());
+                descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+            }
+            self.write_bytes(&descriptor)?;
+            (crc, stored_size)
+        } else {
+            let stored_data = compressed_data.as_deref().unwrap_or(&data);
+            self.write_bytes(stored_data)?;
+            (local_crc, local_stored_size)
+        };
+
+        let mut central_extra = extra;
+        if has_zip64_sizes || has_zip64_offset {
+            let mut values = Vec::new();
+            if has_zip64_sizes {
+                values.extend_from_slice(&size.to_le_bytes());
+                values.extend_from_slice(&stored_size.to_le_bytes());
+            }
+            if has_zip64_offset {
+                values.extend_from_slice(&local_header_offset.to_le_bytes());
+            }
+            push_extra_field(&mut central_extra, ZIP64_EXTRA_ID, &values);
+        }
+        let (header_size, header_stored_size) = if has_zip64_sizes {
+            (u32::MAX, u32::MAX)
+        } else {
+            (size as u32, stored_size as u32)
+        };
+
+        let mut external_attributes = (file_type | (metadata.mode & 0o7777)) << 16;
+        if kind == EntryKind::Directory {
+            external_attributes |= MSDOS_DIRECTORY;
+        }
+        let header_offset = if has_zip64_offset {
+            u32::MAX
+        } else {
+            local_header_offset as u32
+        };
+        let directory = &mut self.central_directory;
+        directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        directory.extend_from_slice(&((UNIX_HOST << 8) | VERSION_ZIP64).to_le_bytes());
+        directory.extend_from_slice(&version_needed.to_le_bytes());
+        directory.extend_from_slice(&flags.to_le_bytes());
+        directory.extend_from_slice(&zip_method.to_le_bytes());
+        directory.extend_from_slice(&dos_time.to_le_bytes());
+        directory.extend_from_slice(&dos_date.to_le_bytes());
+        directory.extend_from_slice(&crc.to_le_bytes());
+        directory.extend_from_slice(&header_stored_size.to_le_bytes());
+        directory.extend_from_slice(&header_size.to_le_bytes());
+        directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        directory.extend_from_slice(&(central_extra.len() as u16).to_le_bytes());
+        // comment length, disk number and internal attributes
+        directory.extend_from_slice(&[0u8; 6]);
+        directory.extend_from_slice(&external_attributes.to_le_bytes());
+        directory.extend_from_slice(&header_offset.to_le_bytes());
+        directory.extend_from_slice(&name);
+        directory.extend_from_slice(&central_extra);
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    /// Writes the `size` bytes of data read from `reader` one piece at a time, each
+    /// deflated as a part of the stream if `zip_method` is `DEFLATED`, returning
+    /// the CRC of the data and the size of the data written
+    fn write_streamed_data<R: Read>(
+        &mut self,
+        name: &[u8],
+        zip_method: u16,
+        size: u64,
+        reader: &mut R,
+    ) -> Result<(u32, u64)> {
+        let mut crc = 0;
+        let mut stored_size = 0;
+        let mut bytes_read = 0;
+        let mut previous_piece = Vec::new();
+        while bytes_read < size {
+            let mut piece = Vec::new();
+            reader
+                .take(MAX_BUFFERED_SIZE.min(size - bytes_read))
+                .read_to_end(&mut piece)?;
+            if piece.is_empty() {
+                bail!(
+                    "entry {} was truncated while archiving",
+                    name.escape_ascii()
+                );
+            }
+            bytes_read += piece.len() as u64;
+            crc = update_crc32(crc, &piece);
+            if zip_method == DEFLATED {
+                let deflated_piece =
+                    deflate::compress_part(&piece, &previous_piece, bytes_read == size)?;
+                self.write_bytes(&deflated_piece)?;
+                stored_size += deflated_piece.len() as u64;
+            } else {
+                self.write_bytes(&piece)?;
+                stored_size += piece.len() as u64;
+            }
+            previous_piece = piece;
+        }
+        Ok((crc, stored_size))
+    }
+
+    /// Writes the central directory and the end of central directory records
+    pub fn finish(mut self) -> Result<W> {
+        let directory_offset = self.position;
+        let directory = std::mem::take(&mut self.central_directory);
+        self.write_bytes(&directory)?;
+        let directory_size = directory.len() as u64;
+        let needs_zip64 = self.num_entries >= u16::MAX as u64
+            || directory_size >= u32::MAX as u64
+            || directory_offset >= u32::MAX as u64;
+        if needs_zip64 {
+            let zip64_record_offset = self.position;
+            let mut record = Vec::with_capacity(ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE);
+            record.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+            // size of the rest of the record
+            record.extend_from_slice(
+                &(ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE as u64 - 12).to_le_bytes(),
+            );
+            record.extend_from_slice(&((UNIX_HOST << 8) | VERSION_ZIP64).to_le_bytes());
+            record.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            // number of this disk and of the disk holding the central directory
+            record.extend_from_slice(&[0u8; 8]);
+            record.extend_from_slice(&self.num_entries.to_le_bytes());
+            record.extend_from_slice(&self.num_entries.to_le_bytes());
+            record.extend_from_slice(&directory_size.to_le_bytes());
+            record.extend_from_slice(&directory_offset.to_le_bytes());
+            record.extend_from_slice(&ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+            record.extend_from_slice(&0u32.to_le_bytes());
+            record.extend_from_slice(&zip64_record_offset.to_le_bytes());
+            record.extend_from_slice(&1u32.to_le_bytes());
+            self.write_bytes(&record)?;
+        }
+        let num_entries = self.num_entries.min(u16::MAX as u64) as u16;
+        let mut record = Vec::with_capacity(END_OF_CENTRAL_DIRECTORY_SIZE);
+        record.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        // number of this disk and of the disk holding the central directory
+        record.extend_from_slice(&[0u8; 4]);
+        record.extend_from_slice(&num_entries.to_le_bytes());
+        record.extend_from_slice(&num_entries.to_le_bytes());
+        record.extend_from_slice(&(directory_size.min(u32::MAX as u64) as u32).to_le_bytes());
+        record.extend_from_slice(&(directory_offset.min(u32::MAX as u64) as u32).to_le_bytes());
+        // comment length
+        record.extend_from_slice(&0u16.to_le_bytes());
+        self.write_bytes(&record)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archiver::archive_files;
+    use crate::archiver::list_entries;
+    use crate::archiver::unarchive_files;
+    use crate::archiver::ArchiveFormat;
+    use crate::archiver::ArchiveOptions;
+    use crate::archiver::ArchiveReader;
+    use crate::archiver::EntryKind;
+    use crate::archiver::UnarchiveOptions;
+    use crate::codec::Method;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs::create_dir_all;
+    use std::fs::metadata;
+    use std::fs::read;
+    use std::fs::read_link;
+    use std::fs::remove_dir_all;
+    use std::fs::set_permissions;
+    use std::fs::write;
+    use std::fs::Permissions;
+    use std::io::Cursor;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_zip_files() -> Result<()> {
+        let hello = b"hello zip\n".repeat(200);
+
+        // written by Info-ZIP 3.0 with zip -r -y
+        let entries = list_entries("testdata/plain.zip")?;
+        let entry = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entry("project/empty").kind, EntryKind::Directory);
+        assert_eq!(
+            entry("project/empty").metadata.as_ref().unwrap().mode,
+            0o700
+        );
+        assert_eq!(entry("project/link").kind, EntryKind::Symlink);
+        assert_eq!(entry("project/link").link_target, "hello.txt");
+        assert_eq!(entry("project/hello.txt").method, Method::Deflate);
+        assert_eq!(entry("project/random.bin").method, Method::Store);
+        let run_metadata = entry("project/run.sh").metadata.clone().unwrap();
+        assert_eq!((run_metadata.mode, run_metadata.mtime), (0o755, 1672628645));
+        let hello_metadata = entry("project/hello.txt").metadata.clone().unwrap();
+        assert_eq!(hello_metadata.mtime, 1709528767);
+        assert_eq!((hello_metadata.uid, hello_metadata.gid), (Some(0), Some(0)));
+
+        // written as a stream by Python's zipfile, with data descriptors
+        let mut archive_reader = ArchiveReader::new(std::fs::File::open("testdata/streamed.zip")?)?;
+        let mut data = Vec::new();
+        archive_reader.extract_entry(0, &mut data)?;
+        assert_eq!(data, hello);
+        data.clear();
+        archive_reader.extract_entry(1, &mut data)?;
+        assert_eq!(data, b"#!/bin/sh\necho run\n");
+        assert_eq!(
+            archive_reader.entries()[1].metadata.as_ref().unwrap().mode,
+            0o755
+        );
+        // without a Unix owner extra field the owner is left unknown
+        let streamed_metadata = archive_reader.entries()[1].metadata.clone().unwrap();
+        assert_eq!((streamed_metadata.uid, streamed_metadata.gid), (None, None));
+
+        // sizes and offsets given by ZIP64 extra fields and end of central directory record
+        let mut archive_reader = ArchiveReader::new(std::fs::File::open("testdata/zip64.zip")?)?;
+        assert_eq!(archive_reader.entries()[0].name, "zip64/data.txt");
+        assert_eq!(archive_reader.entries()[0].size, 600);
+        data.clear();
+        archive_reader.extract_entry(0, &mut data)?;
+        assert_eq!(data, b"zip64 ".repeat(100));
+
+        // a damaged entry is detected by its CRC-32
+        let mut damaged_zip = read("testdata/plain.zip")?;
+        let random_offset = entry("project/random.bin").offset as usize;
+        damaged_zip[random_offset + 10] ^= 0xff;
+        let mut archive_reader = ArchiveReader::new(Cursor::new(damaged_zip))?;
+        let random_index = archive_reader
+            .entries()
+            .iter()
+            .position(|entry| entry.name == "project/random.bin")
+            .unwrap();
+        assert!(archive_reader
+            .extract_entry(random_index, &mut Vec::new())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_round_trip() -> Result<()> {
+        create_dir_all("zip_test/src/docs")?;
+        write("zip_test/src/docs/notes.txt", "notes ".repeat(1000))?;
+        write("zip_test/src/empty.txt", "")?;
+        write("zip_test/src/script.sh", "#!/bin/sh\n")?;
+        set_permissions("zip_test/src/script.sh", Permissions::from_mode(0o750))?;
+        symlink("docs/notes.txt", "zip_test/src/link")?;
+
+        for (format, path) in [
+            (
+                ArchiveFormat::from_path("zip_test/deflated.zip"),
+                "zip_test/deflated.zip",
+            ),
+            (ArchiveFormat::from_name("zip")?, "zip_test/stored.archive"),
+        ] {
+            assert_eq!(format, ArchiveFormat::Zip);
+            let method = if path.ends_with(".zip") {
+                Method::Huffmann
+            } else {
+                Method::Store
+            };
+            let options = ArchiveOptions {
+                format,
+                method,
+                ..Default::default()
+            };
+            archive_files(&["zip_test/src"], path, &options)?;
+            let entries = list_entries(path)?;
+            let notes = entries
+                .iter()
+                .find(|entry| entry.name == "src/docs/notes.txt")
+                .unwrap();
+            let expected_method = if method == Method::Store {
+                Method::Store
+            } else {
+                Method::Deflate
+            };
+            assert_eq!(notes.method, expected_method);
+
+            let output_dir = format!("{}.output", path);
+            unarchive_files(path, &output_dir, &UnarchiveOptions::default())?;
+            assert_eq!(
+                read(format!("{}/src/docs/notes.txt", output_dir))?,
+                "notes ".repeat(1000).as_bytes()
+            );
+            assert_eq!(read(format!("{}/src/empty.txt", output_dir))?, b"");
+            let script_metadata = metadata(format!("{}/src/script.sh", output_dir))?;
+            assert_eq!(script_metadata.mode() & 0o7777, 0o750);
+            assert_eq!(
+                script_metadata.mtime(),
+                metadata("zip_test/src/script.sh")?.mtime()
+            );
+            assert_eq!(
+                read_link(format!("{}/src/link", output_dir))?,
+                std::path::Path::new("docs/notes.txt")
+            );
+        }
+        assert!(archive_files(
+            &["zip_test/src"],
+            "zip_test/solid.zip",
+            &ArchiveOptions {
+                format: ArchiveFormat::Zip,
+                solid: true,
+                ..Default::default()
+            }
+        )
+        .is_err());
+
+        remove_dir_all("zip_test")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+/// Table of the CRC-32 (IEEE 802.3, reflected polynomial 0xedb88320) of each byte value
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 of `data`, as used by ZIP and gzip
+pub fn crc32(data: &[u8]) -> u32 {
+    update_crc32(0, data)
+}
+
+/// CRC-32 of the data whose CRC-32 is `crc` followed by `data`,
+/// for data checked one piece at a time
+pub fn update_crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data.iter() {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Largest prime below 2^16, the modulus of the Adler-32 sums
+const ADLER32_MODULUS: u32 = 65521;
+
+/// Number of bytes that can be summed before the sums may overflow 32 bits
+const ADLER32_MAX_RUN: usize = 5552;
+
+/// Adler-32 of `data`, as used by zlib
+pub fn adler32(data: &[u8]) -> u32 {
+    update_adler32(1, data)
+}
+
+/// Adler-32 of the data whose Adler-32 is `adler` followed by `data`,
+/// for data checked one piece at a time
+pub fn update_adler32(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    for run in data.chunks(ADLER32_MAX_RUN) {
+        for byte in run.iter() {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= ADLER32_MODULUS;
+        b %= ADLER32_MODULUS;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::adler32;
+    use super::crc32;
+    use super::update_adler32;
+    use super::update_crc32;
+    use anyhow::Ok;
+    use anyhow::Result;
+
+    #[test]
+    fn test_crc32() -> Result<()> {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414fa339
+        );
+        assert_eq!(update_crc32(crc32(b"12345"), b"6789"), 0xcbf43926);
+        Ok(())
+    }
+
+    #[test]
+    fn test_adler32() -> Result<()> {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // long runs of large bytes, where the sums have to be reduced
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a302c);
+        assert_eq!(update_adler32(adler32(b"Wiki"), b"pedia"), 0x11e60398);
+        Ok(())
+    }
+}
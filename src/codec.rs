@@ -0,0 +1,130 @@
+use crate::deflate;
+use crate::huffmann;
+use crate::lzss;
+use crate::lzss_huffman;
+use crate::lzw;
+use crate::lzw_huffman;
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+
+/// A compression method that can be applied to data held in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Store,
+    Huffmann,
+    Lzw,
+    Deflate,
+    Lzss,
+    LzssHuffman,
+    LzwHuffman,
+}
+
+impl Method {
+    pub const NAMES: [&'static str; 7] = [
+        "store",
+        "huffmann",
+        "lzw",
+        "deflate",
+        "lzss",
+        "lzss+huffman",
+        "lzw+huffman",
+    ];
+
+    pub fn from_name(name: &str) -> Result<Method> {
+        match name {
+            "store" => Ok(Method::Store),
+            "huffmann" => Ok(Method::Huffmann),
+            "lzw" => Ok(Method::Lzw),
+            "deflate" => Ok(Method::Deflate),
+            "lzss" => Ok(Method::Lzss),
+            "lzss+huffman" => Ok(Method::LzssHuffman),
+            "lzw+huffman" => Ok(Method::LzwHuffman),
+            _ => bail!("unknown compression method {}", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Store => "store",
+            Method::Huffmann => "huffmann",
+            Method::Lzw => "lzw",
+            Method::Deflate => "deflate",
+            Method::Lzss => "lzss",
+            Method::LzssHuffman => "lzss+huffman",
+            Method::LzwHuffman => "lzw+huffman",
+        }
+    }
+
+    /// Identifier of the method, as written to archives
+    pub fn id(&self) -> u8 {
+        match self {
+            Method::Store => 0,
+            Method::Huffmann => 1,
+            Method::Lzw => 2,
+            Method::Deflate => 3,
+            Method::Lzss => 4,
+            Method::LzssHuffman => 5,
+            Method::LzwHuffman => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Method> {
+        match id {
+            0 => Ok(Method::Store),
+            1 => Ok(Method::Huffmann),
+            2 => Ok(Method::Lzw),
+            3 => Ok(Method::Deflate),
+            4 => Ok(Method::Lzss),
+            5 => Ok(Method::LzssHuffman),
+            6 => Ok(Method::LzwHuffman),
+            _ => bail!("unknown compression method id {}", id),
+        }
+    }
+}
+
+pub fn compress(method: Method, data: &[u8]) -> Result<Vec<u8>> {
+    match method {
+        Method::Store => Ok(data.to_vec()),
+        Method::Huffmann => huffmann::compress(data),
+        Method::Lzw => lzw::compress(data),
+        Method::Deflate => deflate::compress(data),
+        Method::Lzss => lzss::compress(data),
+        Method::LzssHuffman => lzss_huffman::compress(data),
+        Method::LzwHuffman => lzw_huffman::compress(data),
+    }
+}
+
+pub fn decompress(method: Method, compressed_data: &[u8]) -> Result<Vec<u8>> {
+    match method {
+        Method::Store => Ok(compressed_data.to_vec()),
+        Method::Huffmann => huffmann::decompress(compressed_data),
+        Method::Lzw => lzw::decompress(compressed_data),
+        Method::Deflate => deflate::decompress(compressed_data),
+        Method::Lzss => lzss::decompress(compressed_data),
+        Method::LzssHuffman => lzss_huffman::decompress(compressed_data),
+        Method::LzwHuffman => lzw_huffman::decompress(compressed_data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::decompress;
+    use super::Method;
+    use anyhow::Ok;
+    use anyhow::Result;
+
+    #[test]
+    fn test_codec_methods() -> Result<()> {
+        let data = b"a codec round trip, a codec round trip, a codec round trip";
+        for name in Method::NAMES {
+            let method = Method::from_name(name)?;
+            assert_eq!(method.name(), name);
+            assert_eq!(Method::from_id(method.id())?, method);
+            assert_eq!(decompress(method, &compress(method, data)?)?, data);
+        }
+        Ok(())
+    }
+}
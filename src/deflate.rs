@@ -0,0 +1,892 @@
+use crate::huffmann;
+use crate::huffmann::canonical_codes;
+use crate::huffmann::HuffmanDecoder;
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+use bitstream_io::BitRead;
+use bitstream_io::BitReader;
+use bitstream_io::BitWrite;
+use bitstream_io::BitWriter;
+use bitstream_io::LittleEndian;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+
+// DEFLATE streams (RFC 1951) are made of blocks, each starting with a bit telling
+// whether it is the last block and two bits giving its type: stored, compressed with
+// the fixed Huffman codes or compressed with Huffman codes described in the block.
+// Compressed blocks hold literal bytes and (length, distance) back-references into
+// the last 32 KiB of data, coded as symbols followed by extra bits.
+const STORED_BLOCK: u32 = 0;
+const FIXED_BLOCK: u32 = 1;
+const DYNAMIC_BLOCK: u32 = 2;
+
+const END_OF_BLOCK: u16 = 256;
+const MAX_CODE_LENGTH: usize = 15;
+const NUM_LITERAL_LENGTH_CODES: usize = 288;
+const NUM_DISTANCE_CODES: usize = 30;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH_LENGTH: usize = 3;
+const MAX_MATCH_LENGTH: usize = 258;
+/// number of earlier positions tried when looking for the longest match
+const MAX_CHAIN_LENGTH: usize = 128;
+const HASH_BITS: u32 = 15;
+/// number of symbols after which a block is closed, so that the codes
+/// of the next block follow the changes in the data
+const MAX_BLOCK_TOKENS: usize = 1 << 14;
+const MAX_STORED_BLOCK_SIZE: usize = 0xffff;
+/// size the decoded data may reach before all but its last `WINDOW_SIZE` bytes
+/// are passed on to the writer
+const MAX_INFLATE_BUFFER_SIZE: usize = 1 << 18;
+
+/// Shortest length coded by each length symbol (from 257) and its number of extra bits
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Shortest distance coded by each distance symbol and its number of extra bits
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the lengths of the code length code are written in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Code lengths of the fixed literal/length code
+fn fixed_literal_length_lengths() -> [u8; NUM_LITERAL_LENGTH_CODES] {
+    let mut lengths = [8u8; NUM_LITERAL_LENGTH_CODES];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// Reads the code lengths of the literal/length and distance codes of a dynamic block
+fn read_dynamic_codes<R: BitRead>(bit_reader: &mut R) -> Result<(HuffmanDecoder, HuffmanDecoder)> {
+    let num_literal_length_codes = bit_reader.read::<u32>(5)? as usize + 257;
+    let num_distance_codes = bit_reader.read::<u32>(5)? as usize + 1;
+    let num_code_length_codes = bit_reader.read::<u32>(4)? as usize + 4;
+    if num_literal_length_codes > 286 || num_distance_codes > NUM_DISTANCE_CODES {
+        bail!("invalid dynamic block header");
+    }
+    let mut code_length_lengths = [0u8; 19];
+    for symbol in CODE_LENGTH_ORDER.iter().take(num_code_length_codes) {
+        code_length_lengths[*symbol] = bit_reader.read::<u8>(3)?;
+    }
+    let code_length_decoder = HuffmanDecoder::new(&code_length_lengths)?;
+
+    // the lengths of both codes form a single sequence, in which runs are coded
+    // by repeating the previous length (16) or a zero length (17 and 18)
+    let num_lengths = num_literal_length_codes + num_distance_codes;
+    let mut lengths = Vec::with_capacity(num_lengths);
+    while lengths.len() < num_lengths {
+        let symbol = code_length_decoder.decode(bit_reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(previous) => (*previous, 3 + bit_reader.read::<u32>(2)? as usize),
+                None => bail!("repeated code length without a previous length"),
+            },
+            17 => (0, 3 + bit_reader.read::<u32>(3)? as usize),
+            _ => (0, 11 + bit_reader.read::<u32>(7)? as usize),
+        };
+        if lengths.len() + repeat > num_lengths {
+            bail!("too many code lengths in dynamic block");
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        bail!("dynamic block has no end of block code");
+    }
+    Ok((
+        HuffmanDecoder::new(&lengths[..num_literal_length_codes])?,
+        HuffmanDecoder::new(&lengths[num_literal_length_codes..])?,
+    ))
+}
+
+/// Data decoded from a DEFLATE stream, keeping the last `WINDOW_SIZE` bytes
+/// written to `writer` for the back-references of the following data
+struct InflateWindow<W: Write> {
+    writer: W,
+    data: Vec<u8>,
+    /// start of the data not written yet, after the dictionary
+    unwritten_start: usize,
+}
+
+impl<W: Write> InflateWindow<W> {
+    /// Writes the data decoded so far once it is large enough, keeping the window
+    fn flush_if_full(&mut self) -> Result<()> {
+        if self.data.len() < MAX_INFLATE_BUFFER_SIZE {
+            return Ok(());
+        }
+        self.writer.write_all(&self.data[self.unwritten_start..])?;
+        self.data.drain(..self.data.len() - WINDOW_SIZE);
+        self.unwritten_start = self.data.len();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.writer.write_all(&self.data[self.unwritten_start..])?;
+        Ok(())
+    }
+}
+
+/// Decodes the symbols of a compressed block until its end, appending the data to `window`
+fn inflate_block<R: BitRead, W: Write>(
+    bit_reader: &mut R,
+    literal_length_decoder: &HuffmanDecoder,
+    distance_decoder: &HuffmanDecoder,
+    window: &mut InflateWindow<W>,
+) -> Result<()> {
+    loop {
+        window.flush_if_full()?;
+        let data = &mut window.data;
+        let symbol = literal_length_decoder.decode(bit_reader)?;
+        if symbol < END_OF_BLOCK {
+            data.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+        let length_index = (symbol - 257) as usize;
+        if length_index >= LENGTH_BASES.len() {
+            bail!("invalid length symbol {}", symbol);
+        }
+        let length = LENGTH_BASES[length_index] as usize
+            + bit_reader.read::<u32>(LENGTH_EXTRA_BITS[length_index] as u32)? as usize;
+        let distance_index = distance_decoder.decode(bit_reader)? as usize;
+        if distance_index >= NUM_DISTANCE_CODES {
+            bail!("invalid distance symbol {}", distance_index);
+        }
+        let distance = DISTANCE_BASES[distance_index] as usize
+            + bit_reader.read::<u32>(DISTANCE_EXTRA_BITS[distance_index] as u32)? as usize;
+        if distance > data.len() {
+            bail!(
+                "back-reference to {} bytes before the start of the data",
+                distance
+            );
+        }
+        // the referenced bytes may overlap the ones being copied
+        let start = data.len() - distance;
+        for i in 0..length {
+            data.push(data[start + i]);
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    Ok(inflate(compressed_data)?.0)
+}
+
+/// Decompresses the DEFLATE stream at the start of `compressed_data`, returning the data
+/// and the number of bytes the stream takes up, so containers can read what follows it
+pub fn inflate(compressed_data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    inflate_with_dictionary(compressed_data, &[])
+}
+
+/// Like `inflate`, for a stream whose back-references may reach into `dictionary`,
+/// data known to precede it (see `compress_with_dictionary`)
+pub fn inflate_with_dictionary(
+    compressed_data: &[u8],
+    dictionary: &[u8],
+) -> Result<(Vec<u8>, usize)> {
+    let mut reader = Cursor::new(compressed_data);
+    let mut data = Vec::new();
+    inflate_stream(&mut reader, dictionary, &mut data)?;
+    Ok((data, reader.position() as usize))
+}
+
+/// Decompresses the DEFLATE stream read from `reader` to `writer` as it is decoded,
+/// holding only the last 32 KiB of data and leaving the bytes after the stream unread
+/// (the reader should be buffered, it is read a byte at a time)
+pub fn inflate_stream<R: Read, W: Write>(reader: R, dictionary: &[u8], writer: W) -> Result<()> {
+    let mut bit_reader = BitReader::endian(reader, LittleEndian);
+    let mut window = InflateWindow {
+        writer,
+        data: dictionary.to_vec(),
+        unwritten_start: dictionary.len(),
+    };
+    let fixed_literal_length_decoder = HuffmanDecoder::new(&fixed_literal_length_lengths())?;
+    let fixed_distance_decoder = HuffmanDecoder::new(&[5u8; NUM_DISTANCE_CODES])?;
+    loop {
+        let is_last_block = bit_reader.read_bit()?;
+        match bit_reader.read::<u32>(2)? {
+            STORED_BLOCK => {
+                bit_reader.byte_align();
+                let length = bit_reader.read::<u16>(16)?;
+                let inverted_length = bit_reader.read::<u16>(16)?;
+                if length != !inverted_length {
+                    bail!("stored block length does not match its complement");
+                }
+                window.flush_if_full()?;
+                let start = window.data.len();
+                window.data.resize(start + length as usize, 0);
+                bit_reader.read_bytes(&mut window.data[start..])?;
+            }
+            FIXED_BLOCK => inflate_block(
+                &mut bit_reader,
+                &fixed_literal_length_decoder,
+                &fixed_distance_decoder,
+                &mut window,
+            )?,
+            DYNAMIC_BLOCK => {
+                let (literal_length_decoder, distance_decoder) =
+                    read_dynamic_codes(&mut bit_reader)?;
+                inflate_block(
+                    &mut bit_reader,
+                    &literal_length_decoder,
+                    &distance_decoder,
+                    &mut window,
+                )?;
+            }
+            _ => bail!("invalid DEFLATE block type"),
+        }
+        if is_last_block {
+            bit_reader.byte_align();
+            return window.finish();
+        }
+    }
+}
+
+/// A literal byte or a back-reference to earlier data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = u32::from_le_bytes([data[position], data[position + 1], data[position + 2], 0]);
+    (value.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Positions of the data chained by the hash of the 3 bytes starting there
+struct HashChains {
+    /// last position inserted for each hash
+    head: Vec<usize>,
+    /// previous position with the same hash as each position
+    previous: Vec<usize>,
+}
+
+impl HashChains {
+    fn new(data_len: usize) -> Self {
+        HashChains {
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            previous: vec![usize::MAX; data_len],
+        }
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH_LENGTH <= data.len() {
+            let hash = hash(data, position);
+            self.previous[position] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+}
+
+impl HashChains {
+    /// Returns the length and distance of the longest match of the data at `position`
+    /// with the data at an earlier position of the window, if it is long enough
+    fn longest_match(&self, data: &[u8], position: usize) -> Option<(usize, usize)> {
+        let max_length = MAX_MATCH_LENGTH.min(data.len() - position);
+        if max_length < MIN_MATCH_LENGTH {
+            return None;
+        }
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        let mut candidate = self.head[hash(data, position)];
+        let mut chain_length = 0;
+        while candidate != usize::MAX
+            && position - candidate <= WINDOW_SIZE
+            && chain_length < MAX_CHAIN_LENGTH
+        {
+            let length = data[candidate..candidate + max_length]
+                .iter()
+                .zip(&data[position..position + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_distance = position - candidate;
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate];
+            chain_length += 1;
+        }
+        if best_length < MIN_MATCH_LENGTH {
+            return None;
+        }
+        Some((best_length, best_distance))
+    }
+}
+
+/// Finds back-references in `data[start..]` with hash chains: the positions of earlier
+/// occurrences of the next 3 bytes are chained by their hash, and the longest
+/// match among the most recent ones within the window is taken. Matches are
+/// evaluated lazily: if the next byte starts a longer match, the current byte
+/// is written as a literal and the longer match is taken instead. Matches may
+/// refer back into the window of `data[..start]`, which gets no tokens.
+fn find_matches(data: &[u8], start: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chains = HashChains::new(data.len());
+    for position in start.saturating_sub(WINDOW_SIZE)..start {
+        chains.insert(data, position);
+    }
+    let mut position = start;
+    while position < data.len() {
+        let current_match = chains.longest_match(data, position);
+        chains.insert(data, position);
+        let Some((length, distance)) = current_match else {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+            continue;
+        };
+        if let Some((next_length, _)) = chains.longest_match(data, position + 1) {
+            if next_length > length {
+                tokens.push(Token::Literal(data[position]));
+                position += 1;
+                continue;
+            }
+        }
+        tokens.push(Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        });
+        for matched_position in position + 1..position + length {
+            chains.insert(data, matched_position);
+        }
+        position += length;
+    }
+    tokens
+}
+
+/// Returns the index of the symbol coding `value` in `bases`, whose entries are the
+/// smallest value of each symbol in increasing order
+fn symbol_index(bases: &[u16], value: u16) -> usize {
+    bases.partition_point(|base| *base <= value) - 1
+}
+
+/// Number of bytes of data a token stands for
+fn token_len(token: &Token) -> usize {
+    match token {
+        Token::Literal(_) => 1,
+        Token::Match { length, .. } => *length as usize,
+    }
+}
+
+/// Counts the literal/length symbols (including the end of block)
+/// and the distance symbols used to code `tokens`
+fn symbol_frequencies(tokens: &[Token]) -> (Vec<u32>, Vec<u32>) {
+    let mut literal_length_frequencies = vec![0u32; NUM_LITERAL_LENGTH_CODES];
+    let mut distance_frequencies = vec![0u32; NUM_DISTANCE_CODES];
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => literal_length_frequencies[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_length_frequencies[257 + symbol_index(&LENGTH_BASES, length)] += 1;
+                distance_frequencies[symbol_index(&DISTANCE_BASES, distance)] += 1;
+            }
+        }
+    }
+    literal_length_frequencies[END_OF_BLOCK as usize] += 1;
+    (literal_length_frequencies, distance_frequencies)
+}
+
+/// Number of bits needed to write symbols with the given frequencies and code
+/// lengths, with the extra bits of the length and distance symbols
+fn coded_size(
+    literal_length_frequencies: &[u32],
+    distance_frequencies: &[u32],
+    literal_length_lengths: &[u8],
+    distance_lengths: &[u8],
+) -> u64 {
+    let mut size = 0;
+    for (symbol, frequency) in literal_length_frequencies.iter().enumerate() {
+        let extra_bits = match symbol.checked_sub(257) {
+            Some(length_index) if length_index < LENGTH_EXTRA_BITS.len() => {
+                LENGTH_EXTRA_BITS[length_index] as u64
+            }
+            _ => 0,
+        };
+        size += *frequency as u64 * (literal_length_lengths[symbol] as u64 + extra_bits);
+    }
+    for (symbol, frequency) in distance_frequencies.iter().enumerate() {
+        size += *frequency as u64
+            * (distance_lengths[symbol] as u64 + DISTANCE_EXTRA_BITS[symbol] as u64);
+    }
+    size
+}
+
+/// Writes a Huffman code, whose bits are packed starting from the most significant one
+fn write_code<W: BitWrite>(bit_writer: &mut W, code: u16, length: u8) -> Result<()> {
+    let reversed_code = code.reverse_bits() >> (16 - length as u32);
+    bit_writer.write(length as u32, reversed_code as u32)?;
+    Ok(())
+}
+
+/// Writes `tokens` followed by the end of block with the codes of the given lengths
+fn write_tokens<W: BitWrite>(
+    bit_writer: &mut W,
+    tokens: &[Token],
+    literal_length_lengths: &[u8],
+    distance_lengths: &[u8],
+) -> Result<()> {
+    let literal_length_codes = canonical_codes(literal_length_lengths);
+    let distance_codes = canonical_codes(distance_lengths);
+    let write_symbol = |bit_writer: &mut W, symbol: usize| {
+        write_code(
+            bit_writer,
+            literal_length_codes[symbol],
+            literal_length_lengths[symbol],
+        )
+    };
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => write_symbol(bit_writer, byte as usize)?,
+            Token::Match { length, distance } => {
+                let length_index = symbol_index(&LENGTH_BASES, length);
+                write_symbol(bit_writer, 257 + length_index)?;
+                bit_writer.write(
+                    LENGTH_EXTRA_BITS[length_index] as u32,
+                    (length - LENGTH_BASES[length_index]) as u32,
+                )?;
+                let distance_index = symbol_index(&DISTANCE_BASES, distance);
+                write_code(
+                    bit_writer,
+                    distance_codes[distance_index],
+                    distance_lengths[distance_index],
+                )?;
+                bit_writer.write(
+                    DISTANCE_EXTRA_BITS[distance_index] as u32,
+                    (distance - DISTANCE_BASES[distance_index]) as u32,
+                )?;
+            }
+        }
+    }
+    write_symbol(bit_writer, END_OF_BLOCK as usize)?;
+    Ok(())
+}
+
+/// Huffman codes of a dynamic block and the description of their lengths
+/// written in the block header
+struct DynamicCodes {
+    literal_length_lengths: Vec<u8>,
+    distance_lengths: Vec<u8>,
+    /// code lengths of both codes as code length symbols, with the value of their
+    /// extra bits: lengths (0-15), or runs of the previous length (16) or zeros (17, 18)
+    code_length_symbols: Vec<(u8, u8)>,
+    code_length_lengths: Vec<u8>,
+    num_code_length_codes: usize,
+}
+
+/// Number of extra bits following each code length symbol
+fn code_length_extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Codes a sequence of code lengths as code length symbols
+fn code_length_symbols(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut symbols = Vec::new();
+    let mut position = 0;
+    while position < lengths.len() {
+        let length = lengths[position];
+        let run = lengths[position..]
+            .iter()
+            .take_while(|other| **other == length)
+            .count();
+        if length == 0 && run >= 11 {
+            let run = run.min(138);
+            symbols.push((18, (run - 11) as u8));
+            position += run;
+        } else if length == 0 && run >= 3 {
+            symbols.push((17, (run - 3) as u8));
+            position += run;
+        } else if position > 0 && lengths[position - 1] == length && run >= 3 {
+            let run = run.min(6);
+            symbols.push((16, (run - 3) as u8));
+            position += run;
+        } else {
+            symbols.push((length, 0));
+            position += 1;
+        }
+    }
+    symbols
+}
+
+impl DynamicCodes {
+    fn new(literal_length_frequencies: &[u32], distance_frequencies: &[u32]) -> Self {
+        // codes are given at least two symbols so that they are complete,
+        // as some decoders reject codes with a single symbol
+        let complete_code_lengths = |frequencies: &[u32], max_length: u8| {
+            let mut frequencies = frequencies.to_vec();
+            if frequencies
+                .iter()
+                .filter(|frequency| **frequency > 0)
+                .count()
+                < 2
+            {
+                frequencies[0] = frequencies[0].max(1);
+                frequencies[1] = frequencies[1].max(1);
+            }
+            huffmann::code_lengths(&frequencies, max_length)
+        };
+        // the last two literal/length symbols are never used
+        let literal_length_lengths = complete_code_lengths(
+            &literal_length_frequencies[..NUM_LITERAL_LENGTH_CODES - 2],
+            MAX_CODE_LENGTH as u8,
+        );
+        let distance_lengths = complete_code_lengths(distance_frequencies, MAX_CODE_LENGTH as u8);
+        let num_literal_length_codes = 257.max(
+            literal_length_lengths
+                .iter()
+                .rposition(|length| *length > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+        let num_distance_codes = distance_lengths
+            .iter()
+            .rposition(|length| *length > 0)
+            .unwrap_or(0)
+            + 1;
+        let code_length_symbols = code_length_symbols(
+            &[
+                &literal_length_lengths[..num_literal_length_codes],
+                &distance_lengths[..num_distance_codes],
+            ]
+            .concat(),
+        );
+        let mut code_length_frequencies = [0u32; 19];
+        for (symbol, _) in code_length_symbols.iter() {
+            code_length_frequencies[*symbol as usize] += 1;
+        }
+        let code_length_lengths = complete_code_lengths(&code_length_frequencies, 7);
+        let num_code_length_codes = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|symbol| code_length_lengths[*symbol] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+        let mut literal_length_lengths = literal_length_lengths;
+        literal_length_lengths.truncate(num_literal_length_codes);
+        let mut distance_lengths = distance_lengths;
+        distance_lengths.truncate(num_distance_codes);
+        DynamicCodes {
+            literal_length_lengths,
+            distance_lengths,
+            code_length_symbols,
+            code_length_lengths,
+            num_code_length_codes,
+        }
+    }
+
+    /// Number of bits of the block header describing the codes
+    fn header_size(&self) -> u64 {
+        let symbols_size: u64 = self
+            .code_length_symbols
+            .iter()
+            .map(|(symbol, _)| {
+                self.code_length_lengths[*symbol as usize] as u64
+                    + code_length_extra_bits(*symbol) as u64
+            })
+            .sum();
+        5 + 5 + 4 + 3 * self.num_code_length_codes as u64 + symbols_size
+    }
+
+    /// Number of bits of the block, header included
+    fn block_size(&self, literal_length_frequencies: &[u32], distance_frequencies: &[u32]) -> u64 {
+        // symbols beyond the written lengths are not used
+        let mut literal_length_lengths = self.literal_length_lengths.clone();
+        literal_length_lengths.resize(NUM_LITERAL_LENGTH_CODES, 0);
+        let mut distance_lengths = self.distance_lengths.clone();
+        distance_lengths.resize(NUM_DISTANCE_CODES, 0);
+        3 + self.header_size()
+            + coded_size(
+                literal_length_frequencies,
+                distance_frequencies,
+                &literal_length_lengths,
+                &distance_lengths,
+            )
+    }
+
+    fn write_header<W: BitWrite>(&self, bit_writer: &mut W) -> Result<()> {
+        bit_writer.write(5, (self.literal_length_lengths.len() - 257) as u32)?;
+        bit_writer.write(5, (self.distance_lengths.len() - 1) as u32)?;
+        bit_writer.write(4, (self.num_code_length_codes - 4) as u32)?;
+        for symbol in CODE_LENGTH_ORDER.iter().take(self.num_code_length_codes) {
+            bit_writer.write(3, self.code_length_lengths[*symbol] as u32)?;
+        }
+        let code_length_codes = canonical_codes(&self.code_length_lengths);
+        for (symbol, extra) in self.code_length_symbols.iter() {
+            write_code(
+                bit_writer,
+                code_length_codes[*symbol as usize],
+                self.code_length_lengths[*symbol as usize],
+            )?;
+            bit_writer.write(code_length_extra_bits(*symbol), *extra as u32)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of bits of the stored blocks holding `data_len` bytes, counting
+/// the padding to a byte boundary before each block header as a whole byte
+fn stored_size(data_len: usize) -> u64 {
+    let num_blocks = data_len.div_ceil(MAX_STORED_BLOCK_SIZE).max(1) as u64;
+    num_blocks * (3 + 7 + 32) + 8 * data_len as u64
+}
+
+/// Writes `data` as stored blocks of up to 65535 bytes
+fn write_stored_blocks<W: Write>(
+    bit_writer: &mut BitWriter<W, LittleEndian>,
+    data: &[u8],
+    is_last_block: bool,
+) -> Result<()> {
+    let num_blocks = data.len().div_ceil(MAX_STORED_BLOCK_SIZE).max(1);
+    for block_index in 0..num_blocks {
+        let start = block_index * MAX_STORED_BLOCK_SIZE;
+        let block_data = &data[start..data.len().min(start + MAX_STORED_BLOCK_SIZE)];
+        bit_writer.write_bit(is_last_block && block_index == num_blocks - 1)?;
+        bit_writer.write(2, STORED_BLOCK)?;
+        bit_writer.byte_align()?;
+        bit_writer.write(16, block_data.len() as u16)?;
+        bit_writer.write(16, !(block_data.len() as u16))?;
+        bit_writer.write_bytes(block_data)?;
+    }
+    Ok(())
+}
+
+/// Writes the data coded by `tokens` as the smallest of a dynamic block,
+/// a fixed block or stored blocks
+fn write_block<W: Write>(
+    bit_writer: &mut BitWriter<W, LittleEndian>,
+    tokens: &[Token],
+    data: &[u8],
+    is_last_block: bool,
+) -> Result<()> {
+    let (literal_length_frequencies, distance_frequencies) = symbol_frequencies(tokens);
+    let fixed_literal_length_lengths = fixed_literal_length_lengths();
+    let fixed_distance_lengths = [5u8; NUM_DISTANCE_CODES];
+    let fixed_size = 3 + coded_size(
+        &literal_length_frequencies,
+        &distance_frequencies,
+        &fixed_literal_length_lengths,
+        &fixed_distance_lengths,
+    );
+    let dynamic_codes = DynamicCodes::new(&literal_length_frequencies, &distance_frequencies);
+    let dynamic_size = dynamic_codes.block_size(&literal_length_frequencies, &distance_frequencies);
+    if stored_size(data.len()) < fixed_size.min(dynamic_size) {
+        return write_stored_blocks(bit_writer, data, is_last_block);
+    }
+    bit_writer.write_bit(is_last_block)?;
+    if dynamic_size < fixed_size {
+        bit_writer.write(2, DYNAMIC_BLOCK)?;
+        dynamic_codes.write_header(bit_writer)?;
+        write_tokens(
+            bit_writer,
+            tokens,
+            &dynamic_codes.literal_length_lengths,
+            &dynamic_codes.distance_lengths,
+        )
+    } else {
+        bit_writer.write(2, FIXED_BLOCK)?;
+        write_tokens(
+            bit_writer,
+            tokens,
+            &fixed_literal_length_lengths,
+            &fixed_distance_lengths,
+        )
+    }
+}
+
+/// Compresses `data` as a raw DEFLATE stream. The back-references found are split
+/// into blocks, each written with the codes (fixed or built from the frequencies of
+/// its symbols) giving the smallest block, or stored if it does not compress.
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    compress_with_dictionary(data, &[])
+}
+
+/// Like `compress`, with back-references allowed into the last 32 KiB of `dictionary`,
+/// data the decompressor knows to precede `data` (a preset dictionary)
+pub fn compress_with_dictionary(data: &[u8], dictionary: &[u8]) -> Result<Vec<u8>> {
+    compress_part(data, dictionary, true)
+}
+
+/// Compresses `data` as a part of a raw DEFLATE stream, following the parts holding
+/// the data ending with `dictionary` (whose last 32 KiB can be referred to), so that
+/// a stream can be written one piece of data at a time. A part other than the last
+/// ends with an empty stored block instead of the last block, which aligns the end
+/// of the part to a byte boundary for the next part to be appended.
+pub fn compress_part(data: &[u8], dictionary: &[u8], is_last: bool) -> Result<Vec<u8>> {
+    let mut compressed_data = Vec::new();
+    let mut bit_writer = BitWriter::endian(&mut compressed_data, LittleEndian);
+    let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+    let window = [dictionary, data].concat();
+    let tokens = find_matches(&window, dictionary.len());
+    let num_blocks = tokens.len().div_ceil(MAX_BLOCK_TOKENS);
+    let mut data_offset = 0;
+    for (block_index, block_tokens) in tokens.chunks(MAX_BLOCK_TOKENS).enumerate() {
+        let block_len: usize = block_tokens.iter().map(token_len).sum();
+        write_block(
+            &mut bit_writer,
+            block_tokens,
+            &data[data_offset..data_offset + block_len],
+            is_last && block_index == num_blocks - 1,
+        )?;
+        data_offset += block_len;
+    }
+    if !is_last {
+        write_stored_blocks(&mut bit_writer, &[], false)?;
+    } else if tokens.is_empty() {
+        write_block(&mut bit_writer, &[], &[], true)?;
+    }
+    bit_writer.byte_align()?;
+    Ok(compressed_data)
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::compress_part;
+    use super::decompress;
+    use super::inflate_stream;
+    use crate::test_util::pseudo_random_bytes;
+    use anyhow::Ok;
+    use anyhow::Result;
+
+    #[test]
+    fn test_deflate_round_trip() -> Result<()> {
+        let random = pseudo_random_bytes(7, 100_000);
+        let text = b"It was the best of times, it was the worst of times. ".repeat(2000);
+        for data in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            &random,
+            &text,
+        ] {
+            let compressed_data = compress(data)?;
+            assert_eq!(decompress(&compressed_data)?, data);
+        }
+        assert!(compress(&text)?.len() < text.len() / 20);
+        // the same data is always compressed to the same bytes
+        assert_eq!(compress(&text)?, compress(&text)?);
+
+        // a stream written in parts, each referring to the data of the previous ones
+        let mut parts = Vec::new();
+        for (part_index, part) in text.chunks(30_000).enumerate() {
+            let dictionary = &text[..30_000 * part_index];
+            let is_last = part_index == text.len().div_ceil(30_000) - 1;
+            parts.extend(compress_part(part, dictionary, is_last)?);
+        }
+        assert_eq!(decompress(&parts)?, text);
+        assert!(parts.len() < text.len() / 20);
+
+        // streams are written out as they are inflated, leaving what follows them unread
+        let sample = include_bytes!("../testdata/sample.txt");
+        let long_data = [sample.repeat(40), random, text].concat();
+        let mut stream = compress(&long_data)?;
+        stream.extend_from_slice(b"trailer");
+        let mut reader = stream.as_slice();
+        let mut inflated_data = Vec::new();
+        inflate_stream(&mut reader, &[], &mut inflated_data)?;
+        assert_eq!(inflated_data, long_data);
+        assert_eq!(reader, b"trailer");
+        Ok(())
+    }
+
+    #[test]
+    fn test_deflate_reference_vectors() -> Result<()> {
+        // small inputs are written as fixed blocks: an empty block as written by
+        // zlib, and "hello " followed by a match of 11 bytes 6 bytes back (zlib
+        // writes one more literal, as it does not look for matches near the end)
+        assert_eq!(compress(b"")?, [0x03, 0x00]);
+        assert_eq!(
+            compress(b"hello hello hello")?,
+            [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x40, 0x22, 0x01]
+        );
+
+        // text is written as dynamic blocks, about as small as with zlib -9 (2583 bytes)
+        let text = include_bytes!("../testdata/sample.txt");
+        let compressed_text = compress(text)?;
+        assert_eq!(compressed_text[0] & 0x07, 0x05);
+        assert!(compressed_text.len() < 2583 * 102 / 100);
+        assert_eq!(decompress(&compressed_text)?, text);
+
+        // data that does not compress is written as stored blocks,
+        // with a 5-byte header for each block of 16384 symbols
+        let random = pseudo_random_bytes(11, 200_000);
+        let compressed_random = compress(&random)?;
+        assert_eq!(compressed_random.len(), random.len() + 5 * 13);
+        assert_eq!(decompress(&compressed_random)?, random);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inflate_blocks() -> Result<()> {
+        // streams written by zlib: a stored block, a fixed block and dynamic blocks
+        let stored = [
+            0x01, 0x11, 0x00, 0xee, 0xff, b'h', b'e', b'l', b'l', b'o', b' ', b'h', b'e', b'l',
+            b'l', b'o', b' ', b'h', b'e', b'l', b'l', b'o',
+        ];
+        assert_eq!(decompress(&stored)?, b"hello hello hello");
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        assert_eq!(decompress(&fixed)?, b"hello hello hello");
+        let dynamic = include_bytes!("../testdata/sample.txt.deflate");
+        assert_eq!(
+            decompress(dynamic)?,
+            include_bytes!("../testdata/sample.txt")
+        );
+
+        // invalid block type, and stored length not matching its complement
+        assert!(decompress(&[0x07]).is_err());
+        assert!(decompress(&[0x01, 0x05, 0x00, 0x00, 0x00]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use crate::checksum::crc32;
+use crate::deflate;
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// A gzip file (RFC 1952) is one or more members, each a header, a DEFLATE stream and a
+// trailer with the CRC-32 and the length (modulo 2^32) of the member's data. The header
+// starts with a fixed 10 bytes (magic, method, flags, mtime, extra flags and OS) and the
+// flags tell which optional fields follow: an extra field, the original file name, a
+// comment and a CRC-16 of the header. Decompressing a file concatenates its members.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE_METHOD: u8 = 8;
+const HEADER_SIZE: usize = 10;
+const TRAILER_SIZE: usize = 8;
+
+const FLAG_TEXT: u8 = 0x01;
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const RESERVED_FLAGS: u8 = !(FLAG_TEXT | FLAG_HEADER_CRC | FLAG_EXTRA | FLAG_NAME | FLAG_COMMENT);
+
+const UNIX_OS: u8 = 3;
+
+/// Whether `data` starts like a gzip member
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 3 && data[..2] == MAGIC && data[2] == DEFLATE_METHOD
+}
+
+/// Compresses `data` as a single gzip member, recording `name` and `mtime` in its header
+pub fn compress(data: &[u8], name: Option<&[u8]>, mtime: u32) -> Result<Vec<u8>> {
+    let mut compressed_data = Vec::new();
+    compressed_data.extend_from_slice(&MAGIC);
+    compressed_data.push(DEFLATE_METHOD);
+    compressed_data.push(if name.is_some() { FLAG_NAME } else { 0 });
+    compressed_data.extend_from_slice(&mtime.to_le_bytes());
+    // no extra flags: they only hint at the compression level
+    compressed_data.push(0);
+    compressed_data.push(UNIX_OS);
+    if let Some(name) = name {
+        if name.contains(&0) {
+            bail!("gzip file names cannot contain NUL bytes");
+        }
+        compressed_data.extend_from_slice(name);
+        compressed_data.push(0);
+    }
+    compressed_data.extend_from_slice(&deflate::compress(data)?);
+    compressed_data.extend_from_slice(&crc32(data).to_le_bytes());
+    compressed_data.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(compressed_data)
+}
+
+/// Returns the offset after the NUL-terminated field at `offset` in `data`
+fn skip_zero_terminated(data: &[u8], offset: usize) -> Result<usize> {
+    match data[offset..].iter().position(|byte| *byte == 0) {
+        Some(len) => Ok(offset + len + 1),
+        None => bail!("truncated gzip header"),
+    }
+}
+
+/// Returns the length of the header of the member at the start of `data`
+fn read_header(data: &[u8]) -> Result<usize> {
+    if data.len() < HEADER_SIZE {
+        bail!("truncated gzip header");
+    }
+    if !is_gzip(data) {
+        bail!("not a gzip member");
+    }
+    let flags = data[3];
+    if flags & RESERVED_FLAGS != 0 {
+        bail!("gzip header has reserved flags set");
+    }
+    let mut offset = HEADER_SIZE;
+    if flags & FLAG_EXTRA != 0 {
+        if data.len() < offset + 2 {
+            bail!("truncated gzip header");
+        }
+        let extra_len = u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+        offset += 2 + extra_len;
+        if data.len() < offset {
+            bail!("truncated gzip header");
+        }
+    }
+    if flags & FLAG_NAME != 0 {
+        offset = skip_zero_terminated(data, offset)?;
+    }
+    if flags & FLAG_COMMENT != 0 {
+        offset = skip_zero_terminated(data, offset)?;
+    }
+    if flags & FLAG_HEADER_CRC != 0 {
+        if data.len() < offset + 2 {
+            bail!("truncated gzip header");
+        }
+        let header_crc = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        if header_crc != crc32(&data[..offset]) as u16 {
+            bail!("gzip header CRC mismatch");
+        }
+        offset += 2;
+    }
+    Ok(offset)
+}
+
+/// Decompresses every member of a gzip file, ignoring zero bytes padding its end
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut offset = 0;
+    loop {
+        let member = &compressed_data[offset..];
+        let header_len = read_header(member)?;
+        let (member_data, stream_len) = deflate::inflate(&member[header_len..])?;
+        let trailer_offset = header_len + stream_len;
+        if member.len() < trailer_offset + TRAILER_SIZE {
+            bail!("truncated gzip trailer");
+        }
+        let trailer = &member[trailer_offset..trailer_offset + TRAILER_SIZE];
+        let expected_crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let expected_len = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc32(&member_data) != expected_crc {
+            bail!("gzip data CRC mismatch");
+        }
+        if member_data.len() as u32 != expected_len {
+            bail!("gzip data length mismatch");
+        }
+        data.extend_from_slice(&member_data);
+        offset += trailer_offset + TRAILER_SIZE;
+        if compressed_data[offset..].iter().all(|byte| *byte == 0) {
+            return Ok(data);
+        }
+    }
+}
+
+/// Whether the file at `file_path` starts like a gzip member
+pub fn is_gzip_file(file_path: &str) -> Result<bool> {
+    let mut magic = Vec::new();
+    File::open(file_path)?.take(3).read_to_end(&mut magic)?;
+    Ok(is_gzip(&magic))
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    // like gzip, keep the file name and an mtime of 0 if it does not fit
+    let mtime = file
+        .metadata()?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| u32::try_from(duration.as_secs()).unwrap_or(0));
+    let name = Path::new(file_path).file_name().map(|name| name.as_bytes());
+    let compressed_data = compress(&data, name, mtime)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::decompress;
+    use super::read_header;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_gzip_files() -> Result<()> {
+        let sample = fs::read("testdata/sample.txt")?;
+
+        // written by `gzip -9`, with the file name in the header
+        let compressed_data = fs::read("testdata/sample.txt.gz")?;
+        assert_eq!(read_header(&compressed_data)?, 10 + b"sample.txt\0".len());
+        assert_eq!(decompress(&compressed_data)?, sample);
+
+        // a member from `gzip -n` followed by one from `gzip`
+        let mut expected = b"first member\n".to_vec();
+        expected.extend_from_slice(&sample);
+        assert_eq!(decompress(&fs::read("testdata/multi.gz")?)?, expected);
+
+        // gzip never writes an extra field, comment or header CRC, so this member was built
+        // by hand and checked with `gzip -t`
+        let compressed_data = fs::read("testdata/extra.gz")?;
+        assert_eq!(
+            decompress(&compressed_data)?,
+            b"gzip header fields\n".repeat(20)
+        );
+
+        let mut damaged = compressed_data.clone();
+        damaged[12] ^= 1;
+        assert!(decompress(&damaged).is_err());
+        let mut damaged = fs::read("testdata/sample.txt.gz")?;
+        let len = damaged.len();
+        damaged[len - 8] ^= 1;
+        assert!(decompress(&damaged).is_err());
+        damaged[len - 8] ^= 1;
+        damaged[len - 1] ^= 1;
+        assert!(decompress(&damaged).is_err());
+        assert!(decompress(&damaged[..len - 4]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_round_trip() -> Result<()> {
+        let data = b"gzip gzip gzip round trip\n".repeat(100);
+        let compressed_data = compress(&data, Some(b"round_trip.txt"), 1_700_000_000)?;
+        assert_eq!(&compressed_data[..4], &[0x1f, 0x8b, 8, 0x08]);
+        assert_eq!(&compressed_data[4..8], &1_700_000_000u32.to_le_bytes());
+        assert_eq!(
+            read_header(&compressed_data)?,
+            10 + b"round_trip.txt\0".len()
+        );
+        assert_eq!(decompress(&compressed_data)?, data);
+
+        // members concatenate, and zero padding after the last one is ignored
+        let mut two_members = compress(b"", None, 0)?;
+        assert_eq!(read_header(&two_members)?, 10);
+        two_members.extend_from_slice(&compressed_data);
+        two_members.extend_from_slice(&[0; 16]);
+        assert_eq!(decompress(&two_members)?, data);
+        two_members.push(1);
+        assert!(decompress(&two_members).is_err());
+        Ok(())
+    }
+}
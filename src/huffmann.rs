@@ -1,3 +1,4 @@
+use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
 use bitstream_io::huffman::compile_read_tree;
@@ -13,44 +14,70 @@ use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::fs::File;
+use std::hash::Hash;
 use std::io::Cursor;
 use std::io::Read;
-use std::io::Seek;
 use std::io::Write;
 
-struct BinaryTreeNode {
-    symbol: u8,
+/// longest code of the canonical codes, as in DEFLATE
+const MAX_CODE_LENGTH: usize = 15;
+
+/// magic and version starting the files written by `compress_file`, followed by the
+/// data of `compress`. Files of earlier releases have no header: they start with the
+/// 32-bit number of codes of their table, whose first byte is always zero.
+const FILE_MAGIC: &[u8; 4] = b"FCHM";
+const FILE_VERSION: u8 = 1;
+
+/// A node of a Huffman tree over symbols of type `S`, such as bytes, the 286 literal and
+/// length symbols of DEFLATE or the 4096 codes of 12-bit LZW
+struct BinaryTreeNode<S> {
+    symbol: Option<S>,
     weight: u32,
-    left: Option<Box<BinaryTreeNode>>,
-    right: Option<Box<BinaryTreeNode>>,
+    /// order in which the node was created, breaking ties between nodes of equal
+    /// weight so that the tree does not depend on the order of the heap
+    order: usize,
+    left: Option<Box<BinaryTreeNode<S>>>,
+    right: Option<Box<BinaryTreeNode<S>>>,
 }
 
-impl Ord for BinaryTreeNode {
+impl<S> Ord for BinaryTreeNode<S> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.weight.cmp(&self.weight)
+        other
+            .weight
+            .cmp(&self.weight)
+            .then(other.order.cmp(&self.order))
     }
 }
 
-impl PartialOrd for BinaryTreeNode {
+impl<S> PartialOrd for BinaryTreeNode<S> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(other.weight.cmp(&self.weight))
+        Some(self.cmp(other))
     }
 }
 
-impl PartialEq for BinaryTreeNode {
+impl<S> PartialEq for BinaryTreeNode<S> {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other) == Ordering::Equal
     }
 }
 
-impl Eq for BinaryTreeNode {}
-
-fn construct_min_heap_with_nodes(symbol_table: &HashMap<u8, u32>) -> BinaryHeap<BinaryTreeNode> {
-    let mut min_heap: BinaryHeap<BinaryTreeNode> = BinaryHeap::new();
-    for (symbol, count) in symbol_table.iter() {
+impl<S> Eq for BinaryTreeNode<S> {}
+
+fn construct_min_heap_with_nodes<S: Copy + Ord>(
+    symbol_table: &HashMap<S, u32>,
+) -> BinaryHeap<BinaryTreeNode<S>> {
+    // the leaves are created in the order of the symbols, not of the hash map
+    let mut symbols: Vec<(S, u32)> = symbol_table
+        .iter()
+        .map(|(symbol, count)| (*symbol, *count))
+        .collect();
+    symbols.sort_unstable_by_key(|(symbol, _)| *symbol);
+    let mut min_heap: BinaryHeap<BinaryTreeNode<S>> = BinaryHeap::new();
+    for (order, (symbol, count)) in symbols.into_iter().enumerate() {
         min_heap.push(BinaryTreeNode {
-            symbol: *symbol,
-            weight: *count,
+            symbol: Some(symbol),
+            weight: count,
+            order,
             left: None,
             right: None,
         });
@@ -58,76 +85,236 @@ fn construct_min_heap_with_nodes(symbol_table: &HashMap<u8, u32>) -> BinaryHeap<
     min_heap
 }
 
-fn build_huffman_tree(min_heap: &mut BinaryHeap<BinaryTreeNode>) -> BinaryTreeNode {
+fn build_huffman_tree<S>(min_heap: &mut BinaryHeap<BinaryTreeNode<S>>) -> BinaryTreeNode<S> {
+    let mut order = min_heap.len();
     while min_heap.len() > 1 {
         let left_node = min_heap.pop().unwrap();
         let right_node = min_heap.pop().unwrap();
         let intermediate_node = BinaryTreeNode {
-            symbol: 0, // no symbols in intermediate nodes,
+            symbol: None, // no symbols in intermediate nodes
             weight: left_node.weight + right_node.weight,
+            order,
             left: Some(Box::new(left_node)),
             right: Some(Box::new(right_node)),
         };
         min_heap.push(intermediate_node);
+        order += 1;
     }
     min_heap.pop().unwrap()
 }
 
-fn traverse_huffmann_tree(
-    huffmann_tree_node: &BinaryTreeNode,
+fn traverse_huffmann_tree<S: Copy + Eq + Hash>(
+    huffmann_tree_node: &BinaryTreeNode<S>,
     code_bits: &mut Vec<u8>,
-    codes: &mut HashMap<u8, Vec<u8>>,
+    codes: &mut HashMap<S, Vec<u8>>,
 ) {
-    if huffmann_tree_node.symbol != 0u8 {
-        codes.insert(huffmann_tree_node.symbol, code_bits.clone());
+    // only leaf nodes hold symbols
+    if let Some(symbol) = huffmann_tree_node.symbol {
+        codes.insert(symbol, code_bits.clone());
     }
-    if huffmann_tree_node.left.is_some() {
+    if let Some(left_node) = &huffmann_tree_node.left {
         code_bits.push(0u8);
-        traverse_huffmann_tree(huffmann_tree_node.left.as_ref().unwrap(), code_bits, codes);
+        traverse_huffmann_tree(left_node, code_bits, codes);
         code_bits.pop();
     }
-    if huffmann_tree_node.right.is_some() {
+    if let Some(right_node) = &huffmann_tree_node.right {
         code_bits.push(1u8);
-        traverse_huffmann_tree(huffmann_tree_node.right.as_ref().unwrap(), code_bits, codes);
+        traverse_huffmann_tree(right_node, code_bits, codes);
         code_bits.pop();
     }
 }
 
-fn encode_symbol_table(huffmann_tree_root: &BinaryTreeNode) -> HashMap<u8, Vec<u8>> {
-    let mut codes: HashMap<u8, Vec<u8>> = HashMap::new();
+fn encode_symbol_table<S: Copy + Eq + Hash>(
+    huffmann_tree_root: &BinaryTreeNode<S>,
+) -> HashMap<S, Vec<u8>> {
+    let mut codes: HashMap<S, Vec<u8>> = HashMap::new();
     let mut code_bits: Vec<u8> = Vec::new();
     traverse_huffmann_tree(huffmann_tree_root, &mut code_bits, &mut codes);
     codes
 }
 
-pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
-    let mut file = File::open(file_path)?;
+/// Builds the Huffman tree of the symbols of `symbol_table`, which maps them to their
+/// weights, and returns the code of each symbol as a vector of bits. Symbols may be
+/// of any type, from bytes to the `u16` or `u32` symbols of larger alphabets. A single
+/// symbol gets an empty code. Symbols of equal weight are ordered by value, so the
+/// codes only depend on the weights.
+pub fn build_codes<S: Copy + Ord + Hash>(symbol_table: &HashMap<S, u32>) -> HashMap<S, Vec<u8>> {
+    if symbol_table.is_empty() {
+        return HashMap::new();
+    }
+    let mut heap = construct_min_heap_with_nodes(symbol_table);
+    let tree_root_node = build_huffman_tree(&mut heap);
+    encode_symbol_table(&tree_root_node)
+}
 
-    // construct symbol table
-    let mut symbol_table: HashMap<u8, u32> = HashMap::new();
-    let mut buffer: [u8; 128] = [0; 128];
-    let mut bytes_read = file.read(&mut buffer)?;
-    while bytes_read > 0 {
-        for i in 0..bytes_read {
-            *symbol_table.entry(buffer[i]).or_insert(0) += 1;
+/// Computes the lengths of the Huffman codes of the symbols `0..weights.len()` from
+/// their weights, symbols of weight zero getting no code. As formats such as DEFLATE
+/// limit the length of the codes, the weights are flattened and the tree is built
+/// again until no code is longer than `max_length` bits.
+pub fn code_lengths(weights: &[u32], max_length: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; weights.len()];
+    let mut symbol_table: HashMap<usize, u32> = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .map(|(symbol, weight)| (symbol, *weight))
+        .collect();
+    match symbol_table.keys().next() {
+        None => return lengths,
+        // a tree with a single node has an empty code, a 1-bit code is used instead
+        Some(symbol) if symbol_table.len() == 1 => {
+            lengths[*symbol] = 1;
+            return lengths;
         }
-        bytes_read = file.read(&mut buffer)?;
+        Some(_) => {}
     }
+    loop {
+        let codes = build_codes(&symbol_table);
+        if codes.values().all(|code| code.len() <= max_length as usize) {
+            for (symbol, code) in codes {
+                lengths[symbol] = code.len() as u8;
+            }
+            return lengths;
+        }
+        for weight in symbol_table.values_mut() {
+            *weight = weight.div_ceil(2);
+        }
+    }
+}
 
-    // construct huffmann tree
-    let mut heap = construct_min_heap_with_nodes(&symbol_table);
-    let tree_root_node = build_huffman_tree(&mut heap);
+/// Assigns the canonical Huffman codes of RFC 1951 to symbols with the given code
+/// lengths: shorter codes come first, and codes of the same length follow the order
+/// of the symbols. Returns the code of each symbol.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut length_counts = [0u16; MAX_CODE_LENGTH + 1];
+    for length in lengths.iter().filter(|length| **length > 0) {
+        length_counts[*length as usize] += 1;
+    }
+    let mut next_codes = [0u16; MAX_CODE_LENGTH + 2];
+    for length in 1..=MAX_CODE_LENGTH {
+        next_codes[length + 1] = (next_codes[length] + length_counts[length]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|length| {
+            let code = next_codes[*length as usize];
+            next_codes[*length as usize] += 1;
+            code
+        })
+        .collect()
+}
 
-    // encode the symbol tree using the huffmann tree
-    // transform symbols to variable-size optimal prefix codes
-    let encoded_symbol_table = encode_symbol_table(&tree_root_node);
+/// A canonical Huffman code used to decode symbols, described by the number of codes
+/// of each length and the symbols in the order of their codes
+pub struct HuffmanDecoder {
+    length_counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
 
-    let tree_vec: Vec<(u8, Vec<u8>)> = encoded_symbol_table.clone().into_iter().collect();
-    let num_pairs = tree_vec.len() as u32;
+impl HuffmanDecoder {
+    pub fn new(lengths: &[u8]) -> Result<Self> {
+        if lengths
+            .iter()
+            .any(|length| *length as usize > MAX_CODE_LENGTH)
+        {
+            bail!("Huffman codes are limited to {} bits", MAX_CODE_LENGTH);
+        }
+        let mut length_counts = [0u16; MAX_CODE_LENGTH + 1];
+        for length in lengths.iter() {
+            length_counts[*length as usize] += 1;
+        }
+        // each length doubles the number of available codes,
+        // the code is invalid if more codes are used than available
+        let mut available: i32 = 1;
+        for count in length_counts.iter().skip(1) {
+            available = (available << 1) - *count as i32;
+            if available < 0 {
+                bail!("invalid Huffman code lengths");
+            }
+        }
+        let mut symbols = Vec::new();
+        for length in 1..=MAX_CODE_LENGTH as u8 {
+            for (symbol, symbol_length) in lengths.iter().enumerate() {
+                if *symbol_length == length {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        length_counts[0] = 0;
+        Ok(HuffmanDecoder {
+            length_counts,
+            symbols,
+        })
+    }
+
+    /// Reads a code bit by bit, starting from its most significant bit
+    pub fn decode<R: BitRead>(&self, bit_reader: &mut R) -> Result<u16> {
+        // first code of the current length and index of its symbol
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for count in self.length_counts.iter().skip(1) {
+            code |= bit_reader.read_bit()? as i32;
+            let count = *count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code");
+    }
+}
+
+/// Splits `value` into a symbol, to be Huffman-coded, and extra bits written as they are:
+/// values below 4 are their own symbol, and each range from a power of two to the next
+/// is split into two symbols followed by the low bits of the value, as the lengths and
+/// distances of DEFLATE. Returns the symbol, the number of extra bits and their value.
+pub fn value_symbol(value: u32) -> (u16, u32, u32) {
+    if value < 4 {
+        return (value as u16, 0, 0);
+    }
+    let high_bit = u32::BITS - 1 - value.leading_zeros();
+    let extra_bits = high_bit - 1;
+    let symbol = 2 * high_bit + ((value >> extra_bits) & 1);
+    (symbol as u16, extra_bits, value & ((1 << extra_bits) - 1))
+}
+
+/// Returns the smallest value of `symbol` and the number of its extra bits
+pub fn symbol_base(symbol: u16) -> (u32, u32) {
+    let symbol = symbol as u32;
+    if symbol < 4 {
+        return (symbol, 0);
+    }
+    let extra_bits = symbol / 2 - 1;
+    ((2 | (symbol & 1)) << extra_bits, extra_bits)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    // construct symbol table
+    let mut symbol_table: HashMap<u8, u32> = HashMap::new();
+    for byte in data.iter() {
+        *symbol_table.entry(*byte).or_insert(0) += 1;
+    }
 
     let mut compressed_data = Vec::new();
     let mut bit_writer = BitWriter::endian(&mut compressed_data, BigEndian);
 
+    // the number of symbols is written first, so that the decoder
+    // knows where the encoded data ends (the last byte is padded)
+    bit_writer.write_bytes(&(data.len() as u64).to_be_bytes())?;
+    if data.is_empty() {
+        bit_writer.write_bytes(&0u32.to_be_bytes())?;
+        return Ok(compressed_data);
+    }
+
+    // construct the huffmann tree and transform symbols
+    // to variable-size optimal prefix codes
+    let mut tree_vec: Vec<(u8, Vec<u8>)> = build_codes(&symbol_table).into_iter().collect();
+    tree_vec.sort_unstable_by_key(|(symbol, _)| *symbol);
+    let num_pairs = tree_vec.len() as u32;
+
     // write the symbol table to `compressed_data`:
     // 1. write number of (symbol, code) pairs in `encoded_symbol_table`
     // 2. for each (symbol, code) pair, write the symbol
@@ -142,37 +329,81 @@ pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()>
         }
     }
 
-    // reset file pointer to start reading from the beginning
-    // (but this time to encode data)
-    file.seek(std::io::SeekFrom::Start(0))?;
+    // a tree with a single node has an empty code for its symbol,
+    // the decoder repeats the symbol without reading any bits
+    if num_pairs == 1 {
+        return Ok(compressed_data);
+    }
 
     let tree = compile_write_tree::<BigEndian, u8>(tree_vec)?;
-    let mut buffer: [u8; 128] = [0; 128];
-    let mut bytes_read = file.read(&mut buffer)?;
-    while bytes_read > 0 {
-        for i in 0..bytes_read {
-            bit_writer.write_huffman(&tree, buffer[i])?;
+    for byte in data.iter() {
+        bit_writer.write_huffman(&tree, *byte)?;
+    }
+    bit_writer.byte_align()?;
+
+    Ok(compressed_data)
+}
+
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let mut bit_reader = BitReader::endian(Cursor::new(compressed_data), BigEndian);
+    let num_symbols: u64 = bit_reader.read_as_to::<BigEndian, u64>()?;
+
+    // read the symbol table from the file
+    let mut codes: Vec<(u8, Vec<u8>)> = Vec::new();
+    let num_pairs: u32 = bit_reader.read_as_to::<BigEndian, u32>()?;
+    for _ in 0..num_pairs {
+        let symbol: u8 = bit_reader.read_as_to::<BigEndian, u8>()?;
+        let code_len: u32 = bit_reader.read_as_to::<BigEndian, u32>()?;
+        let mut code: Vec<u8> = Vec::new();
+        for _ in 0..code_len {
+            let code_bit: bool = bit_reader.read_bit()?;
+            code.push(code_bit as u8);
+        }
+        codes.push((symbol, code));
+    }
+    if num_pairs == 0 {
+        return Ok(Vec::new());
+    }
+    if num_pairs == 1 {
+        // the count is not backed by any coded bits, so it is not trusted to allocate
+        let mut original_data = Vec::new();
+        if usize::try_from(num_symbols)
+            .ok()
+            .and_then(|num_symbols| original_data.try_reserve_exact(num_symbols).ok())
+            .is_none()
+        {
+            bail!("implausible number of symbols: {}", num_symbols);
         }
-        bytes_read = file.read(&mut buffer)?;
+        original_data.resize(num_symbols as usize, codes[0].0);
+        return Ok(original_data);
     }
 
-    let mut compressed_file = File::create_new(compressed_file_path)?;
-    compressed_file.write(compressed_data.as_slice())?;
+    let tree = compile_read_tree::<BigEndian, u8>(codes)?;
+    let mut original_data: Vec<u8> = Vec::new();
+    for _ in 0..num_symbols {
+        original_data.push(bit_reader.read_huffman(&tree)?);
+    }
+    Ok(original_data)
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
 
-    drop(file);
-    drop(compressed_file);
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(FILE_MAGIC)?;
+    compressed_file.write_all(&[FILE_VERSION])?;
+    compressed_file.write_all(compressed_data.as_slice())?;
 
     Ok(())
 }
 
-pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
-    // read all contents of `compressed_file` in `compressed_data`
-    let mut compressed_file = File::open(compressed_file_path)?;
-    let mut compressed_data: Vec<u8> = Vec::new();
-    compressed_file.read_to_end(&mut compressed_data)?;
-    let mut bit_reader = BitReader::endian(Cursor::new(&compressed_data), BigEndian);
-
-    // read the symbol table from the file
+/// Decompresses a file of the earlier releases, made of the code table and the codes
+/// of the data up to the padding of the last byte, which may decode as extra symbols
+fn decompress_headerless(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let mut bit_reader = BitReader::endian(Cursor::new(compressed_data), BigEndian);
     let mut codes: Vec<(u8, Vec<u8>)> = Vec::new();
     let num_pairs: u32 = bit_reader.read_as_to::<BigEndian, u32>()?;
     for _ in 0..num_pairs {
@@ -188,14 +419,28 @@ pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) ->
 
     let tree = compile_read_tree::<BigEndian, u8>(codes)?;
     let mut original_data: Vec<u8> = Vec::new();
-    let mut byte = bit_reader.read_huffman(&tree);
-    while byte.is_ok() {
-        original_data.push(byte?);
-        byte = bit_reader.read_huffman(&tree);
+    while let std::result::Result::Ok(byte) = bit_reader.read_huffman(&tree) {
+        original_data.push(byte);
     }
+    Ok(original_data)
+}
 
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    // read all contents of `compressed_file` in `compressed_data`
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = match compressed_data.strip_prefix(FILE_MAGIC) {
+        Some(versioned_data) => match versioned_data.split_first() {
+            Some((&FILE_VERSION, data)) => decompress(data)?,
+            Some((version, _)) => bail!("unsupported .huffmann file version {}", version),
+            None => bail!("truncated .huffmann file"),
+        },
+        None => decompress_headerless(&compressed_data)?,
+    };
     let mut original_file = File::create_new(restored_file_path)?;
-    original_file.write(&original_data.as_slice())?;
+    original_file.write_all(original_data.as_slice())?;
 
     Ok(())
 }
@@ -203,11 +448,24 @@ pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) ->
 #[cfg(test)]
 mod tests {
 
+    use super::build_codes;
+    use super::code_lengths;
+    use super::compress;
     use super::compress_file;
+    use super::decompress;
     use super::decompress_file;
+    use super::symbol_base;
+    use super::value_symbol;
+    use super::FILE_MAGIC;
     use anyhow::Ok;
     use anyhow::Result;
+    use bitstream_io::BigEndian;
+    use bitstream_io::BitWrite;
+    use bitstream_io::BitWriter;
+    use std::collections::HashMap;
+    use std::fs::read;
     use std::fs::remove_file;
+    use std::fs::write;
     use std::fs::File;
     use std::io::Write;
 
@@ -228,7 +486,7 @@ mod tests {
     #[test]
     fn test_huffmann() -> Result<()> {
         let mut file = File::create_new("sample.txt").unwrap();
-        file.write(FILE_CONTENTS.as_bytes()).unwrap();
+        file.write_all(FILE_CONTENTS.as_bytes()).unwrap();
 
         compress_file("sample.txt", "compressed")?;
         assert!(get_file_size_bytes("compressed") > 0);
@@ -236,10 +494,128 @@ mod tests {
 
         decompress_file("compressed", "sample_restored.txt")?;
         assert!(get_file_size_bytes("compressed") > 0);
+        assert!(read("compressed")?.starts_with(FILE_MAGIC));
+        assert_eq!(read("sample_restored.txt")?, FILE_CONTENTS.as_bytes());
         remove_file("sample.txt")?;
         remove_file("compressed")?;
         remove_file("sample_restored.txt")?;
 
+        // files of earlier releases have no header, the code table comes first
+        let mut legacy_data = Vec::new();
+        let mut bit_writer = BitWriter::endian(&mut legacy_data, BigEndian);
+        bit_writer.write_bytes(&2u32.to_be_bytes())?;
+        for (symbol, code_bit) in [(b'a', false), (b'b', true)] {
+            bit_writer.write_bytes(&[symbol])?;
+            bit_writer.write_bytes(&1u32.to_be_bytes())?;
+            bit_writer.write_bit(code_bit)?;
+        }
+        // the table takes 114 bits, 14 codes end the stream on a byte boundary
+        bit_writer.write(14, 0b01_1001_0110_0110u16)?;
+        bit_writer.byte_align()?;
+        write("legacy.huffmann", &legacy_data)?;
+        decompress_file("legacy.huffmann", "legacy_restored.txt")?;
+        assert_eq!(read("legacy_restored.txt")?, b"abbaababbaabba");
+        remove_file("legacy.huffmann")?;
+        remove_file("legacy_restored.txt")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_huffmann_round_trip() -> Result<()> {
+        let inputs: [&[u8]; 4] = [b"", b"aaaa", &[0u8, 0, 1, 0, 255], FILE_CONTENTS.as_bytes()];
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)?)?, input);
+            // ties between symbols of equal weight are broken the same way every time
+            assert_eq!(compress(input)?, compress(input)?);
+        }
+
+        // a single symbol repeated more times than can be allocated
+        let mut corrupted = compress(b"aaaa")?;
+        corrupted[..8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(decompress(&corrupted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_lengths() -> Result<()> {
+        assert_eq!(code_lengths(&[0, 0, 0], 15), [0, 0, 0]);
+        assert_eq!(code_lengths(&[0, 5, 0], 15), [0, 1, 0]);
+        assert_eq!(code_lengths(&[1, 1, 2, 4], 15), [3, 3, 2, 1]);
+        assert_eq!(code_lengths(&[1, 1, 1, 1, 1], 15), [3, 3, 2, 2, 2]);
+
+        // Fibonacci weights give the deepest trees, the lengths must be limited
+        let mut weights = vec![1u32, 1];
+        while weights.len() < 30 {
+            weights.push(weights[weights.len() - 1] + weights[weights.len() - 2]);
+        }
+        let unlimited_lengths = code_lengths(&weights, 32);
+        assert_eq!(*unlimited_lengths.iter().max().unwrap(), 29);
+        for max_length in [7, 15] {
+            let lengths = code_lengths(&weights, max_length);
+            assert!(lengths
+                .iter()
+                .all(|length| (1..=max_length).contains(length)));
+            // the code remains complete
+            let kraft_sum: f64 = lengths
+                .iter()
+                .map(|length| 0.5f64.powi(*length as i32))
+                .sum();
+            assert_eq!(kraft_sum, 1.0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_symbols() -> Result<()> {
+        assert_eq!(value_symbol(3), (3, 0, 0));
+        assert_eq!(value_symbol(4), (4, 1, 0));
+        assert_eq!(value_symbol(7), (5, 1, 1));
+        assert_eq!(value_symbol(255), (15, 6, 63));
+        assert_eq!(value_symbol((1 << 20) - 1).0, 39);
+        for value in 0..5000 {
+            let (symbol, extra_bits, extra) = value_symbol(value);
+            assert_eq!(symbol_base(symbol), (value - extra, extra_bits));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_codes() -> Result<()> {
+        assert!(build_codes::<u16>(&HashMap::new()).is_empty());
+        assert_eq!(
+            build_codes(&HashMap::from([(300u16, 7)]))[&300],
+            Vec::<u8>::new()
+        );
+
+        // the 286 literal and length symbols of DEFLATE, and 4096 LZW codes
+        let literal_lengths: HashMap<u16, u32> = (0..286u16)
+            .map(|symbol| (symbol, 1 + (symbol as u32 * 7919) % 1000))
+            .collect();
+        let lzw_codes: HashMap<u32, u32> = (0..4096u32)
+            .map(|code| (code, 1 + 4096 / (code + 1)))
+            .collect();
+        fn check_codes<S: Copy + Ord + std::hash::Hash>(symbol_table: &HashMap<S, u32>) {
+            let codes = build_codes(symbol_table);
+            assert_eq!(codes.len(), symbol_table.len());
+            // the code is complete and heavier symbols get codes no longer than lighter ones
+            let kraft_sum: f64 = codes
+                .values()
+                .map(|code| 0.5f64.powi(code.len() as i32))
+                .sum();
+            assert_eq!(kraft_sum, 1.0);
+            let mut weights_and_lengths: Vec<(u32, usize)> = codes
+                .iter()
+                .map(|(symbol, code)| (symbol_table[symbol], code.len()))
+                .collect();
+            weights_and_lengths
+                .sort_by_key(|(weight, length)| (*weight, std::cmp::Reverse(*length)));
+            assert!(weights_and_lengths
+                .windows(2)
+                .all(|pair| pair[0].1 >= pair[1].1));
+        }
+        check_codes(&literal_lengths);
+        check_codes(&lzw_codes);
         Ok(())
     }
 }
@@ -0,0 +1,13 @@
+pub mod archiver;
+pub mod checksum;
+pub mod codec;
+pub mod deflate;
+pub mod gzip;
+pub mod huffmann;
+pub mod lzss;
+pub mod lzss_huffman;
+pub mod lzw;
+pub mod lzw_huffman;
+#[cfg(test)]
+mod test_util;
+pub mod zlib;
@@ -0,0 +1,378 @@
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+use bitstream_io::BigEndian;
+use bitstream_io::BitRead;
+use bitstream_io::BitReader;
+use bitstream_io::BitWrite;
+use bitstream_io::BitWriter;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+
+// LZSS files start with the number of bits of the window and the minimum match length,
+// followed by a stream of tokens, each preceded by a flag bit: 0 for a literal byte
+// and 1 for a back-reference to earlier data, written as its distance minus one (as
+// many bits as the window) and its length minus the minimum match length (8 bits).
+// Back-references are only written when they are shorter than the literals they replace.
+const HEADER_SIZE: usize = 2;
+const MIN_WINDOW_BITS: u32 = 12;
+const MAX_WINDOW_BITS: u32 = 20;
+const MIN_MATCH_LENGTH: usize = 2;
+const MAX_MIN_MATCH_LENGTH: usize = 32;
+const LENGTH_BITS: u32 = 8;
+const LITERAL_BITS: u64 = 9;
+
+const MAX_CHAIN_LENGTH: usize = 256;
+const HASH_BITS: u32 = 16;
+/// number of bytes hashed to find match candidates, at most the minimum match length
+const MAX_HASH_LENGTH: usize = 4;
+
+/// Parameters of the LZSS parse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LzssOptions {
+    /// the window is 2^`window_bits` bytes, from 4 KiB to 1 MiB
+    pub window_bits: u32,
+    pub min_match_length: usize,
+}
+
+impl Default for LzssOptions {
+    fn default() -> Self {
+        LzssOptions {
+            window_bits: 16,
+            min_match_length: 3,
+        }
+    }
+}
+
+impl LzssOptions {
+    fn check(&self) -> Result<()> {
+        if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&self.window_bits) {
+            bail!(
+                "the LZSS window must be between 2^{} and 2^{} bytes",
+                MIN_WINDOW_BITS,
+                MAX_WINDOW_BITS
+            );
+        }
+        if !(MIN_MATCH_LENGTH..=MAX_MIN_MATCH_LENGTH).contains(&self.min_match_length) {
+            bail!(
+                "the minimum LZSS match length must be between {} and {}",
+                MIN_MATCH_LENGTH,
+                MAX_MIN_MATCH_LENGTH
+            );
+        }
+        Ok(())
+    }
+
+    fn window_size(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    fn max_match_length(&self) -> usize {
+        self.min_match_length + (1 << LENGTH_BITS) - 1
+    }
+}
+
+/// A literal byte or a back-reference to earlier data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+impl Token {
+    /// Number of bytes of data the token stands for
+    pub fn data_len(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => *length,
+        }
+    }
+}
+
+/// Chains the positions of the data by the hash of the bytes starting there,
+/// most recent first
+struct HashChains {
+    head: Vec<usize>,
+    previous: Vec<usize>,
+    hash_length: usize,
+}
+
+impl HashChains {
+    fn new(data_len: usize, hash_length: usize) -> Self {
+        HashChains {
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            previous: vec![usize::MAX; data_len],
+            hash_length,
+        }
+    }
+
+    fn hash(&self, data: &[u8], position: usize) -> usize {
+        let mut value = 0u32;
+        for byte in data[position..position + self.hash_length].iter() {
+            value = (value << 8) | *byte as u32;
+        }
+        (value.wrapping_mul(0x9e3779b1) >> (u32::BITS - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + self.hash_length <= data.len() {
+            let hash = self.hash(data, position);
+            self.previous[position] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// Returns the length and distance of the longest match of the data at `position`
+    /// with the data at an earlier position of the window, if it is long enough
+    fn longest_match(
+        &self,
+        data: &[u8],
+        position: usize,
+        options: &LzssOptions,
+    ) -> Option<(usize, usize)> {
+        let max_length = options.max_match_length().min(data.len() - position);
+        if max_length < options.min_match_length {
+            return None;
+        }
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        let mut candidate = self.head[self.hash(data, position)];
+        let mut chain_length = 0;
+        while candidate != usize::MAX
+            && position - candidate <= options.window_size()
+            && chain_length < MAX_CHAIN_LENGTH
+        {
+            // the earlier bytes may run into the bytes being matched
+            let length = data[candidate..]
+                .iter()
+                .zip(data[position..position + max_length].iter())
+                .take_while(|(earlier, current)| earlier == current)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_distance = position - candidate;
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate];
+            chain_length += 1;
+        }
+        (best_length >= options.min_match_length).then_some((best_length, best_distance))
+    }
+}
+
+/// Parses `data` into literals and back-references with hash chains, taking the
+/// longest match among the most recent candidates within the window. Matches are
+/// evaluated lazily: if the next byte starts a longer match, the current byte is
+/// written as a literal and the longer match is taken instead.
+pub fn parse(data: &[u8], options: &LzssOptions) -> Result<Vec<Token>> {
+    options.check()?;
+    let mut tokens = Vec::new();
+    let hash_length = options.min_match_length.min(MAX_HASH_LENGTH);
+    let mut chains = HashChains::new(data.len(), hash_length);
+    let mut position = 0;
+    while position < data.len() {
+        let current_match = chains.longest_match(data, position, options);
+        chains.insert(data, position);
+        let Some((length, distance)) = current_match else {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+            continue;
+        };
+        if let Some((next_length, _)) = chains.longest_match(data, position + 1, options) {
+            if next_length > length {
+                tokens.push(Token::Literal(data[position]));
+                position += 1;
+                continue;
+            }
+        }
+        tokens.push(Token::Match { length, distance });
+        for matched_position in position + 1..position + length {
+            chains.insert(data, matched_position);
+        }
+        position += length;
+    }
+    Ok(tokens)
+}
+
+/// Compresses `data` as an LZSS file with the given window and minimum match length
+pub fn compress_with_options(data: &[u8], options: &LzssOptions) -> Result<Vec<u8>> {
+    let tokens = parse(data, options)?;
+    let mut compressed_data = vec![options.window_bits as u8, options.min_match_length as u8];
+    let mut bit_writer = BitWriter::endian(&mut compressed_data, BigEndian);
+    let match_bits = 1 + options.window_bits as u64 + LENGTH_BITS as u64;
+    let mut data_offset = 0;
+    for token in tokens.iter() {
+        match *token {
+            Token::Match { length, distance } if match_bits < LITERAL_BITS * length as u64 => {
+                bit_writer.write_bit(true)?;
+                bit_writer.write(options.window_bits, distance as u32 - 1)?;
+                bit_writer.write(LENGTH_BITS, (length - options.min_match_length) as u32)?;
+            }
+            _ => {
+                // a short match with a wide window takes more bits than its literals
+                for byte in data[data_offset..data_offset + token.data_len()].iter() {
+                    bit_writer.write_bit(false)?;
+                    bit_writer.write(8, *byte)?;
+                }
+            }
+        }
+        data_offset += token.data_len();
+    }
+    bit_writer.byte_align()?;
+    Ok(compressed_data)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    compress_with_options(data, &LzssOptions::default())
+}
+
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    if compressed_data.len() < HEADER_SIZE {
+        bail!("truncated LZSS header");
+    }
+    let options = LzssOptions {
+        window_bits: compressed_data[0] as u32,
+        min_match_length: compressed_data[1] as usize,
+    };
+    options.check()?;
+    let codes = &compressed_data[HEADER_SIZE..];
+    let num_bits = codes.len() as u64 * 8;
+    let match_bits = 1 + options.window_bits as u64 + LENGTH_BITS as u64;
+    let mut bit_reader = BitReader::endian(Cursor::new(codes), BigEndian);
+    let mut position = 0;
+    let mut data = Vec::new();
+    // the last byte is padded with fewer bits than a token
+    while num_bits - position >= LITERAL_BITS {
+        if !bit_reader.read_bit()? {
+            data.push(bit_reader.read::<u8>(8)?);
+            position += LITERAL_BITS;
+            continue;
+        }
+        if num_bits - position < match_bits {
+            bail!("truncated LZSS back-reference");
+        }
+        let distance = bit_reader.read::<u32>(options.window_bits)? as usize + 1;
+        let length = bit_reader.read::<u32>(LENGTH_BITS)? as usize + options.min_match_length;
+        position += match_bits;
+        if distance > data.len() {
+            bail!("LZSS back-reference before the start of the data");
+        }
+        // the referenced bytes may overlap the ones being copied
+        let start = data.len() - distance;
+        for i in 0..length {
+            data.push(data[start + i]);
+        }
+    }
+    Ok(data)
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::compress_with_options;
+    use super::decompress;
+    use super::parse;
+    use super::LzssOptions;
+    use super::Token;
+    use crate::test_util::pseudo_random_bytes;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_lzss_parse() -> Result<()> {
+        let tokens = parse(b"abcabcabcd", &LzssOptions::default())?;
+        assert_eq!(
+            tokens,
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 6,
+                    distance: 3
+                },
+                Token::Literal(b'd'),
+            ]
+        );
+        // a flag bit and 8 bits per literal, a flag bit, 16 bits of distance
+        // and 8 bits of length for the match
+        let compressed_data = compress(b"abcabcabcd")?;
+        assert_eq!(compressed_data.len(), 2 + (4 * 9 + 25_usize).div_ceil(8));
+        assert_eq!(&compressed_data[..3], &[16, 3, 0x30]);
+
+        let too_narrow = LzssOptions {
+            window_bits: 11,
+            ..LzssOptions::default()
+        };
+        assert!(parse(b"", &too_narrow).is_err());
+        let too_short = LzssOptions {
+            min_match_length: 1,
+            ..LzssOptions::default()
+        };
+        assert!(compress_with_options(b"", &too_short).is_err());
+        // a back-reference to before the start of the data
+        assert!(decompress(&[16, 3, 0x80, 0, 0x80, 0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzss_round_trip() -> Result<()> {
+        let sample = fs::read("testdata/sample.txt")?;
+        let random = pseudo_random_bytes(11, 20_000);
+        // the random bytes repeat 8 KiB apart, out of reach of a 4 KiB window
+        let mut distant = random[..8192].to_vec();
+        distant.extend_from_slice(&random[..8192]);
+        let runs = [vec![b'a'; 1000], vec![b'b'; 300]].concat();
+        let inputs: [&[u8]; 6] = [b"", b"a", &sample, &random, &distant, &runs];
+        for window_bits in [12, 16, 20] {
+            for min_match_length in [2, 3, 5] {
+                let options = LzssOptions {
+                    window_bits,
+                    min_match_length,
+                };
+                for input in inputs {
+                    let compressed_data = compress_with_options(input, &options)?;
+                    assert_eq!(decompress(&compressed_data)?, input);
+                }
+            }
+        }
+        assert!(compress(&sample)?.len() < sample.len() / 2);
+        let narrow = LzssOptions {
+            window_bits: 12,
+            ..LzssOptions::default()
+        };
+        assert!(compress_with_options(&distant, &narrow)?.len() > distant.len());
+        assert!(compress(&distant)?.len() < distant.len() * 3 / 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use crate::huffmann;
+use crate::huffmann::canonical_codes;
+use crate::huffmann::symbol_base;
+use crate::huffmann::value_symbol;
+use crate::huffmann::HuffmanDecoder;
+use crate::lzss;
+use crate::lzss::LzssOptions;
+use crate::lzss::Token;
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+use bitstream_io::BigEndian;
+use bitstream_io::BitRead;
+use bitstream_io::BitReader;
+use bitstream_io::BitWrite;
+use bitstream_io::BitWriter;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+
+// The data is parsed by LZSS with a 1 MiB window, and the tokens are written in blocks,
+// each with its own canonical Huffman codes: one for the literals, the end of block and
+// the match lengths, and one for the match distances. A block starts with a bit telling
+// whether it is the last block and the code lengths of both codes, each written as a
+// bit telling whether it changes from the previous one and, if it does, in 4 bits.
+// Lengths and distances are written as a symbol followed by extra bits, as given by
+// `huffmann::value_symbol`.
+const PARSE_OPTIONS: LzssOptions = LzssOptions {
+    window_bits: 20,
+    min_match_length: 3,
+};
+
+const END_OF_BLOCK: u16 = 256;
+const FIRST_LENGTH_SYMBOL: u16 = 257;
+/// symbols of the match lengths, up to the maximum length of 258
+const NUM_LENGTH_SYMBOLS: usize = 16;
+const NUM_LITERAL_LENGTH_SYMBOLS: usize = FIRST_LENGTH_SYMBOL as usize + NUM_LENGTH_SYMBOLS;
+/// symbols of the distances, up to the window size of 1 MiB
+const NUM_DISTANCE_SYMBOLS: usize = 40;
+
+const MAX_CODE_LENGTH: u8 = 15;
+const CODE_LENGTH_BITS: u32 = 4;
+const MAX_BLOCK_TOKENS: usize = 1 << 16;
+
+/// Writes code lengths, each as a bit telling whether it differs from the previous one
+/// followed by the new length
+fn write_code_lengths<W: BitWrite>(bit_writer: &mut W, lengths: &[u8]) -> Result<()> {
+    let mut previous_length = 0;
+    for length in lengths.iter() {
+        bit_writer.write_bit(*length != previous_length)?;
+        if *length != previous_length {
+            bit_writer.write(CODE_LENGTH_BITS, *length)?;
+            previous_length = *length;
+        }
+    }
+    Ok(())
+}
+
+fn read_code_lengths<R: BitRead>(bit_reader: &mut R, num_symbols: usize) -> Result<Vec<u8>> {
+    let mut lengths = Vec::with_capacity(num_symbols);
+    let mut previous_length = 0;
+    for _ in 0..num_symbols {
+        if bit_reader.read_bit()? {
+            previous_length = bit_reader.read::<u8>(CODE_LENGTH_BITS)?;
+        }
+        lengths.push(previous_length);
+    }
+    Ok(lengths)
+}
+
+/// Writes a block of tokens with Huffman codes built from their frequencies
+fn write_block<W: BitWrite>(bit_writer: &mut W, tokens: &[Token], is_last: bool) -> Result<()> {
+    let mut literal_length_frequencies = vec![0u32; NUM_LITERAL_LENGTH_SYMBOLS];
+    let mut distance_frequencies = vec![0u32; NUM_DISTANCE_SYMBOLS];
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => literal_length_frequencies[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                let length_symbol =
+                    value_symbol((length - PARSE_OPTIONS.min_match_length) as u32).0;
+                literal_length_frequencies[(FIRST_LENGTH_SYMBOL + length_symbol) as usize] += 1;
+                distance_frequencies[value_symbol(distance as u32 - 1).0 as usize] += 1;
+            }
+        }
+    }
+    literal_length_frequencies[END_OF_BLOCK as usize] += 1;
+    let literal_length_lengths =
+        huffmann::code_lengths(&literal_length_frequencies, MAX_CODE_LENGTH);
+    let distance_lengths = huffmann::code_lengths(&distance_frequencies, MAX_CODE_LENGTH);
+    let literal_length_codes = canonical_codes(&literal_length_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+
+    bit_writer.write_bit(is_last)?;
+    write_code_lengths(bit_writer, &literal_length_lengths)?;
+    write_code_lengths(bit_writer, &distance_lengths)?;
+    let write_symbol = |bit_writer: &mut W, codes: &[u16], lengths: &[u8], symbol: usize| {
+        bit_writer.write(lengths[symbol] as u32, codes[symbol])
+    };
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => write_symbol(
+                bit_writer,
+                &literal_length_codes,
+                &literal_length_lengths,
+                byte as usize,
+            )?,
+            Token::Match { length, distance } => {
+                let (symbol, extra_bits, extra) =
+                    value_symbol((length - PARSE_OPTIONS.min_match_length) as u32);
+                write_symbol(
+                    bit_writer,
+                    &literal_length_codes,
+                    &literal_length_lengths,
+                    (FIRST_LENGTH_SYMBOL + symbol) as usize,
+                )?;
+                bit_writer.write(extra_bits, extra)?;
+                let (symbol, extra_bits, extra) = value_symbol(distance as u32 - 1);
+                write_symbol(
+                    bit_writer,
+                    &distance_codes,
+                    &distance_lengths,
+                    symbol as usize,
+                )?;
+                bit_writer.write(extra_bits, extra)?;
+            }
+        }
+    }
+    write_symbol(
+        bit_writer,
+        &literal_length_codes,
+        &literal_length_lengths,
+        END_OF_BLOCK as usize,
+    )?;
+    Ok(())
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let tokens = lzss::parse(data, &PARSE_OPTIONS)?;
+    let mut compressed_data = Vec::new();
+    let mut bit_writer = BitWriter::endian(&mut compressed_data, BigEndian);
+    if tokens.is_empty() {
+        write_block(&mut bit_writer, &[], true)?;
+    }
+    let num_blocks = tokens.len().div_ceil(MAX_BLOCK_TOKENS);
+    for (block_index, block_tokens) in tokens.chunks(MAX_BLOCK_TOKENS).enumerate() {
+        write_block(&mut bit_writer, block_tokens, block_index == num_blocks - 1)?;
+    }
+    bit_writer.byte_align()?;
+    Ok(compressed_data)
+}
+
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let mut bit_reader = BitReader::endian(Cursor::new(compressed_data), BigEndian);
+    let mut data = Vec::new();
+    loop {
+        let is_last_block = bit_reader.read_bit()?;
+        let literal_length_decoder = HuffmanDecoder::new(&read_code_lengths(
+            &mut bit_reader,
+            NUM_LITERAL_LENGTH_SYMBOLS,
+        )?)?;
+        let distance_decoder =
+            HuffmanDecoder::new(&read_code_lengths(&mut bit_reader, NUM_DISTANCE_SYMBOLS)?)?;
+        loop {
+            let symbol = literal_length_decoder.decode(&mut bit_reader)?;
+            if symbol < END_OF_BLOCK {
+                data.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                break;
+            }
+            let (base, extra_bits) = symbol_base(symbol - FIRST_LENGTH_SYMBOL);
+            let length = (base + bit_reader.read::<u32>(extra_bits)?) as usize
+                + PARSE_OPTIONS.min_match_length;
+            let (base, extra_bits) = symbol_base(distance_decoder.decode(&mut bit_reader)?);
+            let distance = (base + bit_reader.read::<u32>(extra_bits)?) as usize + 1;
+            if distance > data.len() {
+                bail!("back-reference before the start of the data");
+            }
+            // the referenced bytes may overlap the ones being copied
+            let start = data.len() - distance;
+            for i in 0..length {
+                data.push(data[start + i]);
+            }
+        }
+        if is_last_block {
+            return Ok(data);
+        }
+    }
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::decompress;
+    use crate::deflate;
+    use crate::test_util::pseudo_random_bytes;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_lzss_huffman_round_trip() -> Result<()> {
+        let sample = fs::read("testdata/sample.txt")?;
+        let random = pseudo_random_bytes(5, 100_000);
+        let repeated_text = sample.repeat(40);
+        let inputs: [&[u8]; 5] = [b"", b"a", &sample, &random, &repeated_text];
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)?)?, input);
+        }
+
+        // on par with DEFLATE on text, and far better on repetitions beyond its window
+        let compressed_len = compress(&sample)?.len();
+        assert!(compressed_len < deflate::compress(&sample)?.len() * 105 / 100);
+        let distant = random.repeat(2);
+        assert!(compress(&distant)?.len() < random.len() * 102 / 100);
+        assert!(deflate::compress(&distant)?.len() > random.len() * 2);
+
+        assert!(decompress(&[0x80]).is_err());
+        Ok(())
+    }
+}
@@ -1,21 +1,734 @@
+use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
+use bitstream_io::BigEndian;
+use bitstream_io::BitRead;
+use bitstream_io::BitReader;
+use bitstream_io::BitWrite;
+use bitstream_io::BitWriter;
+use bitstream_io::Endianness;
+use bitstream_io::LittleEndian;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+
+// codes 0-255 represent single bytes, code 256 resets the dictionary
+// and new strings are assigned codes starting from 257
+const CLEAR_CODE: u32 = 256;
+const FIRST_CODE: u32 = 257;
+const MIN_CODE_WIDTH: u32 = 9;
+const MAX_CODE_WIDTH: u32 = 16;
+
+/// magic and version starting the files written by `compress_file`, followed by the
+/// data of `compress` (earlier releases did not write LZW files)
+const FILE_MAGIC: &[u8; 4] = b"FCLZ";
+const FILE_VERSION: u8 = 1;
+
+// The .Z files of Unix compress (and ncompress) start with the magic 1f 9d and a byte
+// giving the maximum code width and whether code 256 clears the dictionary (block
+// mode), followed by codes written least significant bit first. Codes start 9 bits
+// wide and widen when the decoder's next code no longer fits. The original decoder
+// reads codes in groups of 8, a whole number of bytes, and skips the rest of the group
+// whenever the width changes or the dictionary is cleared: encoders pad the group with
+// zero bits at those points.
+const UNIX_MAGIC: [u8; 2] = [0x1f, 0x9d];
+const UNIX_HEADER_SIZE: usize = 3;
+const UNIX_MAX_WIDTH_MASK: u8 = 0x1f;
+const UNIX_BLOCK_MODE: u8 = 0x80;
+const UNIX_GROUP_SIZE: u64 = 8;
+/// the original decoder widens 9-bit codes once the dictionary is full, so files
+/// with 9-bit codes are only readable while they do not fill it
+const UNIX_MIN_MAX_WIDTH: u32 = 10;
+/// number of bytes compressed between checks of the compression ratio once the
+/// dictionary is full, the dictionary being cleared when the ratio drops
+const UNIX_CHECK_INTERVAL: usize = 10_000;
+
+/// Order in which the bits of codes are packed into bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// least significant bit first, as in GIF images and .Z files
+    LsbFirst,
+    /// most significant bit first, as in TIFF images and the crate's LZW files
+    MsbFirst,
+}
+
+/// Parameters of a variant of LZW. Codes below 2^`literal_width` stand for themselves,
+/// followed by the clear and end codes if the variant has them, and the codes of the
+/// strings added to the dictionary. Codes start one bit wider than the literals and
+/// widen when the decoder's next code would not fit, or one code earlier with
+/// `early_change`, up to `max_code_width`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LzwOptions {
+    pub bit_order: BitOrder,
+    pub literal_width: u32,
+    pub max_code_width: u32,
+    /// whether a code clears the dictionary, which the encoder does once it is full
+    pub clear_code: bool,
+    /// whether the encoder starts with a clear code
+    pub initial_clear: bool,
+    /// whether a code ends the stream, written by the encoder after the data
+    pub end_code: bool,
+    pub early_change: bool,
+}
+
+/// Options of the crate's LZW files, which give the length of the data instead
+/// of ending with an end code
+const FILE_OPTIONS: LzwOptions = LzwOptions {
+    bit_order: BitOrder::MsbFirst,
+    literal_width: 8,
+    max_code_width: MAX_CODE_WIDTH,
+    clear_code: true,
+    initial_clear: false,
+    end_code: false,
+    early_change: false,
+};
+
+impl LzwOptions {
+    /// Options of GIF image data whose pixels are `literal_width` bits wide
+    /// (the minimum code size of the image)
+    pub fn gif(literal_width: u32) -> Self {
+        LzwOptions {
+            bit_order: BitOrder::LsbFirst,
+            literal_width,
+            max_code_width: 12,
+            clear_code: true,
+            initial_clear: true,
+            end_code: true,
+            early_change: false,
+        }
+    }
+
+    /// Options of LZW-compressed TIFF strips
+    pub fn tiff() -> Self {
+        LzwOptions {
+            bit_order: BitOrder::MsbFirst,
+            literal_width: 8,
+            max_code_width: 12,
+            clear_code: true,
+            initial_clear: true,
+            end_code: true,
+            early_change: true,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if !(2..=8).contains(&self.literal_width) {
+            bail!("LZW literals must be between 2 and 8 bits wide");
+        }
+        if !(self.literal_width + 1..=MAX_CODE_WIDTH).contains(&self.max_code_width) {
+            bail!(
+                "the maximum LZW code width must be between {} and {} bits",
+                self.literal_width + 1,
+                MAX_CODE_WIDTH
+            );
+        }
+        if self.initial_clear && !self.clear_code {
+            bail!("LZW streams cannot start with a clear code without one");
+        }
+        Ok(())
+    }
+
+    fn clear_code(&self) -> Option<u32> {
+        self.clear_code.then_some(1 << self.literal_width)
+    }
+
+    fn end_code(&self) -> Option<u32> {
+        self.end_code
+            .then_some((1 << self.literal_width) + self.clear_code as u32)
+    }
+
+    fn first_code(&self) -> u32 {
+        (1 << self.literal_width) + self.clear_code as u32 + self.end_code as u32
+    }
+
+    /// Width of the code read when the decoder's next code is `next_code`
+    fn code_width(&self, next_code: u32) -> u32 {
+        let max_code = next_code + self.early_change as u32;
+        (u32::BITS - max_code.leading_zeros()).clamp(self.literal_width + 1, self.max_code_width)
+    }
+}
+
+/// Writes LZW codes with the width the decoder expects
+struct CodeWriter<'a, E: Endianness> {
+    bit_writer: BitWriter<&'a mut Vec<u8>, E>,
+    options: &'a LzwOptions,
+    /// the decoder assigns codes one code later than the encoder
+    decoder_next_code: u32,
+    is_first_code: bool,
+}
+
+impl<'a, E: Endianness> CodeWriter<'a, E> {
+    fn write_code(&mut self, code: u32) -> Result<()> {
+        self.bit_writer
+            .write(self.options.code_width(self.decoder_next_code), code)?;
+        if Some(code) == self.options.clear_code() {
+            self.decoder_next_code = self.options.first_code();
+            self.is_first_code = true;
+        } else {
+            if !self.is_first_code && self.decoder_next_code < 1 << self.options.max_code_width {
+                self.decoder_next_code += 1;
+            }
+            self.is_first_code = false;
+        }
+        Ok(())
+    }
+}
+
+/// Runs LZW over `data`, passing each code to `write_code`
+fn encode_codes(
+    data: &[u8],
+    options: &LzwOptions,
+    mut write_code: impl FnMut(u32) -> Result<()>,
+) -> Result<()> {
+    if let (true, Some(clear_code)) = (options.initial_clear, options.clear_code()) {
+        write_code(clear_code)?;
+    }
+
+    // maps (code of prefix, next byte) to the code of the extended string,
+    // the last code being left unused as by GIF and TIFF encoders
+    let mut dictionary: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next_code = options.first_code();
+    let max_next_code = (1 << options.max_code_width) - 1;
+    let mut prefix: Option<u32> = None;
+    for byte in data.iter() {
+        if (*byte as u32) >> options.literal_width != 0 {
+            bail!(
+                "{} is not a {}-bit LZW literal",
+                byte,
+                options.literal_width
+            );
+        }
+        let Some(prefix_code) = prefix else {
+            prefix = Some(*byte as u32);
+            continue;
+        };
+        if let Some(code) = dictionary.get(&(prefix_code, *byte)) {
+            prefix = Some(*code);
+            continue;
+        }
+        write_code(prefix_code)?;
+        if next_code < max_next_code {
+            dictionary.insert((prefix_code, *byte), next_code);
+            next_code += 1;
+        } else if let Some(clear_code) = options.clear_code() {
+            write_code(clear_code)?;
+            dictionary.clear();
+            next_code = options.first_code();
+        }
+        prefix = Some(*byte as u32);
+    }
+    if let Some(prefix_code) = prefix {
+        write_code(prefix_code)?;
+    }
+    if let Some(end_code) = options.end_code() {
+        write_code(end_code)?;
+    }
+    Ok(())
+}
+
+fn write_codes<E: Endianness>(
+    data: &[u8],
+    options: &LzwOptions,
+    bit_writer: BitWriter<&mut Vec<u8>, E>,
+) -> Result<()> {
+    let mut code_writer = CodeWriter {
+        bit_writer,
+        options,
+        decoder_next_code: options.first_code(),
+        is_first_code: true,
+    };
+    encode_codes(data, options, |code| code_writer.write_code(code))?;
+    code_writer.bit_writer.byte_align()?;
+    Ok(())
+}
+
+/// Encodes `data` as LZW codes as described by `options`
+pub fn encode(data: &[u8], options: &LzwOptions) -> Result<Vec<u8>> {
+    options.check()?;
+    let mut codes = Vec::new();
+    match options.bit_order {
+        BitOrder::LsbFirst => {
+            write_codes(data, options, BitWriter::endian(&mut codes, LittleEndian))?
+        }
+        BitOrder::MsbFirst => write_codes(data, options, BitWriter::endian(&mut codes, BigEndian))?,
+    }
+    Ok(codes)
+}
+
+/// Encodes `data` as the values of the LZW codes described by `options`, for stages
+/// writing the codes in their own way rather than at the widths of `encode`
+pub fn encode_values(data: &[u8], options: &LzwOptions) -> Result<Vec<u32>> {
+    options.check()?;
+    let mut codes = Vec::new();
+    encode_codes(data, options, |code| {
+        codes.push(code);
+        Ok(())
+    })?;
+    Ok(codes)
+}
+
+/// Decodes LZW codes one at a time, building the dictionary as it goes
+struct CodeDecoder<'a> {
+    options: &'a LzwOptions,
+    dictionary: Vec<Vec<u8>>,
+    previous: Option<Vec<u8>>,
+}
+
+impl<'a> CodeDecoder<'a> {
+    fn new(options: &'a LzwOptions) -> Self {
+        CodeDecoder {
+            options,
+            dictionary: Vec::new(),
+            previous: None,
+        }
+    }
+
+    /// Code of the next string added to the dictionary
+    fn next_code(&self) -> u32 {
+        self.options.first_code() + self.dictionary.len() as u32
+    }
+
+    /// Appends the string of `code` to `original_data`, returning false at the end code
+    fn decode_code(&mut self, code: u32, original_data: &mut Vec<u8>) -> Result<bool> {
+        let options = self.options;
+        let next_code = self.next_code();
+        if Some(code) == options.clear_code() {
+            self.dictionary.clear();
+            self.previous = None;
+            return Ok(true);
+        }
+        if Some(code) == options.end_code() {
+            return Ok(false);
+        }
+        let string = if code >> options.literal_width == 0 {
+            vec![code as u8]
+        } else if code >= options.first_code() && code < next_code {
+            self.dictionary[(code - options.first_code()) as usize].clone()
+        } else if code == next_code && self.previous.is_some() {
+            // the code was assigned by the encoder while writing it,
+            // the string is the previous string extended by its first byte
+            let mut string = self.previous.clone().unwrap();
+            string.push(string[0]);
+            string
+        } else {
+            bail!("invalid LZW code {}", code);
+        };
+        if let Some(mut previous_string) = self.previous.take() {
+            if next_code < 1 << options.max_code_width {
+                previous_string.push(string[0]);
+                self.dictionary.push(previous_string);
+            }
+        }
+        original_data.extend_from_slice(&string);
+        self.previous = Some(string);
+        Ok(true)
+    }
+}
+
+fn decode_codes<E: Endianness>(
+    codes: &[u8],
+    options: &LzwOptions,
+    mut bit_reader: BitReader<Cursor<&[u8]>, E>,
+) -> Result<Vec<u8>> {
+    let num_bits = codes.len() as u64 * 8;
+    let mut position = 0;
+    let mut decoder = CodeDecoder::new(options);
+    let mut original_data: Vec<u8> = Vec::new();
+    loop {
+        let width = options.code_width(decoder.next_code());
+        // streams without an end code stop at the padding of their last byte
+        if num_bits - position < width as u64 {
+            break;
+        }
+        let code: u32 = bit_reader.read(width)?;
+        position += width as u64;
+        if !decoder.decode_code(code, &mut original_data)? {
+            break;
+        }
+    }
+    Ok(original_data)
+}
+
+/// Decodes the LZW `codes` described by `options`
+pub fn decode(codes: &[u8], options: &LzwOptions) -> Result<Vec<u8>> {
+    options.check()?;
+    match options.bit_order {
+        BitOrder::LsbFirst => decode_codes(
+            codes,
+            options,
+            BitReader::endian(Cursor::new(codes), LittleEndian),
+        ),
+        BitOrder::MsbFirst => decode_codes(
+            codes,
+            options,
+            BitReader::endian(Cursor::new(codes), BigEndian),
+        ),
+    }
+}
+
+/// Decodes the values of the LZW codes described by `options`, as returned by
+/// `encode_values`
+pub fn decode_values(codes: &[u32], options: &LzwOptions) -> Result<Vec<u8>> {
+    options.check()?;
+    let mut decoder = CodeDecoder::new(options);
+    let mut original_data: Vec<u8> = Vec::new();
+    for (index, code) in codes.iter().enumerate() {
+        if !decoder.decode_code(*code, &mut original_data)? {
+            if index + 1 < codes.len() {
+                bail!("LZW codes after the end code");
+            }
+            break;
+        }
+    }
+    Ok(original_data)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    // the number of bytes is written first, so that the decoder
+    // knows where the codes end (the last byte is padded)
+    let mut compressed_data = (data.len() as u64).to_be_bytes().to_vec();
+    compressed_data.extend_from_slice(&encode(data, &FILE_OPTIONS)?);
+    Ok(compressed_data)
+}
+
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let Some((num_bytes, codes)) = compressed_data.split_first_chunk::<8>() else {
+        bail!("truncated LZW data");
+    };
+    let original_data = decode(codes, &FILE_OPTIONS)?;
+    if original_data.len() as u64 != u64::from_be_bytes(*num_bytes) {
+        bail!("LZW data length mismatch");
+    }
+    Ok(original_data)
+}
+
+/// Writes the codes of a .Z file, widening them as the decoder expects
+struct UnixCodeWriter<'a> {
+    bit_writer: BitWriter<&'a mut Vec<u8>, LittleEndian>,
+    width: u32,
+    max_width: u32,
+    bits_written: u64,
+    /// bits written since the start of the current group
+    group_bits: u64,
+    /// the decoder assigns codes one code later than the encoder
+    decoder_next_code: u32,
+    is_first_code: bool,
+}
+
+impl<'a> UnixCodeWriter<'a> {
+    fn new(compressed_data: &'a mut Vec<u8>, max_width: u32) -> Self {
+        UnixCodeWriter {
+            bit_writer: BitWriter::endian(compressed_data, LittleEndian),
+            width: MIN_CODE_WIDTH,
+            max_width,
+            bits_written: 0,
+            group_bits: 0,
+            decoder_next_code: FIRST_CODE,
+            is_first_code: true,
+        }
+    }
+
+    /// Pads the current group of codes with zero bits
+    fn end_group(&mut self) -> Result<()> {
+        let group_size = UNIX_GROUP_SIZE * self.width as u64;
+        let padding = (group_size - self.group_bits % group_size) % group_size;
+        for _ in 0..padding / self.width as u64 {
+            self.bit_writer.write(self.width, 0)?;
+        }
+        self.bits_written += padding;
+        self.group_bits = 0;
+        Ok(())
+    }
+
+    fn write_code(&mut self, code: u32) -> Result<()> {
+        if self.width < self.max_width && self.decoder_next_code >= 1 << self.width {
+            self.end_group()?;
+            self.width += 1;
+        }
+        self.bit_writer.write(self.width, code)?;
+        self.bits_written += self.width as u64;
+        self.group_bits += self.width as u64;
+        if code == CLEAR_CODE {
+            self.end_group()?;
+            self.width = MIN_CODE_WIDTH;
+            // the decoder assigns the unused code 256 after a clear, having kept the
+            // previous string
+            self.decoder_next_code = CLEAR_CODE;
+        } else if !self.is_first_code && self.decoder_next_code < 1 << self.max_width {
+            self.decoder_next_code += 1;
+        }
+        self.is_first_code = false;
+        Ok(())
+    }
+}
+
+/// Compresses `data` as a .Z file with codes up to `max_width` bits wide
+pub fn compress_unix(data: &[u8], max_width: u32) -> Result<Vec<u8>> {
+    if !(UNIX_MIN_MAX_WIDTH..=MAX_CODE_WIDTH).contains(&max_width) {
+        bail!(
+            "the maximum code width must be between {} and {} bits",
+            UNIX_MIN_MAX_WIDTH,
+            MAX_CODE_WIDTH
+        );
+    }
+    let mut compressed_data = UNIX_MAGIC.to_vec();
+    compressed_data.push(max_width as u8 | UNIX_BLOCK_MODE);
+    let mut code_writer = UnixCodeWriter::new(&mut compressed_data, max_width);
+
+    let mut dictionary: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next_code = FIRST_CODE;
+    let mut prefix: Option<u32> = None;
+    let mut next_check = UNIX_CHECK_INTERVAL;
+    let mut best_ratio = 0;
+    for (position, byte) in data.iter().enumerate() {
+        let Some(prefix_code) = prefix else {
+            prefix = Some(*byte as u32);
+            continue;
+        };
+        if let Some(code) = dictionary.get(&(prefix_code, *byte)) {
+            prefix = Some(*code);
+            continue;
+        }
+        code_writer.write_code(prefix_code)?;
+        if next_code < 1 << max_width {
+            dictionary.insert((prefix_code, *byte), next_code);
+            next_code += 1;
+        } else if position >= next_check {
+            next_check = position + UNIX_CHECK_INTERVAL;
+            let ratio = ((position as u64) << 8) / code_writer.bits_written.div_ceil(8);
+            if ratio > best_ratio {
+                best_ratio = ratio;
+            } else {
+                best_ratio = 0;
+                code_writer.write_code(CLEAR_CODE)?;
+                dictionary.clear();
+                next_code = FIRST_CODE;
+            }
+        }
+        prefix = Some(*byte as u32);
+    }
+    if let Some(prefix_code) = prefix {
+        code_writer.write_code(prefix_code)?;
+    }
+    code_writer.bit_writer.byte_align()?;
+
+    Ok(compressed_data)
+}
+
+/// Whether `data` starts like a .Z file
+pub fn is_unix_compressed(data: &[u8]) -> bool {
+    data.len() >= UNIX_HEADER_SIZE && data[..2] == UNIX_MAGIC
+}
+
+/// Reads the codes of a .Z file, in groups as written by `UnixCodeWriter`
+struct UnixCodeReader<'a> {
+    bit_reader: BitReader<Cursor<&'a [u8]>, LittleEndian>,
+    num_bits: u64,
+    position: u64,
+    group_start: u64,
+    width: u32,
+}
+
+impl<'a> UnixCodeReader<'a> {
+    fn new(codes: &'a [u8]) -> Self {
+        UnixCodeReader {
+            bit_reader: BitReader::endian(Cursor::new(codes), LittleEndian),
+            num_bits: codes.len() as u64 * 8,
+            position: 0,
+            group_start: 0,
+            width: MIN_CODE_WIDTH,
+        }
+    }
+
+    /// Skips the rest of the current group of codes, returning false if the data ends
+    fn end_group(&mut self) -> Result<bool> {
+        let group_size = UNIX_GROUP_SIZE * self.width as u64;
+        let group_end =
+            self.group_start + (self.position - self.group_start).div_ceil(group_size) * group_size;
+        if group_end >= self.num_bits {
+            return Ok(false);
+        }
+        self.bit_reader.skip((group_end - self.position) as u32)?;
+        self.position = group_end;
+        self.group_start = group_end;
+        Ok(true)
+    }
+
+    /// Reads the next code, or `None` at the end of the data (the last byte being padded)
+    fn read_code(&mut self) -> Result<Option<u32>> {
+        if self.num_bits - self.position < self.width as u64 {
+            return Ok(None);
+        }
+        self.position += self.width as u64;
+        Ok(Some(self.bit_reader.read(self.width)?))
+    }
+}
+
+/// Decompresses a .Z file, in block mode or not
+pub fn decompress_unix(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    if !is_unix_compressed(compressed_data) {
+        bail!("not a .Z file");
+    }
+    let flags = compressed_data[2];
+    let max_width = (flags & UNIX_MAX_WIDTH_MASK) as u32;
+    if !(MIN_CODE_WIDTH..=MAX_CODE_WIDTH).contains(&max_width) {
+        bail!("unsupported .Z maximum code width {}", max_width);
+    }
+    let block_mode = flags & UNIX_BLOCK_MODE != 0;
+    let mut code_reader = UnixCodeReader::new(&compressed_data[UNIX_HEADER_SIZE..]);
+
+    // each string is stored as the code of its prefix and its last byte
+    let num_codes = 1usize << max_width;
+    let mut prefixes = vec![0u32; num_codes];
+    let mut suffixes = vec![0u8; num_codes];
+    let mut next_code = if block_mode { FIRST_CODE } else { CLEAR_CODE };
+    let mut previous: Option<u32> = None;
+    let mut first_byte = 0u8;
+    let mut string = Vec::new();
+    let mut original_data = Vec::new();
+    loop {
+        if code_reader.width < max_width && next_code >= 1 << code_reader.width {
+            if !code_reader.end_group()? {
+                break;
+            }
+            code_reader.width += 1;
+        }
+        let Some(mut code) = code_reader.read_code()? else {
+            break;
+        };
+        let Some(previous_code) = previous else {
+            if code >= CLEAR_CODE {
+                bail!("invalid first .Z code {}", code);
+            }
+            first_byte = code as u8;
+            original_data.push(first_byte);
+            previous = Some(code);
+            continue;
+        };
+        if code == CLEAR_CODE && block_mode {
+            if !code_reader.end_group()? {
+                break;
+            }
+            code_reader.width = MIN_CODE_WIDTH;
+            // like the original decoder, the previous string is kept
+            // and the unused code 256 is assigned next
+            next_code = CLEAR_CODE;
+            continue;
+        }
+        let current_code = code;
+        if code >= next_code {
+            if code > next_code {
+                bail!("invalid .Z code {}", code);
+            }
+            // the code was assigned by the encoder while writing it,
+            // the string is the previous string extended by its first byte
+            string.push(first_byte);
+            code = previous_code;
+        }
+        while code >= CLEAR_CODE {
+            string.push(suffixes[code as usize]);
+            code = prefixes[code as usize];
+        }
+        first_byte = code as u8;
+        string.push(first_byte);
+        original_data.extend(string.drain(..).rev());
+        if (next_code as usize) < num_codes {
+            prefixes[next_code as usize] = previous_code;
+            suffixes[next_code as usize] = first_byte;
+            next_code += 1;
+        }
+        previous = Some(current_code);
+    }
+
+    Ok(original_data)
+}
 
 pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(FILE_MAGIC)?;
+    compressed_file.write_all(&[FILE_VERSION])?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
     Ok(())
 }
 
 pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let Some(versioned_data) = compressed_data.strip_prefix(FILE_MAGIC) else {
+        bail!("not an LZW file");
+    };
+    let original_data = match versioned_data.split_first() {
+        Some((&FILE_VERSION, data)) => decompress(data)?,
+        Some((version, _)) => bail!("unsupported LZW file version {}", version),
+        None => bail!("truncated LZW file"),
+    };
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+/// Whether the file at `file_path` starts like a .Z file
+pub fn is_unix_compressed_file(file_path: &str) -> Result<bool> {
+    let mut header = Vec::new();
+    File::open(file_path)?
+        .take(UNIX_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok(is_unix_compressed(&header))
+}
+
+pub fn compress_unix_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress_unix(&data, MAX_CODE_WIDTH)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_unix_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress_unix(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::compress;
     use super::compress_file;
+    use super::compress_unix;
+    use super::decode;
+    use super::decompress;
     use super::decompress_file;
+    use super::decompress_unix;
+    use super::encode;
+    use super::BitOrder;
+    use super::LzwOptions;
+    use super::FILE_MAGIC;
+    use super::FILE_VERSION;
     use anyhow::Ok;
     use anyhow::Result;
+    use std::fs;
     use std::fs::remove_file;
     use std::fs::File;
     use std::io::Write;
@@ -34,21 +747,157 @@ mod tests {
         File::open(file_path).unwrap().metadata().unwrap().len()
     }
 
-    // #[test]
+    #[test]
     fn test_lzw() -> Result<()> {
-        let mut file = File::create_new("sample.txt").unwrap();
-        file.write(FILE_CONTENTS.as_bytes()).unwrap();
+        let mut file = File::create_new("lzw_sample.txt").unwrap();
+        file.write_all(FILE_CONTENTS.as_bytes()).unwrap();
+
+        compress_file("lzw_sample.txt", "lzw_compressed")?;
+        assert!(get_file_size_bytes("lzw_compressed") > 0);
+        assert!(get_file_size_bytes("lzw_sample.txt") > get_file_size_bytes("lzw_compressed"));
 
-        compress_file("sample.txt", "compressed")?;
-        assert!(get_file_size_bytes("compressed") > 0);
-        assert!(get_file_size_bytes("sample.txt") > get_file_size_bytes("compressed"));
+        decompress_file("lzw_compressed", "lzw_sample_restored.txt")?;
+        assert!(get_file_size_bytes("lzw_compressed") > 0);
+        assert_eq!(
+            fs::read("lzw_sample_restored.txt")?,
+            FILE_CONTENTS.as_bytes()
+        );
+        // files start with a magic and version, newer versions are rejected
+        let mut compressed_data = fs::read("lzw_compressed")?;
+        assert!(compressed_data.starts_with(FILE_MAGIC));
+        compressed_data[FILE_MAGIC.len()] = FILE_VERSION + 1;
+        fs::write("lzw_compressed", &compressed_data)?;
+        assert!(decompress_file("lzw_compressed", "lzw_sample_future.txt").is_err());
+        remove_file("lzw_sample.txt")?;
+        remove_file("lzw_compressed")?;
+        remove_file("lzw_sample_restored.txt")?;
 
-        decompress_file("compressed", "sample_restored.txt")?;
-        assert!(get_file_size_bytes("compressed") > 0);
-        remove_file("sample.txt")?;
-        remove_file("compressed")?;
-        remove_file("sample_restored.txt")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzw_round_trip() -> Result<()> {
+        // a long, repetitive input fills the dictionary and forces it to be reset
+        let repetitive: Vec<u8> = (0..400_000u64).map(|i| (i * i % 251) as u8).collect();
+        let inputs: [&[u8]; 5] = [
+            b"",
+            b"a",
+            b"abababababababab",
+            FILE_CONTENTS.as_bytes(),
+            &repetitive,
+        ];
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)?)?, input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unix_compress_files() -> Result<()> {
+        // written by libarchive (`bsdtar --format raw -cZf`), which emulates ncompress,
+        // with 16-bit codes: the codes widen from 9 to 12 bits
+        let sample = fs::read("testdata/sample.txt")?;
+        let compressed_data = fs::read("testdata/sample.txt.Z")?;
+        assert_eq!(decompress_unix(&compressed_data)?, sample);
+        assert_eq!(compress_unix(&sample, 16)?, compressed_data);
+
+        // without block mode, code 256 is the first string rather than a clear code:
+        // a, b, ab and aba (assigned while it is written)
+        let compressed_data = [0x1f, 0x9d, 0x10, 97, 196, 0, 20, 8];
+        assert_eq!(decompress_unix(&compressed_data)?, b"abababa");
+        let mut damaged = compressed_data;
+        damaged[6] = 84;
+        assert!(decompress_unix(&damaged).is_err());
+        assert_eq!(decompress_unix(&[0x1f, 0x9d, 0x89])?, b"");
+        assert!(decompress_unix(&[0x1f, 0x9d, 0x91]).is_err());
+        assert!(decompress_unix(&[0x1f, 0x8b, 0x90]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unix_compress_round_trip() -> Result<()> {
+        // with narrow codes the dictionary fills and gets cleared as the data changes
+        let mut data: Vec<u8> = (0..200_000u64).map(|i| (i * i % 251) as u8).collect();
+        data.extend(FILE_CONTENTS.as_bytes().repeat(20));
+        for max_width in 10..=16 {
+            let compressed_data = compress_unix(&data, max_width)?;
+            assert_eq!(compressed_data[2], 0x80 | max_width as u8);
+            assert_eq!(decompress_unix(&compressed_data)?, data);
+        }
+        assert_eq!(decompress_unix(&compress_unix(b"", 16)?)?, b"");
+        assert_eq!(decompress_unix(&compress_unix(b"a", 16)?)?, b"a");
+        assert!(compress_unix(b"a", 9).is_err());
+        Ok(())
+    }
+
+    /// Pixels of the fixture images, `mask` giving their depth
+    fn image_pixels(mask: usize) -> Vec<u8> {
+        (0..128 * 128)
+            .map(|i| {
+                let (x, y) = (i % 128, i / 128);
+                ((x * y + (x ^ y) * 7 + ((x * x) >> 3)) & mask) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lzw_image_fixtures() -> Result<()> {
+        // the image data of a 128x128 GIF with 16 colors written by giflib
+        let pixels = image_pixels(0x0f);
+        let codes = fs::read("testdata/image.gif.lzw")?;
+        assert_eq!(decode(&codes, &LzwOptions::gif(4))?, pixels);
+        assert_eq!(encode(&pixels, &LzwOptions::gif(4))?, codes);
+
+        // the strip of a 128x128 grayscale TIFF written by libtiff, whose dictionary
+        // gets full and is cleared
+        let pixels = image_pixels(0xff);
+        let codes = fs::read("testdata/image.tif.lzw")?;
+        assert_eq!(decode(&codes, &LzwOptions::tiff())?, pixels);
+        assert_eq!(
+            decode(&encode(&pixels, &LzwOptions::tiff())?, &LzwOptions::tiff())?,
+            pixels
+        );
+
+        assert!(encode(&[0, 1, 16], &LzwOptions::gif(4)).is_err());
+        let options = LzwOptions {
+            bit_order: BitOrder::LsbFirst,
+            ..LzwOptions::tiff()
+        };
+        assert!(decode(&codes, &options).is_err());
+        Ok(())
+    }
 
+    #[test]
+    fn test_lzw_options() -> Result<()> {
+        let data: Vec<u8> = (0..50_000u64).map(|i| (i * i % 7 + i % 3) as u8).collect();
+        for bit_order in [BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            for (clear_code, initial_clear, end_code) in [
+                (false, false, false),
+                (true, false, false),
+                (true, true, false),
+                (false, false, true),
+                (true, true, true),
+            ] {
+                for early_change in [false, true] {
+                    let options = LzwOptions {
+                        bit_order,
+                        literal_width: 3,
+                        max_code_width: 10,
+                        clear_code,
+                        initial_clear,
+                        end_code,
+                        early_change,
+                    };
+                    assert_eq!(decode(&encode(&data, &options)?, &options)?, data);
+                }
+            }
+        }
+        let options = LzwOptions {
+            initial_clear: true,
+            clear_code: false,
+            ..LzwOptions::tiff()
+        };
+        assert!(encode(b"", &options).is_err());
         Ok(())
     }
 }
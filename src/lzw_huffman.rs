@@ -0,0 +1,242 @@
+use crate::huffmann;
+use crate::huffmann::canonical_codes;
+use crate::huffmann::symbol_base;
+use crate::huffmann::value_symbol;
+use crate::huffmann::HuffmanDecoder;
+use crate::lzw;
+use crate::lzw::BitOrder;
+use crate::lzw::LzwOptions;
+use anyhow::bail;
+use anyhow::Ok;
+use anyhow::Result;
+use bitstream_io::BigEndian;
+use bitstream_io::BitRead;
+use bitstream_io::BitReader;
+use bitstream_io::BitWrite;
+use bitstream_io::BitWriter;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+
+// The data is encoded as LZW codes of up to 16 bits, ended by an end code, and the codes
+// are written with a canonical Huffman code instead of at the width of the dictionary.
+// Literals, the clear code and the end code are their own symbols. Strings of the
+// dictionary, which are mostly used soon after they are added, are coded by how far
+// back from the decoder's next code they are, as a symbol and extra bits given by
+// `huffmann::value_symbol`. The code lengths of the symbols come first, as runs of equal
+// lengths: each a 4-bit length and the number of symbols in the run, in Elias gamma code.
+const LZW_OPTIONS: LzwOptions = LzwOptions {
+    bit_order: BitOrder::MsbFirst,
+    literal_width: 8,
+    max_code_width: 16,
+    clear_code: true,
+    initial_clear: false,
+    end_code: true,
+    early_change: false,
+};
+const CLEAR_CODE: u32 = 256;
+const END_CODE: u32 = 257;
+const FIRST_CODE: u32 = 258;
+/// symbols of the distances back from the next code, up to 2^16
+const NUM_DISTANCE_SYMBOLS: usize = 32;
+const NUM_SYMBOLS: usize = FIRST_CODE as usize + NUM_DISTANCE_SYMBOLS;
+
+const MAX_CODE_LENGTH: u8 = 15;
+const CODE_LENGTH_BITS: u32 = 4;
+
+/// Follows the decoder's next code, the code of the next string added to the dictionary
+struct NextCode {
+    next_code: u32,
+    is_first_code: bool,
+}
+
+impl NextCode {
+    fn new() -> Self {
+        NextCode {
+            next_code: FIRST_CODE,
+            is_first_code: true,
+        }
+    }
+
+    fn update(&mut self, code: u32) {
+        if code == CLEAR_CODE {
+            self.next_code = FIRST_CODE;
+            self.is_first_code = true;
+        } else {
+            if !self.is_first_code && self.next_code < 1 << LZW_OPTIONS.max_code_width {
+                self.next_code += 1;
+            }
+            self.is_first_code = false;
+        }
+    }
+}
+
+/// Writes `value`, at least 1, as its width minus one in zero bits followed by its bits
+fn write_elias_gamma<W: BitWrite>(bit_writer: &mut W, value: u32) -> Result<()> {
+    let width = u32::BITS - value.leading_zeros();
+    bit_writer.write(width - 1, 0u32)?;
+    bit_writer.write(width, value)?;
+    Ok(())
+}
+
+fn read_elias_gamma<R: BitRead>(bit_reader: &mut R) -> Result<u32> {
+    let mut width = 1;
+    while !bit_reader.read_bit()? {
+        width += 1;
+        if width > u32::BITS {
+            bail!("invalid Elias gamma code");
+        }
+    }
+    // the leading one bit has been read
+    let low_bits: u32 = bit_reader.read(width - 1)?;
+    Ok((1 << (width - 1)) | low_bits)
+}
+
+fn write_code_lengths<W: BitWrite>(bit_writer: &mut W, lengths: &[u8]) -> Result<()> {
+    let mut start = 0;
+    while start < lengths.len() {
+        let run_len = lengths[start..]
+            .iter()
+            .take_while(|length| **length == lengths[start])
+            .count();
+        bit_writer.write(CODE_LENGTH_BITS, lengths[start])?;
+        write_elias_gamma(bit_writer, run_len as u32)?;
+        start += run_len;
+    }
+    Ok(())
+}
+
+fn read_code_lengths<R: BitRead>(bit_reader: &mut R) -> Result<Vec<u8>> {
+    let mut lengths = Vec::with_capacity(NUM_SYMBOLS);
+    while lengths.len() < NUM_SYMBOLS {
+        let length: u8 = bit_reader.read(CODE_LENGTH_BITS)?;
+        let run_len = read_elias_gamma(bit_reader)? as usize;
+        if lengths.len() + run_len > NUM_SYMBOLS {
+            bail!("too many code lengths");
+        }
+        lengths.resize(lengths.len() + run_len, length);
+    }
+    Ok(lengths)
+}
+
+/// Returns the symbol of `code`, the number of its extra bits and their value
+fn code_symbol(code: u32, next_code: u32) -> (u16, u32, u32) {
+    if code < FIRST_CODE {
+        return (code as u16, 0, 0);
+    }
+    // a code may be the next code itself, for a string repeated right after it
+    let (symbol, extra_bits, extra) = value_symbol(next_code - code);
+    (FIRST_CODE as u16 + symbol, extra_bits, extra)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let codes = lzw::encode_values(data, &LZW_OPTIONS)?;
+    let mut symbols = Vec::with_capacity(codes.len());
+    let mut frequencies = vec![0u32; NUM_SYMBOLS];
+    let mut next_code = NextCode::new();
+    for code in codes.iter() {
+        let symbol = code_symbol(*code, next_code.next_code);
+        frequencies[symbol.0 as usize] += 1;
+        symbols.push(symbol);
+        next_code.update(*code);
+    }
+    let lengths = huffmann::code_lengths(&frequencies, MAX_CODE_LENGTH);
+    let huffman_codes = canonical_codes(&lengths);
+
+    let mut compressed_data = Vec::new();
+    let mut bit_writer = BitWriter::endian(&mut compressed_data, BigEndian);
+    write_code_lengths(&mut bit_writer, &lengths)?;
+    for (symbol, extra_bits, extra) in symbols {
+        let symbol = symbol as usize;
+        bit_writer.write(lengths[symbol] as u32, huffman_codes[symbol])?;
+        bit_writer.write(extra_bits, extra)?;
+    }
+    bit_writer.byte_align()?;
+    Ok(compressed_data)
+}
+
+pub fn decompress(compressed_data: &[u8]) -> Result<Vec<u8>> {
+    let mut bit_reader = BitReader::endian(Cursor::new(compressed_data), BigEndian);
+    let decoder = HuffmanDecoder::new(&read_code_lengths(&mut bit_reader)?)?;
+    let mut codes = Vec::new();
+    let mut next_code = NextCode::new();
+    loop {
+        let symbol = decoder.decode(&mut bit_reader)? as u32;
+        let code = if symbol < FIRST_CODE {
+            symbol
+        } else {
+            let (base, extra_bits) = symbol_base((symbol - FIRST_CODE) as u16);
+            let distance = base + bit_reader.read::<u32>(extra_bits)?;
+            if distance > next_code.next_code - FIRST_CODE {
+                bail!("LZW code before the first code of the dictionary");
+            }
+            next_code.next_code - distance
+        };
+        codes.push(code);
+        if code == END_CODE {
+            break;
+        }
+        next_code.update(code);
+    }
+    lzw::decode_values(&codes, &LZW_OPTIONS)
+}
+
+pub fn compress_file(file_path: &str, compressed_file_path: &str) -> Result<()> {
+    let mut file = File::open(file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let compressed_data = compress(&data)?;
+    let mut compressed_file = File::create_new(compressed_file_path)?;
+    compressed_file.write_all(compressed_data.as_slice())?;
+
+    Ok(())
+}
+
+pub fn decompress_file(compressed_file_path: &str, restored_file_path: &str) -> Result<()> {
+    let mut compressed_file = File::open(compressed_file_path)?;
+    let mut compressed_data: Vec<u8> = Vec::new();
+    compressed_file.read_to_end(&mut compressed_data)?;
+
+    let original_data = decompress(&compressed_data)?;
+    let mut original_file = File::create_new(restored_file_path)?;
+    original_file.write_all(original_data.as_slice())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::decompress;
+    use crate::lzw;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_lzw_huffman_corpus() -> Result<()> {
+        let sample = fs::read("testdata/sample.txt")?;
+        // compressed data, whose bytes are nearly random
+        let gzipped = fs::read("testdata/sample.txt.gz")?;
+        let image = fs::read("testdata/image.tif.lzw")?;
+        let words: Vec<u8> = (0..100_000u64)
+            .flat_map(|i| {
+                ["the ", "of ", "lzw ", "code ", "and "][(i * i % 7 % 5) as usize].bytes()
+            })
+            .collect();
+        let corpus: [&[u8]; 4] = [&sample, &gzipped, &image, &words];
+        for data in corpus {
+            let compressed_data = compress(data)?;
+            assert_eq!(decompress(&compressed_data)?, data);
+            // Huffman coding saves about 4% on text and up to half on repetitive data,
+            // and codes the literals of incompressible data with fewer bits
+            assert!(compressed_data.len() < lzw::compress(data)?.len() * 97 / 100);
+        }
+        assert_eq!(decompress(&compress(b"")?)?, b"");
+        assert!(decompress(&compress(&sample)?[..100]).is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,21 @@
-mod archiver;
-mod huffmann;
-mod lzw;
 use anyhow::{Ok, Result};
 use clap::arg;
+use clap::parser::ValueSource;
+use clap::value_parser;
+use clap::ArgAction;
 use clap::Command;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use file_compressor::archiver;
+use file_compressor::codec;
+use file_compressor::deflate;
+use file_compressor::gzip;
+use file_compressor::huffmann;
+use file_compressor::lzss;
+use file_compressor::lzss_huffman;
+use file_compressor::lzw;
+use file_compressor::lzw_huffman;
 
 fn cli() -> Command {
     Command::new("file-compressor")
@@ -16,9 +28,13 @@ fn cli() -> Command {
                 arg!([COMPRESSED_FILEPATH] "path to write the compressed file").required(true),
                 arg!([METHOD] "Compression method")
                     .required(false)
-                    .value_parser(["lzw", "huffmann"])
+                    .value_parser(["lzw", "huffmann", "deflate", "lzss", "lzss+huffman", "lzw+huffman"])
                     .default_value("huffmann")
                     .default_missing_value("huffmann"),
+                arg!(--format <FORMAT> "file format to write (gzip files are always deflated, compress files are the .Z files of Unix compress)")
+                    .required(false)
+                    .value_parser(["native", "gzip", "compress"])
+                    .default_value("native"),
             ]),
         )
         .subcommand(
@@ -26,8 +42,13 @@ fn cli() -> Command {
                 .about("de-compress a file")
                 .args(&[
                     arg!([COMPRESSED_FILEPATH] "path to the compressed file").required(true),
-                    arg!([DECOMPRESSED_FILEPATH] "path to write the decompressed file")
+                        arg!([DECOMPRESSED_FILEPATH] "path to write the decompressed file")
                         .required(true),
+                    arg!([METHOD] "Compression method used for the compressed file (gzip and .Z files are detected)")
+                        .required(false)
+                        .value_parser(["lzw", "huffmann", "deflate", "lzss", "lzss+huffman", "lzw+huffman"])
+                        .default_value("huffmann")
+                        .default_missing_value("huffmann"),
                 ]),
         )
         .subcommand(
@@ -35,7 +56,41 @@ fn cli() -> Command {
                 .about("combine multiple files into a single file")
                 .args(&[
                     arg!([ARCHIVE_FILEPATH] "path to write the archive file").required(true),
-                    arg!([FILEPATHS] "paths to the files to archive")
+                    arg!(-m --method <METHOD> "compression method for the archived files (ZIP files are deflated unless it is store)")
+                        .value_parser(codec::Method::NAMES)
+                        .default_value("huffmann"),
+                    arg!(--format <FORMAT> "format of the archive (default zip for .zip files, native otherwise)")
+                        .value_parser(archiver::ArchiveFormat::NAMES)
+                        .conflicts_with_all(["append", "update"]),
+                    arg!(--solid "compress the archived files together as a single stream"),
+                    arg!(--"solid-block-size" <SIZE> "start a new solid block after SIZE bytes (e.g. 64M)")
+                        .value_parser(parse_size)
+                        .requires("solid"),
+                    arg!(--chunked "split the archived files into content-defined chunks, storing each unique chunk once")
+                        .conflicts_with("solid"),
+                    arg!(--"chunk-size" <SIZE> "average size of the chunks (default 64K)")
+                        .value_parser(parse_size)
+                        .requires("chunked"),
+                    arg!(-L --dereference "archive the files symbolic links point to instead of the links"),
+                    arg!(--append "add the files to an existing archive"),
+                    arg!(--update "add the files to an existing archive, replacing older entries")
+                        .conflicts_with("append"),
+                    arg!(--"incremental-from" <ARCHIVE> "store only the files changed since ARCHIVE was written")
+                        .value_parser(value_parser!(PathBuf))
+                        .conflicts_with_all(["append", "update"]),
+                    arg!(--"volume-size" <SIZE> "split the archive into volumes of at most SIZE bytes (e.g. 2G)")
+                        .value_parser(parse_size)
+                        .conflicts_with_all(["append", "update"]),
+                    arg!(--include <PATTERN> "archive only the files matching PATTERN (may be repeated)")
+                        .action(ArgAction::Append),
+                    arg!(--exclude <PATTERN> "skip the files and directories matching PATTERN (may be repeated)")
+                        .action(ArgAction::Append),
+                    arg!(-X --"exclude-from" <FILE> "skip the files and directories matching the patterns listed in FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Append),
+                    arg!(--"respect-gitignore" "skip the files ignored by .gitignore files in the archived directories"),
+                    arg!(--xattrs "record the extended attributes and ACLs of the archived files"),
+                    arg!([FILEPATHS] "paths to the files and directories to archive")
                         .required(true)
                         .num_args(1..)
                         .value_delimiter(' '),
@@ -47,10 +102,75 @@ fn cli() -> Command {
                 .args(&[
                     arg!([ARCHIVE_FILEPATH] "path to the archive file").required(true),
                     arg!([TARGET_DIR] "path to write the extracted files").required(true),
+                    arg!([ENTRIES] "names of the entries to extract (all entries if omitted)")
+                        .value_parser(value_parser!(OsString))
+                        .required(false)
+                        .num_args(0..),
+                    arg!(-i --incremental <ARCHIVE> "archive of the chain ARCHIVE_FILEPATH was made from, oldest first (may be repeated)")
+                        .action(ArgAction::Append),
+                    arg!(--"same-owner" "restore the owner of extracted files (default when run as root)"),
+                    arg!(--xattrs "restore the extended attributes and ACLs recorded in the archive"),
+                ]),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("list the entries of an archive")
+                .args(&[arg!([ARCHIVE_FILEPATH] "path to the archive file").required(true)]),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("summarize the contents of an archive")
+                .args(&[arg!([ARCHIVE_FILEPATH] "path to the archive file").required(true)]),
+        )
+        .subcommand(
+            Command::new("import-tar")
+                .about("convert a tar file into an archive")
+                .args(&[
+                    arg!([TAR_FILEPATH] "path to the tar file, or - to read standard input")
+                        .required(true),
+                    arg!([ARCHIVE_FILEPATH] "path to write the archive file").required(true),
+                    arg!(-m --method <METHOD> "compression method for the archived files")
+                        .value_parser(codec::Method::NAMES)
+                        .default_value("huffmann"),
+                    arg!(--solid "compress the archived files together as a single stream"),
+                ]),
+        )
+        .subcommand(
+            Command::new("export-tar")
+                .about("convert an archive into a tar file")
+                .args(&[
+                    arg!([ARCHIVE_FILEPATH] "path to the archive file").required(true),
+                    arg!([TAR_FILEPATH] "path to write the tar file, or - to write standard output")
+                        .required(true),
+                ]),
+        )
+        .subcommand(
+            Command::new("delete")
+                .about("remove entries from an archive")
+                .args(&[
+                    arg!([ARCHIVE_FILEPATH] "path to the archive file").required(true),
+                    arg!([ENTRIES] "names of the entries to remove")
+                        .value_parser(value_parser!(OsString))
+                        .required(true)
+                        .num_args(1..),
                 ]),
         )
 }
 
+/// Parses a size in bytes with an optional K, M or G (binary) suffix
+fn parse_size(size: &str) -> Result<u64> {
+    let (digits, multiplier) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 1u64 << 10),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 1u64 << 20),
+        Some('G') | Some('g') => (&size[..size.len() - 1], 1u64 << 30),
+        _ => (size, 1u64),
+    };
+    let value: u64 = digits.parse()?;
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("size {} is too large", size))
+}
+
 fn main() -> Result<()> {
     let matches = cli().get_matches();
     match matches.subcommand() {
@@ -67,9 +187,36 @@ fn main() -> Result<()> {
                 .get_one::<String>("METHOD")
                 .map(|s| s.to_string())
                 .unwrap();
-            match method.as_str() {
-                "lzw" => lzw::compress_file(&input_filepath, &output_filepath)?,
-                "huffmann" => huffmann::compress_file(&input_filepath, &output_filepath)?,
+            let format = sub_matches.get_one::<String>("format").unwrap();
+            let format_method = match format.as_str() {
+                "gzip" => Some("deflate"),
+                "compress" => Some("lzw"),
+                _ => None,
+            };
+            if let Some(format_method) = format_method {
+                if sub_matches.value_source("METHOD") == Some(ValueSource::CommandLine)
+                    && method != format_method
+                {
+                    anyhow::bail!(
+                        "{} files can only be compressed with {}",
+                        format,
+                        format_method
+                    );
+                }
+            }
+            match (format.as_str(), method.as_str()) {
+                ("gzip", _) => gzip::compress_file(&input_filepath, &output_filepath)?,
+                ("compress", _) => lzw::compress_unix_file(&input_filepath, &output_filepath)?,
+                (_, "lzw") => lzw::compress_file(&input_filepath, &output_filepath)?,
+                (_, "huffmann") => huffmann::compress_file(&input_filepath, &output_filepath)?,
+                (_, "deflate") => deflate::compress_file(&input_filepath, &output_filepath)?,
+                (_, "lzss") => lzss::compress_file(&input_filepath, &output_filepath)?,
+                (_, "lzss+huffman") => {
+                    lzss_huffman::compress_file(&input_filepath, &output_filepath)?
+                }
+                (_, "lzw+huffman") => {
+                    lzw_huffman::compress_file(&input_filepath, &output_filepath)?
+                }
                 _ => unreachable!(),
             }
         }
@@ -82,7 +229,29 @@ fn main() -> Result<()> {
                 .get_one::<String>("DECOMPRESSED_FILEPATH")
                 .map(|s| s.to_string())
                 .unwrap();
-            huffmann::decompress_file(&input_filepath, &output_filepath)?;
+            let method = sub_matches
+                .get_one::<String>("METHOD")
+                .map(|s| s.to_string())
+                .unwrap();
+            if gzip::is_gzip_file(&input_filepath)? {
+                gzip::decompress_file(&input_filepath, &output_filepath)?;
+            } else if lzw::is_unix_compressed_file(&input_filepath)? {
+                lzw::decompress_unix_file(&input_filepath, &output_filepath)?;
+            } else {
+                match method.as_str() {
+                    "lzw" => lzw::decompress_file(&input_filepath, &output_filepath)?,
+                    "huffmann" => huffmann::decompress_file(&input_filepath, &output_filepath)?,
+                    "deflate" => deflate::decompress_file(&input_filepath, &output_filepath)?,
+                    "lzss" => lzss::decompress_file(&input_filepath, &output_filepath)?,
+                    "lzss+huffman" => {
+                        lzss_huffman::decompress_file(&input_filepath, &output_filepath)?
+                    }
+                    "lzw+huffman" => {
+                        lzw_huffman::decompress_file(&input_filepath, &output_filepath)?
+                    }
+                    _ => unreachable!(),
+                }
+            }
         }
         Some(("archive", sub_matches)) => {
             let archive_filepath = sub_matches
@@ -91,9 +260,48 @@ fn main() -> Result<()> {
                 .unwrap();
             let filepaths: Vec<&String> = sub_matches.get_many("FILEPATHS").unwrap().collect();
             let filepaths_str: Vec<&str> = filepaths.into_iter().map(|s| s.as_str()).collect();
-            archiver::archive_files(filepaths_str.as_slice(), &archive_filepath)?;
+            let method = sub_matches
+                .get_one::<String>("method")
+                .map(|s| s.to_string())
+                .unwrap();
+            let format = match sub_matches.get_one::<String>("format") {
+                Some(format) => archiver::ArchiveFormat::from_name(format)?,
+                None => archiver::ArchiveFormat::from_path(&archive_filepath),
+            };
+            let options = archiver::ArchiveOptions {
+                format,
+                method: codec::Method::from_name(&method)?,
+                solid: sub_matches.get_flag("solid"),
+                solid_block_size: sub_matches.get_one::<u64>("solid-block-size").copied(),
+                dereference: sub_matches.get_flag("dereference"),
+                include: sub_matches
+                    .get_many::<String>("include")
+                    .map(|patterns| patterns.cloned().collect())
+                    .unwrap_or_default(),
+                exclude: sub_matches
+                    .get_many::<String>("exclude")
+                    .map(|patterns| patterns.cloned().collect())
+                    .unwrap_or_default(),
+                exclude_from: sub_matches
+                    .get_many::<PathBuf>("exclude-from")
+                    .map(|paths| paths.cloned().collect())
+                    .unwrap_or_default(),
+                respect_gitignore: sub_matches.get_flag("respect-gitignore"),
+                xattrs: sub_matches.get_flag("xattrs"),
+                chunked: sub_matches.get_flag("chunked"),
+                chunk_size: sub_matches.get_one::<u64>("chunk-size").copied(),
+                incremental_from: sub_matches.get_one::<PathBuf>("incremental-from").cloned(),
+                volume_size: sub_matches.get_one::<u64>("volume-size").copied(),
+            };
+            if sub_matches.get_flag("append") {
+                archiver::append_files(filepaths_str.as_slice(), &archive_filepath, &options)?;
+            } else if sub_matches.get_flag("update") {
+                archiver::update_files(filepaths_str.as_slice(), &archive_filepath, &options)?;
+            } else {
+                archiver::archive_files(filepaths_str.as_slice(), &archive_filepath, &options)?;
+            }
         }
-        Some(("unarhive", sub_matches)) => {
+        Some(("unarchive", sub_matches)) => {
             let archive_filepath = sub_matches
                 .get_one::<String>("ARCHIVE_FILEPATH")
                 .map(|s| s.to_string())
@@ -102,7 +310,141 @@ fn main() -> Result<()> {
                 .get_one::<String>("TARGET_DIR")
                 .map(|s| s.to_string())
                 .unwrap();
-            archiver::unarchive_files(&archive_filepath, &target_dir)?;
+            let entry_names: Vec<&OsString> = sub_matches
+                .get_many::<OsString>("ENTRIES")
+                .map(|names| names.collect())
+                .unwrap_or_default();
+            let options = archiver::UnarchiveOptions {
+                same_owner: sub_matches.get_flag("same-owner"),
+                xattrs: sub_matches.get_flag("xattrs"),
+            };
+            let incremental: Vec<&String> = sub_matches
+                .get_many::<String>("incremental")
+                .map(|paths| paths.collect())
+                .unwrap_or_default();
+            if !incremental.is_empty() {
+                let mut chain: Vec<&str> = incremental.iter().map(|s| s.as_str()).collect();
+                chain.push(&archive_filepath);
+                archiver::unarchive_chain(&chain, &target_dir, &entry_names, &options)?;
+            } else if entry_names.is_empty() {
+                archiver::unarchive_files(&archive_filepath, &target_dir, &options)?;
+            } else {
+                archiver::unarchive_entries(
+                    &archive_filepath,
+                    &target_dir,
+                    &entry_names,
+                    &options,
+                )?;
+            }
+        }
+        Some(("list", sub_matches)) => {
+            let archive_filepath = sub_matches
+                .get_one::<String>("ARCHIVE_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            for entry in archiver::list_entries(&archive_filepath)? {
+                let metadata = entry.metadata.clone().unwrap_or_default();
+                let (kind, link) = match entry.kind {
+                    archiver::EntryKind::File => ('-', String::new()),
+                    archiver::EntryKind::Directory => ('d', String::new()),
+                    archiver::EntryKind::Symlink => (
+                        'l',
+                        format!(" -> {}", archiver::escape_name(&entry.link_target)),
+                    ),
+                    archiver::EntryKind::HardLink => (
+                        'h',
+                        format!(" link to {}", archiver::escape_name(&entry.link_target)),
+                    ),
+                };
+                println!(
+                    "{}{}  {:<16}  {:>12}  {:>12}  {:<8}  {}{}",
+                    kind,
+                    metadata.permissions_string(),
+                    format!("{}/{}", metadata.user_name, metadata.group_name),
+                    entry.size,
+                    entry.stored_size,
+                    entry.method.name(),
+                    entry.display_name(),
+                    link
+                );
+            }
+        }
+        Some(("info", sub_matches)) => {
+            let archive_filepath = sub_matches
+                .get_one::<String>("ARCHIVE_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            let info = archiver::archive_info(&archive_filepath)?;
+            println!("entries:       {}", info.num_entries);
+            println!("solid blocks:  {}", info.num_solid_blocks);
+            println!("chunks:        {}", info.num_chunks);
+            println!("size:          {} bytes", info.size);
+            println!("archive size:  {} bytes", info.archive_size);
+            if info.num_volumes > 1 {
+                println!("volumes:       {}", info.num_volumes);
+            }
+            println!(
+                "deduplicated:  {} entries, {} bytes saved",
+                info.num_duplicates, info.deduplicated_size
+            );
+            if info.incremental {
+                println!(
+                    "incremental:   {} unchanged entries, {} deleted entries",
+                    info.num_unchanged, info.num_deleted
+                );
+            }
+        }
+        Some(("import-tar", sub_matches)) => {
+            let tar_filepath = sub_matches
+                .get_one::<String>("TAR_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            let archive_filepath = sub_matches
+                .get_one::<String>("ARCHIVE_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            let method = sub_matches
+                .get_one::<String>("method")
+                .map(|s| s.to_string())
+                .unwrap();
+            let options = archiver::ArchiveOptions {
+                method: codec::Method::from_name(&method)?,
+                solid: sub_matches.get_flag("solid"),
+                ..Default::default()
+            };
+            if tar_filepath == "-" {
+                archiver::import_tar(std::io::stdin().lock(), &archive_filepath, &options)?;
+            } else {
+                let tar_file = std::io::BufReader::new(std::fs::File::open(&tar_filepath)?);
+                archiver::import_tar(tar_file, &archive_filepath, &options)?;
+            }
+        }
+        Some(("export-tar", sub_matches)) => {
+            let archive_filepath = sub_matches
+                .get_one::<String>("ARCHIVE_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            let tar_filepath = sub_matches
+                .get_one::<String>("TAR_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            if tar_filepath == "-" {
+                archiver::export_tar(&archive_filepath, std::io::stdout().lock())?;
+            } else {
+                let tar_file = std::io::BufWriter::new(std::fs::File::create_new(&tar_filepath)?);
+                archiver::export_tar(&archive_filepath, tar_file)?;
+            }
+        }
+        Some(("delete", sub_matches)) => {
+            let archive_filepath = sub_matches
+                .get_one::<String>("ARCHIVE_FILEPATH")
+                .map(|s| s.to_string())
+                .unwrap();
+            let entry_names: Vec<&OsString> = sub_matches
+                .get_many::<OsString>("ENTRIES")
+                .unwrap()
+                .collect();
+            archiver::delete_entries(&archive_filepath, &entry_names)?;
         }
         _ => unreachable!(),
     }
@@ -0,0 +1,13 @@
+/// `len` bytes of pseudo-random data from a xorshift generator started at `seed`,
+/// the same on every run
+pub fn pseudo_random_bytes(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        })
+        .collect()
+}
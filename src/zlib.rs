@@ -0,0 +1,337 @@
+use crate::checksum::adler32;
+use crate::checksum::update_adler32;
+use crate::deflate;
+use anyhow::bail;
+use anyhow::Result;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// A zlib stream (RFC 1950) is a 2-byte header, a DEFLATE stream and the Adler-32 of the
+// data, big-endian. The first header byte gives the method and the window size, the
+// second the compression level, whether a preset dictionary is used and check bits
+// making the header a multiple of 31. With a preset dictionary, the header is followed
+// by the Adler-32 of the dictionary, which the stream may refer back into.
+const DEFLATE_METHOD: u8 = 8;
+const MAX_WINDOW_BITS: u8 = 15;
+const HEADER_SIZE: usize = 2;
+const DICTIONARY_ID_SIZE: usize = 4;
+const TRAILER_SIZE: usize = 4;
+
+const FLAG_DICTIONARY: u8 = 0x20;
+/// compression level hint of zlib's default level
+const DEFAULT_LEVEL: u8 = 2;
+/// size of the pieces of data compressed at a time by `ZlibEncoder`
+const PIECE_SIZE: usize = 1 << 20;
+
+/// Header of a zlib stream, followed by the Adler-32 of the preset `dictionary` if given
+fn header(dictionary: Option<&[u8]>) -> Vec<u8> {
+    let method = DEFLATE_METHOD | (MAX_WINDOW_BITS - 8) << 4;
+    let mut flags = DEFAULT_LEVEL << 6;
+    if dictionary.is_some() {
+        flags |= FLAG_DICTIONARY;
+    }
+    flags += 31 - (u16::from_be_bytes([method, flags]) % 31) as u8;
+    let mut header = vec![method, flags];
+    if let Some(dictionary) = dictionary {
+        header.extend_from_slice(&adler32(dictionary).to_be_bytes());
+    }
+    header
+}
+
+/// Compresses `data` as a zlib stream, which may refer back into the preset `dictionary`
+pub fn compress(data: &[u8], dictionary: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut compressed_data = header(dictionary);
+    compressed_data.extend_from_slice(&deflate::compress_with_dictionary(
+        data,
+        dictionary.unwrap_or_default(),
+    )?);
+    compressed_data.extend_from_slice(&adler32(data).to_be_bytes());
+    Ok(compressed_data)
+}
+
+/// Decompresses a zlib stream, given the preset `dictionary` if it was compressed with one
+pub fn decompress(compressed_data: &[u8], dictionary: Option<&[u8]>) -> Result<Vec<u8>> {
+    let (data, stream_len) = inflate(compressed_data, dictionary)?;
+    if compressed_data.len() > stream_len {
+        bail!("trailing data after zlib stream");
+    }
+    Ok(data)
+}
+
+/// Decompresses the zlib stream at the start of `compressed_data`, returning the data
+/// and the length of the stream up to the end of its trailer
+pub fn inflate(compressed_data: &[u8], dictionary: Option<&[u8]>) -> Result<(Vec<u8>, usize)> {
+    let mut reader = compressed_data;
+    let data = read_stream(&mut reader, dictionary)?;
+    Ok((data, compressed_data.len() - reader.len()))
+}
+
+/// Fills `buf` from `reader`, naming the `part` of the stream that is truncated if it ends
+fn read_part<R: Read>(reader: &mut R, buf: &mut [u8], part: &str) -> Result<()> {
+    match reader.read_exact(buf) {
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => {
+            bail!("truncated zlib {}", part)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Decompresses the zlib stream read from `reader`, reading no further than its trailer
+fn read_stream<R: Read>(reader: &mut R, dictionary: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut header = [0u8; HEADER_SIZE];
+    read_part(reader, &mut header, "header")?;
+    let [method, flags] = header;
+    if !u16::from_be_bytes([method, flags]).is_multiple_of(31) {
+        bail!("invalid zlib header check bits");
+    }
+    if method & 0x0f != DEFLATE_METHOD {
+        bail!("unsupported zlib compression method {}", method & 0x0f);
+    }
+    if (method >> 4) + 8 > MAX_WINDOW_BITS {
+        bail!("invalid zlib window size");
+    }
+    let dictionary = if flags & FLAG_DICTIONARY != 0 {
+        let mut dictionary_id = [0u8; DICTIONARY_ID_SIZE];
+        read_part(reader, &mut dictionary_id, "header")?;
+        let dictionary_id = u32::from_be_bytes(dictionary_id);
+        let Some(dictionary) = dictionary else {
+            bail!(
+                "zlib stream needs the preset dictionary {:08x}",
+                dictionary_id
+            );
+        };
+        if adler32(dictionary) != dictionary_id {
+            bail!(
+                "zlib stream needs the preset dictionary {:08x}, not {:08x}",
+                dictionary_id,
+                adler32(dictionary)
+            );
+        }
+        dictionary
+    } else {
+        &[]
+    };
+    let mut data = Vec::new();
+    deflate::inflate_stream(&mut *reader, dictionary, &mut data)?;
+    let mut trailer = [0u8; TRAILER_SIZE];
+    read_part(reader, &mut trailer, "trailer")?;
+    if adler32(&data) != u32::from_be_bytes(trailer) {
+        bail!("zlib data checksum mismatch");
+    }
+    Ok(data)
+}
+
+/// Compresses the data written to it as a zlib stream written to `writer`, a piece of
+/// `PIECE_SIZE` bytes at a time (each referring back into the previous one), the end
+/// of the stream being written by `finish`. Unlike zlib's deflate, `flush` does not
+/// write the data buffered so far, which is only written once a piece is full.
+pub struct ZlibEncoder<W: Write> {
+    writer: W,
+    /// header of the stream, until it is written with the first piece
+    header: Option<Vec<u8>>,
+    /// the piece written before the current one, or the preset dictionary
+    previous_piece: Vec<u8>,
+    piece: Vec<u8>,
+    adler32: u32,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        ZlibEncoder {
+            writer,
+            header: Some(header(None)),
+            previous_piece: Vec::new(),
+            piece: Vec::new(),
+            adler32: 1,
+        }
+    }
+
+    pub fn with_dictionary(writer: W, dictionary: &[u8]) -> Self {
+        ZlibEncoder {
+            writer,
+            header: Some(header(Some(dictionary))),
+            previous_piece: dictionary.to_vec(),
+            piece: Vec::new(),
+            adler32: 1,
+        }
+    }
+
+    /// Compresses the current piece as a part of the stream
+    fn write_piece(&mut self, is_last: bool) -> Result<()> {
+        if let Some(header) = self.header.take() {
+            self.writer.write_all(&header)?;
+        }
+        let compressed_piece = deflate::compress_part(&self.piece, &self.previous_piece, is_last)?;
+        self.writer.write_all(&compressed_piece)?;
+        self.adler32 = update_adler32(self.adler32, &self.piece);
+        self.previous_piece = std::mem::take(&mut self.piece);
+        Ok(())
+    }
+
+    /// Writes the data written so far and the end of the zlib stream
+    pub fn finish(mut self) -> Result<W> {
+        self.write_piece(true)?;
+        self.writer.write_all(&self.adler32.to_be_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(PIECE_SIZE - self.piece.len());
+        self.piece.extend_from_slice(&buf[..len]);
+        if self.piece.len() == PIECE_SIZE {
+            self.write_piece(false).map_err(std::io::Error::other)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the data of the zlib stream at the start of `reader`. The first call to
+/// `read` reads the stream up to the end of its trailer and decompresses it at once,
+/// holding its data until it is read, so it waits for the whole stream (there is no
+/// equivalent of zlib's partial inflate). The bytes following the stream are left
+/// unread in `reader` (see `into_inner`), which is read one byte at a time and so
+/// is best buffered.
+pub struct ZlibDecoder<R: Read> {
+    reader: R,
+    dictionary: Option<Vec<u8>>,
+    data: Option<Vec<u8>>,
+    position: usize,
+}
+
+impl<R: Read> ZlibDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        ZlibDecoder {
+            reader,
+            dictionary: None,
+            data: None,
+            position: 0,
+        }
+    }
+
+    pub fn with_dictionary(reader: R, dictionary: &[u8]) -> Self {
+        ZlibDecoder {
+            reader,
+            dictionary: Some(dictionary.to_vec()),
+            data: None,
+            position: 0,
+        }
+    }
+
+    /// Returns `reader`, positioned after the stream once it was read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.data.is_none() {
+            let data = read_stream(&mut self.reader, self.dictionary.as_deref())
+                .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))?;
+            self.data = Some(data);
+        }
+        let data = self.data.as_deref().unwrap_or_default();
+        let len = buf.len().min(data.len() - self.position);
+        buf[..len].copy_from_slice(&data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::compress;
+    use super::decompress;
+    use super::inflate;
+    use super::ZlibDecoder;
+    use super::ZlibEncoder;
+    use super::PIECE_SIZE;
+    use anyhow::Ok;
+    use anyhow::Result;
+    use std::fs;
+    use std::io::Read;
+    use std::io::Write;
+
+    #[test]
+    fn test_zlib_files() -> Result<()> {
+        let sample = fs::read("testdata/sample.txt")?;
+        let dictionary = b"pub fn let mut Result<()> Vec<u8> std::io::";
+
+        // written by Python's zlib.compress at level 9
+        let compressed_data = fs::read("testdata/sample.txt.zlib")?;
+        assert_eq!(decompress(&compressed_data, None)?, sample);
+        assert!(decompress(&compressed_data[..compressed_data.len() - 1], None).is_err());
+        let mut damaged = compressed_data.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(decompress(&damaged, None).is_err());
+
+        // written by zlib with the preset dictionary above
+        let compressed_data = fs::read("testdata/sample.txt.dict.zlib")?;
+        assert!(decompress(&compressed_data, None).is_err());
+        assert!(decompress(&compressed_data, Some(b"another dictionary")).is_err());
+        assert_eq!(decompress(&compressed_data, Some(dictionary))?, sample);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib_adapters() -> Result<()> {
+        let data = b"zlib adapters, zlib adapters\n".repeat(100);
+        let dictionary = b"adapters\nzlib ";
+
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        encoder.write_all(&data[..1000])?;
+        encoder.write_all(&data[1000..])?;
+        let compressed_data = encoder.finish()?;
+        assert_eq!(compressed_data, compress(&data, None)?);
+        assert_eq!(compressed_data[0], 0x78);
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(compressed_data.as_slice()).read_to_end(&mut decoded)?;
+        assert_eq!(decoded, data);
+
+        let mut encoder = ZlibEncoder::with_dictionary(Vec::new(), dictionary);
+        encoder.write_all(&data)?;
+        let compressed_data = encoder.finish()?;
+        assert_eq!(compressed_data[1] & 0x20, 0x20);
+        let mut decoded = Vec::new();
+        ZlibDecoder::with_dictionary(compressed_data.as_slice(), dictionary)
+            .read_to_end(&mut decoded)?;
+        assert_eq!(decoded, data);
+        assert!(ZlibDecoder::new(compressed_data.as_slice())
+            .read_to_end(&mut Vec::new())
+            .is_err());
+
+        // the data following a stream is left to the caller
+        let mut concatenated = compress(&data, None)?;
+        let stream_len = concatenated.len();
+        concatenated.extend_from_slice(b"next record");
+        assert!(decompress(&concatenated, None).is_err());
+        assert_eq!(inflate(&concatenated, None)?, (data.clone(), stream_len));
+        let mut decoder = ZlibDecoder::new(concatenated.as_slice());
+        let mut decoded = vec![0u8; data.len()];
+        decoder.read_exact(&mut decoded)?;
+        assert_eq!(decoded, data);
+        assert_eq!(decoder.into_inner(), b"next record");
+
+        // larger data is compressed a piece at a time
+        let long_data = [b"0123456789".repeat(PIECE_SIZE / 5), data.clone()].concat();
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        encoder.write_all(&long_data)?;
+        let compressed_data = encoder.finish()?;
+        assert!(compressed_data.len() < long_data.len() / 100);
+        assert_eq!(decompress(&compressed_data, None)?, long_data);
+
+        // data found in the dictionary becomes a single back-reference
+        assert!(compress(dictionary, Some(dictionary))?.len() < compress(dictionary, None)?.len());
+        assert_eq!(decompress(&compress(b"", None)?, None)?, b"");
+        Ok(())
+    }
+}